@@ -1,7 +1,8 @@
 MEMORY
 {
   /* NOTE K = KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x8000000, LENGTH = 128K 
+  /* last 4K (two 2K pages at 0x801F000) hold the config records, see statics */
+  FLASH : ORIGIN = 0x8000000, LENGTH = 124K
   RAM : ORIGIN = 0x20000000, LENGTH = 64K
 }
 
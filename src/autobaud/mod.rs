@@ -0,0 +1,107 @@
+//! Bitrate detection for commissioning a bus of unknown speed.
+//!
+//! The controller is put in silent mode, so it never drives an ACK or error frame onto
+//! the bus, and listens at each of `CANDIDATES` for `DWELL_MS` while accepting every
+//! ID. At the right rate frames arrive and the receive error counter (REC) stays put,
+//! at a wrong one stuff, form and CRC errors push REC up. `pick` chooses the clean
+//! candidate that received the most frames.
+
+use crate::health::Esr;
+
+/// Rates tried, slowest first
+pub const CANDIDATES: [u32; 5] = [125_000, 250_000, 500_000, 800_000, 1_000_000];
+/// Listening time per candidate, long enough for a 1 s heartbeat
+pub const DWELL_MS: u64 = 2000;
+/// Frames needed before a candidate counts, a single frame can be a fluke
+const MIN_FRAMES: u32 = 2;
+
+/// What the bus looked like at one candidate rate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Trial {
+    pub bitrate: u32,
+    pub frames: u32,
+    /// REC increments seen while listening
+    pub errors: u32,
+    rec: u8,
+}
+
+impl Trial {
+    /// Start listening at `bitrate`, `esr` read once the controller is running
+    pub fn new(bitrate: u32, esr: Esr) -> Self {
+        Self {
+            bitrate,
+            frames: 0,
+            errors: 0,
+            rec: esr.rec,
+        }
+    }
+
+    pub fn frame(&mut self) {
+        self.frames += 1;
+    }
+
+    /// Count the REC growth since the last reading, it drops by one per good frame
+    pub fn esr(&mut self, esr: Esr) {
+        self.errors += esr.rec.saturating_sub(self.rec) as u32;
+        self.rec = esr.rec;
+    }
+
+    /// Enough frames and no errors
+    pub fn is_clean(&self) -> bool {
+        self.frames >= MIN_FRAMES && self.errors == 0
+    }
+}
+
+/// The clean trial with the most frames
+pub fn pick(trials: &[Trial]) -> Option<u32> {
+    trials
+        .iter()
+        .filter(|trial| trial.is_clean())
+        .max_by_key(|trial| trial.frames)
+        .map(|trial| trial.bitrate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn esr(rec: u8) -> Esr {
+        Esr::from_bits((rec as u32) << 24)
+    }
+
+    fn listen(bitrate: u32, frames: u32, recs: &[u8]) -> Trial {
+        let mut trial = Trial::new(bitrate, esr(recs[0]));
+        for _ in 0..frames {
+            trial.frame();
+        }
+        for rec in &recs[1..] {
+            trial.esr(esr(*rec));
+        }
+        trial
+    }
+
+    #[test]
+    fn picks_the_rate_without_errors() {
+        let trials = [
+            listen(125_000, 0, &[0, 40, 96]),
+            // a few frames decode by chance at twice the real rate, with errors
+            listen(250_000, 3, &[0, 8, 16]),
+            // REC left over from the last rate drains as good frames arrive
+            listen(500_000, 40, &[16, 10, 0]),
+            listen(800_000, 0, &[0, 127, 127]),
+        ];
+        assert_eq!(trials[1].errors, 16);
+        assert_eq!(trials[2].errors, 0);
+        assert_eq!(pick(&trials), Some(500_000));
+    }
+
+    #[test]
+    fn quiet_or_noisy_bus_has_no_result() {
+        let quiet = CANDIDATES.map(|bitrate| listen(bitrate, 0, &[0, 0]));
+        assert_eq!(pick(&quiet), None);
+        // one frame is not enough
+        assert_eq!(pick(&[listen(250_000, 1, &[0, 0])]), None);
+        // counter growth between readings counts even if it drained again
+        assert!(!listen(250_000, 20, &[0, 5, 2, 0]).is_clean());
+    }
+}
@@ -0,0 +1,121 @@
+use super::{BatteryDriver, BatteryState, CellData, Ingest};
+use crate::filter::{self, Rule};
+use bxcan::{Frame, Id};
+use embassy_time::Duration;
+use kangoo_battery::{bms::Bms, request_init, request_tx_frame, Data, RequestMode};
+
+const SCHEDULE: &[Duration] = &[
+    Duration::from_millis(100),
+    Duration::from_millis(5050),
+    Duration::from_millis(11025),
+];
+const WANTED_IDS: &[Rule] = &[
+    Rule::standard(0x155),
+    Rule::standard(0x424),
+    Rule::standard(0x425),
+    Rule::standard(0x4ae),
+    Rule::standard(DIAG_ID),
+];
+const DIAG_ID: u16 = 0x7bb;
+
+#[derive(Debug)]
+pub enum KangooError {
+    RapidData,
+    Diag,
+    Update,
+}
+
+/// Renault Kangoo / Fluence pack, rapid broadcast data plus diag polling
+pub struct Kangoo {
+    data: Data,
+    bms_validated: Bms,
+    update_inverter: bool,
+}
+
+impl Kangoo {
+    pub fn new() -> Self {
+        Self {
+            data: Data::new(),
+            bms_validated: Bms::new(),
+            update_inverter: false,
+        }
+    }
+}
+
+impl BatteryDriver for Kangoo {
+    type Error = KangooError;
+
+    const FILTERS: &'static [Rule] = WANTED_IDS;
+
+    fn schedule(&self) -> &'static [Duration] {
+        SCHEDULE
+    }
+
+    fn request(&mut self, slot: usize) -> Option<Frame> {
+        match slot {
+            0 => request_init().ok(),
+            1 => request_tx_frame(RequestMode::CellBank1).ok(),
+            2 => request_tx_frame(RequestMode::Balance).ok(),
+            _ => None,
+        }
+    }
+
+    fn ingest(&mut self, frame: &Frame) -> Result<Ingest, Self::Error> {
+        let Id::Standard(id) = frame.id() else {
+            return Ok(Ingest::Ignored);
+        };
+        // replayed frames bypass the hardware filters
+        if !filter::accepts(WANTED_IDS, frame.id()) {
+            return Ok(Ingest::Ignored);
+        }
+        let id = id.as_raw();
+        if id != DIAG_ID {
+            // data can be parsed without diag data is true - use a different validity checker
+            let valid = self
+                .data
+                .rapid_data_processor(frame.clone())
+                .map_err(|_e| KangooError::RapidData)?;
+            self.update_inverter |= valid;
+            return Ok(if valid { Ingest::Alive } else { Ingest::Pending });
+        }
+        match self.data.diag_data_processor(frame.clone()) {
+            Ok(None) => {
+                self.bms_validated
+                    .update_bms_data(&self.data)
+                    .map_err(|_e| KangooError::Update)?;
+                Ok(Ingest::Pending)
+            }
+            Ok(Some(next_tx_frame)) => Ok(Ingest::Reply(next_tx_frame)),
+            Err(_e) => Err(KangooError::Diag),
+        }
+    }
+
+    fn take_state(&mut self) -> Result<Option<BatteryState>, Self::Error> {
+        if !core::mem::take(&mut self.update_inverter) {
+            return Ok(None);
+        }
+        Ok(Some(BatteryState::from(&self.bms_validated)))
+    }
+
+    fn cells(&self) -> Option<CellData> {
+        Some(CellData::from(&self.bms_validated))
+    }
+
+    fn set_dod(&mut self, min: u8, max: u8) {
+        self.bms_validated.set_dod(min, max);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bxcan::StandardId;
+
+    #[test]
+    fn ignores_unwanted_ids() {
+        let mut kangoo = Kangoo::new();
+        let frame = Frame::new_data(StandardId::new(0x123).unwrap(), [0u8; 8]);
+        assert!(matches!(kangoo.ingest(&frame), Ok(Ingest::Ignored)));
+        assert!(kangoo.take_state().unwrap().is_none());
+    }
+}
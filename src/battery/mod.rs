@@ -0,0 +1,233 @@
+use crate::config::BatteryType;
+use crate::filter::{self, Rule};
+use crate::Frames;
+use bxcan::Frame;
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+#[cfg(feature = "kangoo")]
+pub mod kangoo;
+
+#[cfg(feature = "ze50")]
+pub mod ze50;
+
+/// Battery state in standard units, shared by every BMS driver and inverter protocol.
+///
+/// BMS processors convert their native frames into this struct, inverter processors
+/// and the MQTT task only ever read from it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BatteryState {
+    /// State of charge, %
+    pub soc: f32,
+    /// Pack voltage, V
+    pub pack_volts: f32,
+    /// Pack voltage at 100% SoC, V
+    pub pack_volts_max: f32,
+    /// Maximum charge voltage requested from the inverter, V
+    pub charge_volts_max: f32,
+    /// Minimum discharge voltage allowed by the inverter, V
+    pub charge_volts_min: f32,
+    /// Pack current, A (positive = charging)
+    pub current: f32,
+    /// Highest cell voltage, mV
+    pub cell_mv_high: u16,
+    /// Lowest cell voltage, mV
+    pub cell_mv_low: u16,
+    /// Highest cell temperature, °C
+    pub cell_temp_high: f32,
+    /// Lowest cell temperature, °C
+    pub cell_temp_low: f32,
+    /// Average pack temperature, °C
+    pub temp_avg: f32,
+    /// Energy remaining, kWh
+    pub kwh_remaining: f32,
+    /// Charge current limit, A
+    pub charge_max: f32,
+    /// Discharge current limit, A
+    pub discharge_max: f32,
+    /// Number of cells currently balancing
+    pub balancing_cells: u8,
+    /// BMS has reported a complete, plausible data set
+    pub valid: bool,
+    /// Time of the last update from the BMS
+    pub timestamp: Option<Instant>,
+}
+
+impl BatteryState {
+    /// Seconds since the BMS last produced a reading, `None` if it never has
+    pub fn age_secs(&self) -> Option<u64> {
+        self.timestamp.map(|t| t.elapsed().as_secs())
+    }
+}
+
+/// Largest pack supported, ZE50 and Kangoo are both 96s
+pub const MAX_CELLS: usize = 96;
+/// Largest number of temperature sensing modules
+pub const MAX_MODULES: usize = 12;
+
+/// Per-cell readings, published in chunks by the telemetry scheduler
+#[derive(Clone, Debug, Default)]
+pub struct CellData {
+    /// Cell voltages, mV
+    pub millivolts: Vec<u16, MAX_CELLS>,
+    /// Module temperatures, °C
+    pub temps: Vec<f32, MAX_MODULES>,
+    /// Balancing flag per cell, indexed like `millivolts`, empty if not reported
+    pub balancing: Vec<bool, MAX_CELLS>,
+}
+
+/// Result of feeding one BMS frame to a driver
+#[derive(Debug)]
+pub enum Ingest {
+    /// Frame is not used by this driver
+    Ignored,
+    /// Frame accepted, reading not complete yet
+    Pending,
+    /// Valid BMS data, resets the BMS watchdog
+    Alive,
+    /// Frame needs an immediate reply, e.g. ISO-TP flow control
+    Reply(Frame),
+}
+
+/// A battery-side CAN protocol, driven by `tasks::battery::run`.
+///
+/// Drivers own all protocol state, the task owns the channels, request timing,
+/// BMS watchdog and publishing of `BatteryState`.
+pub trait BatteryDriver {
+    type Error: core::fmt::Debug;
+
+    /// IDs read from the BMS, compiled into the CAN1 acceptance filters
+    const FILTERS: &'static [Rule] = filter::ACCEPT_ALL;
+
+    /// Intervals of the periodic request slots, `request(slot)` is called on each
+    fn schedule(&self) -> &'static [Duration];
+
+    /// Delay after boot before the first frame is sent
+    fn startup_delay(&self) -> Duration {
+        Duration::from_millis(0)
+    }
+
+    /// Spacing between the one-off init frames
+    fn init_interval(&self) -> Duration {
+        Duration::from_millis(200)
+    }
+
+    /// One-off frames sent before the periodic requests start
+    fn init_frames(&mut self) -> Frames {
+        Frames::new()
+    }
+
+    /// Build the request for a schedule slot
+    fn request(&mut self, slot: usize) -> Option<Frame>;
+
+    /// Process a frame received from the BMS
+    fn ingest(&mut self, frame: &Frame) -> Result<Ingest, Self::Error>;
+
+    /// Take a newly completed reading, `None` until one is ready
+    fn take_state(&mut self) -> Result<Option<BatteryState>, Self::Error>;
+
+    /// Per-cell data behind the last `take_state` reading, `None` if not supported
+    fn cells(&self) -> Option<CellData> {
+        None
+    }
+
+    /// Apply depth of discharge limits, %
+    fn set_dod(&mut self, min: u8, max: u8);
+}
+
+/// Acceptance filter table of `battery`, everything when it is not compiled in
+pub fn filters(battery: BatteryType) -> &'static [Rule] {
+    match battery {
+        #[cfg(feature = "ze50")]
+        BatteryType::Ze50 => <ze50::Ze50 as BatteryDriver>::FILTERS,
+        #[cfg(feature = "kangoo")]
+        BatteryType::Kangoo => <kangoo::Kangoo as BatteryDriver>::FILTERS,
+        #[allow(unreachable_patterns)]
+        _ => filter::ACCEPT_ALL,
+    }
+}
+
+// BMS libraries report in 0.1 units (0.1V, 0.1A, 0.1°C, 0.1kWh), cells in mV
+
+#[cfg(feature = "kangoo")]
+impl From<&kangoo_battery::Bms> for BatteryState {
+    fn from(bms: &kangoo_battery::Bms) -> Self {
+        Self {
+            soc: bms.soc as f32,
+            pack_volts: bms.pack_volts as f32 * 0.1,
+            pack_volts_max: bms.pack_voltage_max as f32 * 0.1,
+            charge_volts_max: bms.slave_voltage_max as f32 * 0.1,
+            charge_volts_min: bms.slave_voltage_min as f32 * 0.1,
+            current: bms.current as f32 * 0.1,
+            cell_mv_high: bms.max_volts,
+            cell_mv_low: bms.min_volts,
+            cell_temp_high: bms.temp_max as f32 * 0.1,
+            cell_temp_low: bms.temp_min as f32 * 0.1,
+            temp_avg: bms.temp_avg as f32 * 0.1,
+            kwh_remaining: bms.kwh_remaining as f32 * 0.1,
+            charge_max: bms.charge_max as f32 * 0.1,
+            discharge_max: bms.discharge_max as f32 * 0.1,
+            balancing_cells: bms.balancing_cells,
+            valid: bms.valid,
+            timestamp: Some(Instant::now()),
+        }
+    }
+}
+
+#[cfg(feature = "ze50")]
+impl From<&renault_zoe_ph2_battery::bms::Bms> for BatteryState {
+    fn from(bms: &renault_zoe_ph2_battery::bms::Bms) -> Self {
+        Self {
+            soc: bms.soc as f32,
+            pack_volts: bms.pack_volts as f32 * 0.1,
+            pack_volts_max: bms.pack_voltage_max as f32 * 0.1,
+            charge_volts_max: bms.slave_voltage_max as f32 * 0.1,
+            charge_volts_min: bms.slave_voltage_min as f32 * 0.1,
+            current: bms.current as f32 * 0.1,
+            cell_mv_high: bms.max_volts,
+            cell_mv_low: bms.min_volts,
+            cell_temp_high: bms.temp_max as f32 * 0.1,
+            cell_temp_low: bms.temp_min as f32 * 0.1,
+            temp_avg: bms.temp_avg as f32 * 0.1,
+            kwh_remaining: bms.kwh_remaining as f32 * 0.1,
+            charge_max: bms.charge_max as f32 * 0.1,
+            discharge_max: bms.discharge_max as f32 * 0.1,
+            balancing_cells: 0,
+            valid: bms.valid,
+            timestamp: Some(Instant::now()),
+        }
+    }
+}
+
+#[cfg(feature = "kangoo")]
+impl From<&kangoo_battery::Bms> for CellData {
+    fn from(bms: &kangoo_battery::Bms) -> Self {
+        Self {
+            millivolts: bms.cells.iter().copied().take(MAX_CELLS).collect(),
+            temps: bms
+                .module_temps
+                .iter()
+                .take(MAX_MODULES)
+                .map(|t| *t as f32 * 0.1)
+                .collect(),
+            balancing: bms.bal_cells.iter().copied().take(MAX_CELLS).collect(),
+        }
+    }
+}
+
+#[cfg(feature = "ze50")]
+impl From<&renault_zoe_ph2_battery::bms::Bms> for CellData {
+    fn from(bms: &renault_zoe_ph2_battery::bms::Bms) -> Self {
+        // balancing is not decoded for the ZE50, see `balancing_cells` above
+        Self {
+            millivolts: bms.cells.iter().copied().take(MAX_CELLS).collect(),
+            temps: bms
+                .module_temps
+                .iter()
+                .take(MAX_MODULES)
+                .map(|t| *t as f32 * 0.1)
+                .collect(),
+            balancing: Vec::new(),
+        }
+    }
+}
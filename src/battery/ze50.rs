@@ -0,0 +1,145 @@
+use super::{BatteryDriver, BatteryState, CellData, Ingest};
+use crate::filter::Rule;
+use crate::Frames;
+use bxcan::{Frame, Id::Extended};
+use embassy_time::Duration;
+use embedded_hal::can::Frame as _;
+use embedded_hal::can::{ExtendedId, Id, StandardId};
+use renault_zoe_ph2_battery::{bms::Bms, init_payloads, preamble_payloads, BmsError, Data};
+
+const SCHEDULE: &[Duration] = &[Duration::from_millis(200), Duration::from_millis(225)];
+/// ISO-TP responses from the BMS
+const RESPONSE_ID: u32 = 0x18DAF1DB;
+
+/// Renault Zoe Ph2 (ZE50) pack, polled over ISO-TP diagnostics
+pub struct Ze50 {
+    data: Data,
+    bms: Bms,
+    preamble_frame_number_1: bool,
+    reported: bool,
+}
+
+impl Ze50 {
+    pub fn new() -> Self {
+        Self {
+            data: Data::new(),
+            bms: Bms::new(),
+            preamble_frame_number_1: true,
+            reported: false,
+        }
+    }
+}
+
+impl BatteryDriver for Ze50 {
+    type Error = BmsError;
+
+    const FILTERS: &'static [Rule] = &[Rule::extended(RESPONSE_ID)];
+
+    fn schedule(&self) -> &'static [Duration] {
+        SCHEDULE
+    }
+
+    fn startup_delay(&self) -> Duration {
+        Duration::from_millis(2000)
+    }
+
+    fn init_frames(&mut self) -> Frames {
+        init_payloads()
+            .iter()
+            .map(|payload| Frame::new(Id::Standard(StandardId::new(0x373).unwrap()), payload).unwrap())
+            .collect()
+    }
+
+    fn request(&mut self, slot: usize) -> Option<Frame> {
+        match slot {
+            0 => {
+                let payload = {
+                    if self.preamble_frame_number_1 {
+                        self.preamble_frame_number_1 = false;
+                        preamble_payloads()[0]
+                    } else {
+                        self.preamble_frame_number_1 = true;
+                        preamble_payloads()[1]
+                    }
+                };
+                Frame::new(Id::Standard(StandardId::new(0x373).unwrap()), &payload)
+            }
+            1 => {
+                let pid = self.data.req_code;
+                let pid_id = if pid == 0x5d {
+                    // current 100ms sampling
+                    0x92
+                } else if pid == 0xc8 {
+                    // kWh remaining
+                    0x91
+                } else {
+                    0x90
+                };
+                Frame::new(
+                    Id::Extended(ExtendedId::new(0x18DADBF1).unwrap()),
+                    &[0x03, 0x22, pid_id, pid, 0xff, 0xff, 0xff, 0xff],
+                )
+            }
+            _ => None,
+        }
+    }
+
+    fn ingest(&mut self, frame: &Frame) -> Result<Ingest, Self::Error> {
+        let Extended(id) = frame.id() else {
+            return Ok(Ingest::Ignored);
+        };
+        if id.as_raw() != RESPONSE_ID {
+            return Ok(Ingest::Ignored);
+        }
+        let Some(payload) = frame.data() else {
+            return Ok(Ingest::Ignored);
+        };
+        // process_payload into Data struct
+        self.data.process_payload(payload)?;
+        Ok(Ingest::Alive)
+    }
+
+    fn take_state(&mut self) -> Result<Option<BatteryState>, Self::Error> {
+        // push vals to the Bms struct once the reading loop has finished
+        if self.data.req_code != 0x1 {
+            self.reported = false;
+            return Ok(None);
+        }
+        if self.reported {
+            return Ok(None);
+        }
+        self.reported = true;
+        self.bms.update_bms_data(&self.data)?;
+        Ok(Some(BatteryState::from(&self.bms)))
+    }
+
+    fn cells(&self) -> Option<CellData> {
+        Some(CellData::from(&self.bms))
+    }
+
+    fn set_dod(&mut self, min: u8, max: u8) {
+        self.bms.set_dod(min, max);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignores_standard_ids() {
+        let mut ze50 = Ze50::new();
+        let frame = Frame::new(Id::Standard(StandardId::new(0x155).unwrap()), &[0u8; 8]).unwrap();
+        assert!(matches!(ze50.ingest(&frame), Ok(Ingest::Ignored)));
+    }
+
+    #[test]
+    fn alternates_preamble() {
+        let mut ze50 = Ze50::new();
+        let first = ze50.request(0).unwrap();
+        let second = ze50.request(0).unwrap();
+        assert_eq!(first.data().unwrap().as_ref(), &preamble_payloads()[0][..]);
+        assert_eq!(second.data().unwrap().as_ref(), &preamble_payloads()[1][..]);
+        assert!(ze50.request(2).is_none());
+    }
+}
@@ -0,0 +1,480 @@
+//! Interrupt driven bxCAN for both buses, replacing the `receive`/`transmit` polling.
+//!
+//! The RX FIFO interrupts are level triggered while a FIFO holds frames, so each one
+//! masks itself and wakes its reader, which drains the FIFO and unmasks it again. The
+//! TX interrupt fires when a mailbox completes, clears the request flags and wakes the
+//! writer, which keeps all three mailboxes filled.
+//!
+//! The error status register is read every `ESR_POLL`, see `first_test::health`. A
+//! bus-off controller is restarted through initialisation mode, dropping the frames
+//! still in its mailboxes rather than sending them late.
+//!
+//! Bit timing is solved from the APB1 clock the RCC actually set up, see
+//! `first_test::timing`.
+//!
+//! While candump or a UART bridge forwards a bus's frames its protocol filters are
+//! swapped for an accept-all bank, checked every `ESR_POLL`.
+//!
+//! An `autobaud` request stops the bus's futures and listens in silent mode at each
+//! candidate rate, see `first_test::autobaud`. While listening the bus's filter banks
+//! are swapped for one that accepts everything into FIFO 0, transmit is paused and the
+//! error monitor is not fed. Frames already in the mailboxes are aborted and counted in
+//! the report, the one on its way into a mailbox and those still in the TX channel go
+//! out at the new rate.
+
+use crate::statics::GATEWAY;
+use crate::types::_Mutex;
+use core::future::poll_fn;
+use core::sync::atomic::Ordering;
+use core::task::Poll;
+use defmt::{error, info, warn};
+use embassy_futures::join::join4;
+use embassy_futures::select::{select, Either};
+use embassy_futures::yield_now;
+use embassy_stm32::can::bxcan::{self, Frame, Instance, Interrupts, Tx};
+use embassy_stm32::interrupt::{Interrupt, InterruptExt};
+use embassy_stm32::pac;
+use embassy_sync::channel::{Receiver, Sender};
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_time::{Duration, Instant, Timer};
+use first_test::autobaud::{self, Trial, CANDIDATES, DWELL_MS};
+use first_test::candump::Bus;
+use first_test::filter::BANKS;
+use first_test::health::{Esr, Monitor};
+use first_test::timing::{self, DEFAULT_BITRATE, SAMPLE_POINT_PERMILLE};
+
+const ESR_POLL: Duration = Duration::from_millis(100);
+/// FIFO drain and ESR reading interval while searching, 3 frames at 1 Mbit/s fill a FIFO
+/// in about 400 us, so overruns are expected and only slow the count
+const AUTOBAUD_POLL: Duration = Duration::from_millis(1);
+
+struct Wakers {
+    tx: AtomicWaker,
+    rx: [AtomicWaker; 2],
+}
+
+impl Wakers {
+    const fn new() -> Self {
+        Self {
+            tx: AtomicWaker::new(),
+            rx: [AtomicWaker::new(), AtomicWaker::new()],
+        }
+    }
+}
+
+/// CAN1, CAN2
+static WAKERS: [Wakers; 2] = [Wakers::new(), Wakers::new()];
+
+fn regs(index: usize) -> pac::can::Can {
+    match index {
+        0 => pac::CAN1,
+        _ => pac::CAN2,
+    }
+}
+
+unsafe fn on_tx<const N: usize>(_: *mut ()) {
+    regs(N).tsr().write(|w| {
+        for mailbox in 0..3 {
+            w.set_rqcp(mailbox, true);
+        }
+    });
+    WAKERS[N].tx.wake();
+}
+
+unsafe fn on_rx<const N: usize, const FIFO: usize>(_: *mut ()) {
+    regs(N).ier().modify(|w| w.set_fmpie(FIFO, false));
+    WAKERS[N].rx[FIFO].wake();
+}
+
+/// Install the TX and RX FIFO handlers of `bus`, call before its task starts
+pub fn bind(bus: Bus, tx: impl Interrupt, rx0: impl Interrupt, rx1: impl Interrupt) {
+    match bus {
+        Bus::Bms => {
+            tx.set_handler(on_tx::<0>);
+            rx0.set_handler(on_rx::<0, 0>);
+            rx1.set_handler(on_rx::<0, 1>);
+        }
+        Bus::Inverter => {
+            tx.set_handler(on_tx::<1>);
+            rx0.set_handler(on_rx::<1, 0>);
+            rx1.set_handler(on_rx::<1, 1>);
+        }
+    }
+    start(&tx);
+    start(&rx0);
+    start(&rx1);
+}
+
+fn start(irq: &impl InterruptExt) {
+    irq.unpend();
+    irq.enable();
+}
+
+fn pclk1() -> u32 {
+    unsafe { embassy_stm32::rcc::get_freqs() }.apb1.0
+}
+
+fn esr(index: usize) -> Esr {
+    Esr::from_bits(unsafe { regs(index).esr().read().0 })
+}
+
+/// BTR value for `bitrate` on `bus`, falling back to the default rate when the clock
+/// cannot produce it. Records the rate in `Gateway::bitrates`.
+pub fn bit_timing(bus: Bus, bitrate: u32) -> u32 {
+    let pclk1 = pclk1();
+    let (bitrate, timing) = match timing::solve(pclk1, bitrate, SAMPLE_POINT_PERMILLE) {
+        Ok(timing) => (bitrate, timing),
+        Err(_) => {
+            error!(
+                "{} no bit timing for {} bit/s from {} Hz",
+                bus.iface(),
+                bitrate,
+                pclk1
+            );
+            let timing = timing::solve(pclk1, DEFAULT_BITRATE, SAMPLE_POINT_PERMILLE);
+            (DEFAULT_BITRATE, defmt::unwrap!(timing.ok()))
+        }
+    };
+    info!(
+        "{} {} bit/s, {} quanta, sample point {}/1000",
+        bus.iface(),
+        bitrate,
+        timing.quanta(),
+        timing.sample_point_permille()
+    );
+    GATEWAY.bitrates[bus.index()].store(bitrate, Ordering::Relaxed);
+    timing.btr()
+}
+
+/// Next frame from `fifo`, sleeping on its interrupt while it is empty
+async fn receive(
+    bus: Bus,
+    fifo: usize,
+    mut try_receive: impl FnMut() -> nb::Result<Frame, bxcan::OverrunError>,
+) -> Frame {
+    let index = bus.index();
+    poll_fn(|cx| loop {
+        match try_receive() {
+            Ok(frame) => return Poll::Ready(frame),
+            Err(nb::Error::Other(_)) => {
+                warn!("{} FIFO{} overrun, frames lost", bus.iface(), fifo)
+            }
+            Err(nb::Error::WouldBlock) => {
+                WAKERS[index].rx[fifo].register(cx.waker());
+                // fires straight away if a frame arrived since `try_receive`
+                cortex_m::interrupt::free(|_| unsafe {
+                    regs(index).ier().modify(|w| w.set_fmpie(fifo, true))
+                });
+                return Poll::Pending;
+            }
+        }
+    })
+    .await
+}
+
+/// Queue `frame` in a mailbox, sleeping on the TX interrupt while none can take it.
+/// Returns a lower priority frame that gave up its mailbox, it still has to be sent.
+async fn transmit<I: Instance>(bus: Bus, tx: &mut Tx<I>, frame: &Frame) -> Option<Frame> {
+    let waker = &WAKERS[bus.index()].tx;
+    poll_fn(|cx| {
+        waker.register(cx.waker());
+        // also blocks while a frame of the same priority is pending, keeping their order
+        match tx.transmit(frame) {
+            Ok(evicted) => Poll::Ready(evicted),
+            Err(nb::Error::WouldBlock) => Poll::Pending,
+            Err(nb::Error::Other(never)) => match never {},
+        }
+    })
+    .await
+}
+
+fn abort(index: usize) {
+    unsafe {
+        regs(index).tsr().write(|w| {
+            for mailbox in 0..3 {
+                w.set_abrq(mailbox, true);
+            }
+        })
+    }
+}
+
+/// Abort the pending mailboxes, then leave bus-off by cycling through initialisation.
+/// The controller rejoins after 128 x 11 recessive bits.
+async fn restart(index: usize) {
+    abort(index);
+    let regs = regs(index);
+    unsafe {
+        regs.mcr().modify(|w| w.set_inrq(true));
+        while !regs.msr().read().inak() {
+            yield_now().await;
+        }
+        regs.mcr().modify(|w| w.set_inrq(false));
+    }
+}
+
+/// Filter banks of one bus replaced by a single accept-all bank into FIFO 0. The
+/// banks of both buses sit in CAN1, so this goes to the registers rather than through
+/// the `bxcan` filter API, which only the CAN1 task could use. Only the bits of the
+/// bus's own banks are restored, the other bus may have changed its own meanwhile,
+/// and a take inside another take restores to the outer one's open bank.
+struct OpenFilters {
+    bank: usize,
+    /// Bits of the bus's banks in the per-bank registers
+    owned: u32,
+    active: u32,
+    scale: u32,
+    mode: u32,
+    fifo: u32,
+    bank_regs: [u32; 2],
+}
+
+impl OpenFilters {
+    /// `None` when the bus has no banks, e.g. CAN1 with an empty table
+    fn take(index: usize) -> Option<Self> {
+        let filters = pac::CAN1;
+        unsafe {
+            let split = ((filters.fmr().read().0 >> 8) & 0x3f) as usize;
+            let banks = if index == 0 { 0..split } else { split..BANKS };
+            let bank = banks.start;
+            if banks.is_empty() {
+                return None;
+            }
+            let saved = Self {
+                bank,
+                owned: banks.fold(0u32, |mask, bank| mask | 1 << bank),
+                active: filters.fa1r().read().0,
+                scale: filters.fs1r().read().0,
+                mode: filters.fm1r().read().0,
+                fifo: filters.ffa1r().read().0,
+                bank_regs: [
+                    filters.fb(bank).fr1().read().0,
+                    filters.fb(bank).fr2().read().0,
+                ],
+            };
+            filters.fmr().modify(|w| w.set_finit(true));
+            // 32-bit mask mode with an all-zero mask, FIFO 0
+            filters.fs1r().write(|w| w.0 = saved.scale | 1 << bank);
+            filters.fm1r().write(|w| w.0 = saved.mode & !(1 << bank));
+            filters.ffa1r().write(|w| w.0 = saved.fifo & !(1 << bank));
+            filters.fb(bank).fr1().write(|w| w.0 = 0);
+            filters.fb(bank).fr2().write(|w| w.0 = 0);
+            filters
+                .fa1r()
+                .write(|w| w.0 = (saved.active & !saved.owned) | 1 << bank);
+            filters.fmr().modify(|w| w.set_finit(false));
+            Some(saved)
+        }
+    }
+
+    fn restore(self) {
+        let filters = pac::CAN1;
+        let merge = |now: u32, saved: u32| (now & !self.owned) | (saved & self.owned);
+        unsafe {
+            filters.fmr().modify(|w| w.set_finit(true));
+            let scale = merge(filters.fs1r().read().0, self.scale);
+            filters.fs1r().write(|w| w.0 = scale);
+            let mode = merge(filters.fm1r().read().0, self.mode);
+            filters.fm1r().write(|w| w.0 = mode);
+            let fifo = merge(filters.ffa1r().read().0, self.fifo);
+            filters.ffa1r().write(|w| w.0 = fifo);
+            filters
+                .fb(self.bank)
+                .fr1()
+                .write(|w| w.0 = self.bank_regs[0]);
+            filters
+                .fb(self.bank)
+                .fr2()
+                .write(|w| w.0 = self.bank_regs[1]);
+            let active = merge(filters.fa1r().read().0, self.active);
+            filters.fa1r().write(|w| w.0 = active);
+            filters.fmr().modify(|w| w.set_finit(false));
+        }
+    }
+}
+
+/// Swap the protocol filters of `bus` for accept-all while its frames go out over the
+/// UART, see `Gateway::sniffing`, and back once they stop
+fn follow_sniffing(bus: Bus, open: &mut Option<Option<OpenFilters>>) {
+    let sniffing = GATEWAY.sniffing(bus);
+    if sniffing == open.is_some() {
+        return;
+    }
+    if sniffing {
+        info!("{} accepting all IDs for capture", bus.iface());
+        *open = Some(OpenFilters::take(bus.index()));
+    } else if let Some(filters) = open.take().flatten() {
+        info!("{} protocol filters restored", bus.iface());
+        filters.restore();
+    }
+}
+
+/// Rejoin the bus with `btr`, listen only with `silent`
+async fn reconfigure<I: Instance>(can: &mut bxcan::Can<I>, btr: u32, silent: bool) {
+    can.modify_config()
+        .set_bit_timing(btr)
+        .set_silent(silent)
+        .leave_disabled();
+    while can.enable_non_blocking().is_err() {
+        yield_now().await;
+    }
+}
+
+/// Listen at each candidate rate, then rejoin at the detected one, or at the rate the
+/// bus ran at before when none was clean. Also returns the number of frames aborted
+/// in the mailboxes.
+async fn search<I: Instance>(bus: Bus, can: &mut bxcan::Can<I>) -> (Option<u32>, u8) {
+    let index = bus.index();
+    let pclk1 = pclk1();
+    let previous = GATEWAY.bitrate(bus);
+    // frames in the mailboxes were set up for the old rate and must not go out at a
+    // candidate, the ones still in the channel wait for the result
+    let dropped = (0..3)
+        .filter(|mailbox| unsafe { !regs(index).tsr().read().tme(*mailbox) })
+        .count() as u8;
+    if dropped > 0 {
+        warn!("{} {} frames aborted for the search", bus.iface(), dropped);
+    }
+    abort(index);
+    let filters = OpenFilters::take(index);
+    let mut trials = heapless::Vec::<Trial, { CANDIDATES.len() }>::new();
+    for bitrate in CANDIDATES {
+        let Ok(timing) = timing::solve(pclk1, bitrate, SAMPLE_POINT_PERMILLE) else {
+            warn!(
+                "{} cannot try {} bit/s from {} Hz",
+                bus.iface(),
+                bitrate,
+                pclk1
+            );
+            continue;
+        };
+        reconfigure(can, timing.btr(), true).await;
+        let mut trial = Trial::new(bitrate, esr(index));
+        let until = Instant::now() + Duration::from_millis(DWELL_MS);
+        while Instant::now() < until {
+            Timer::after(AUTOBAUD_POLL).await;
+            // the FIFO interrupts masked themselves, nothing else reads the FIFOs now
+            loop {
+                match can.receive() {
+                    Ok(_) => trial.frame(),
+                    Err(nb::Error::Other(_)) => {}
+                    Err(nb::Error::WouldBlock) => break,
+                }
+            }
+            trial.esr(esr(index));
+        }
+        info!(
+            "{} {} bit/s: {} frames, {} errors",
+            bus.iface(),
+            bitrate,
+            trial.frames,
+            trial.errors
+        );
+        let _ = trials.push(trial);
+    }
+    if let Some(filters) = filters {
+        filters.restore();
+    }
+    let found = autobaud::pick(&trials);
+    match found {
+        Some(bitrate) => info!("{} bitrate detected: {} bit/s", bus.iface(), bitrate),
+        None => warn!(
+            "{} no bitrate detected, keeping {} bit/s",
+            bus.iface(),
+            previous
+        ),
+    }
+    reconfigure(can, bit_timing(bus, found.unwrap_or(previous)), false).await;
+    (found, dropped)
+}
+
+/// A frame on its way from the TX channel into a mailbox
+struct Pending {
+    frame: Frame,
+    /// Already captured, it was in a mailbox before a higher priority frame evicted it
+    captured: bool,
+}
+
+/// Move frames between `bus` and its channels: both RX FIFOs into `rx`, `tx` into the
+/// three mailboxes. Received and sent frames are captured, the error state is
+/// monitored and the filters follow `Gateway::sniffing`. Pauses for bitrate searches,
+/// otherwise never returns.
+pub async fn run<I: Instance, const RX: usize, const TX: usize>(
+    bus: Bus,
+    can: &mut bxcan::Can<I>,
+    rx: Sender<'_, _Mutex, Frame, RX>,
+    tx: Receiver<'_, _Mutex, Frame, TX>,
+) {
+    can.enable_interrupts(
+        Interrupts::TRANSMIT_MAILBOX_EMPTY
+            | Interrupts::FIFO0_MESSAGE_PENDING
+            | Interrupts::FIFO1_MESSAGE_PENDING,
+    );
+    let mut monitor = Monitor::new();
+    // `Some` while sniffing, holding the saved banks if the bus has any
+    let mut open: Option<Option<OpenFilters>> = None;
+    // taken off `tx` but not yet in a mailbox, kept when a search stops the futures
+    let mut pending: Option<Pending> = None;
+    loop {
+        let save = {
+            let (can_tx, rx0, rx1) = can.split_by_ref();
+            let fifo0 = async {
+                loop {
+                    let frame = receive(bus, 0, || rx0.receive()).await;
+                    GATEWAY.capture(bus, &frame);
+                    rx.send(frame).await;
+                }
+            };
+            let fifo1 = async {
+                loop {
+                    let frame = receive(bus, 1, || rx1.receive()).await;
+                    GATEWAY.capture(bus, &frame);
+                    rx.send(frame).await;
+                }
+            };
+            let mailboxes = async {
+                loop {
+                    let next = match pending.take() {
+                        Some(next) => next,
+                        None => Pending {
+                            frame: tx.recv().await,
+                            captured: false,
+                        },
+                    };
+                    let next = pending.insert(next);
+                    let evicted = transmit(bus, can_tx, &next.frame).await;
+                    if !next.captured {
+                        GATEWAY.capture(bus, &next.frame);
+                    }
+                    pending = evicted.map(|frame| Pending {
+                        frame,
+                        captured: true,
+                    });
+                }
+            };
+            let errors = async {
+                loop {
+                    Timer::after(ESR_POLL).await;
+                    if GATEWAY
+                        .bus_error_status(bus, &mut monitor, esr(bus.index()))
+                        .await
+                    {
+                        restart(bus.index()).await;
+                    }
+                    follow_sniffing(bus, &mut open);
+                }
+            };
+            // a received frame waiting for room in `rx` when the request lands is dropped
+            match select(
+                join4(fifo0, fifo1, mailboxes, errors),
+                GATEWAY.autobaud[bus.index()].wait(),
+            )
+            .await
+            {
+                Either::First(_) => continue,
+                Either::Second(save) => save,
+            }
+        };
+        let (found, dropped) = search(bus, can).await;
+        GATEWAY.finish_autobaud(bus, save, found, dropped).await;
+    }
+}
@@ -6,15 +6,16 @@
 // #![feature(async_closure)]
 #![feature(error_in_core)]
 
-use defmt::debug;
+use defmt::{debug, info, Debug2Format};
 use embassy_executor::Spawner;
 use embassy_stm32::can::Can;
 use embassy_stm32::time::mhz;
 use embedded_alloc::Heap;
 
+use first_test::config;
+use first_test::mqtt::discovery::DeviceId;
 use {defmt_rtt as _, panic_probe as _};
-pub mod config;
-mod errors;
+mod can;
 mod statics;
 mod tasks;
 mod types;
@@ -26,7 +27,8 @@ static HEAP: Heap = Heap::empty();
 async fn main(spawner: Spawner) {
     {
         use core::mem::MaybeUninit;
-        const HEAP_SIZE: usize = 1024 * 2;
+        // the MQTT client holds a topic and payload per publish
+        const HEAP_SIZE: usize = if cfg!(feature = "esp-at") { 1024 * 4 } else { 1024 * 2 };
         static mut HEAP_MEM: [MaybeUninit<u8>; HEAP_SIZE] = [MaybeUninit::uninit(); HEAP_SIZE];
         unsafe { HEAP.init(HEAP_MEM.as_ptr() as usize, HEAP_SIZE) }
     }
@@ -45,49 +47,109 @@ async fn main(spawner: Spawner) {
         pac::AFIO.mapr().modify(|w| w.set_swj_cfg(0b010)); // disables JTAG, enables PA15
     }
 
-    let uart = {
+    let (uart_tx, uart_rx) = {
         use embassy_stm32::interrupt;
         use embassy_stm32::usart;
         use embassy_stm32::usart::Uart;
         let mut config = usart::Config::default();
         config.baudrate = 115200;
         let irq = interrupt::take!(USART3);
-        Uart::new(
+        let mut uart = Uart::new(
             p.USART3, p.PC11, p.PC10, irq, p.DMA1_CH2, p.DMA1_CH3, config,
-        )
+        );
+        if uart.blocking_flush().is_err() {
+            panic!();
+        };
+        uart.split()
+    };
+
+    #[cfg(feature = "modbus")]
+    let rs485 = {
+        use embassy_stm32::interrupt;
+        use embassy_stm32::usart;
+        use embassy_stm32::usart::Uart;
+        let mut config = usart::Config::default();
+        config.baudrate = 19200;
+        // 8N2, the Modbus alternative to even parity
+        config.stop_bits = usart::StopBits::STOP2;
+        let irq = interrupt::take!(USART2);
+        Uart::new(p.USART2, p.PA3, p.PA2, irq, p.DMA1_CH7, p.DMA1_CH6, config)
     };
 
     let can1 = Can::new(p.CAN1, p.PA11, p.PA12);
     let can2 = Can::new(p.CAN2, p.PB5, p.PB6);
+    {
+        use embassy_stm32::interrupt;
+        use first_test::candump::Bus;
+        crate::can::bind(
+            Bus::Bms,
+            interrupt::take!(CAN1_TX),
+            interrupt::take!(CAN1_RX0),
+            interrupt::take!(CAN1_RX1),
+        );
+        crate::can::bind(
+            Bus::Inverter,
+            interrupt::take!(CAN2_TX),
+            interrupt::take!(CAN2_RX0),
+            interrupt::take!(CAN2_RX1),
+        );
+    }
 
-    defmt::unwrap!(spawner.spawn(crate::tasks::led_task(p.PC12)));
-    defmt::unwrap!(spawner.spawn(crate::tasks::contactor_task(p.PA15, p.TIM2)));
-    defmt::unwrap!(spawner.spawn(crate::tasks::mqtt::uart_task(uart)));
-
-    #[cfg(feature = "kangoo")]
-    use crate::tasks::can_processors_kangoo::*;
-    #[cfg(feature = "ze50")]
-    use crate::tasks::can_processors_ze50::*;
-
-    #[cfg(feature = "solax")]
-    use crate::tasks::can_processors_solax::*;
-
-    #[cfg(feature = "pylontech")]
-    use crate::tasks::can_processors_pylontech::*;
+    // restore the stored config before anything reads it
+    let mut store = crate::types::ConfigStore::new(
+        embassy_stm32::flash::Flash::new(p.FLASH),
+        crate::statics::CONFIG_FLASH_OFFSET,
+        crate::statics::CONFIG_FLASH_PAGES,
+    );
+    {
+        let mut stored = crate::statics::GATEWAY.config.lock().await;
+        *stored = config::record::load(&mut store);
+        crate::statics::GATEWAY
+            .capture_enabled
+            .store(stored.candump(), core::sync::atomic::Ordering::Relaxed);
+    }
+    defmt::unwrap!(spawner.spawn(crate::tasks::config_store_task(store)));
 
-    #[cfg(feature = "byd")]
-    use crate::tasks::can_processors_byd::*;
+    // Home Assistant device id from the 96-bit unique ID
+    let uid = unsafe { core::ptr::read_volatile(crate::statics::UID_ADDRESS as *const [u8; 12]) };
+    *crate::statics::GATEWAY.device_id.lock().await = DeviceId::from_uid(uid);
 
-    defmt::unwrap!(spawner.spawn(bms_rx()));
+    defmt::unwrap!(spawner.spawn(crate::tasks::led_task(p.PC12)));
+    defmt::unwrap!(spawner.spawn(crate::tasks::contactor_task(p.PA15, p.TIM2, p.PA8)));
+    defmt::unwrap!(spawner.spawn(crate::tasks::uart_rx_task(uart_rx)));
+    #[cfg(not(feature = "esp-at"))]
+    defmt::unwrap!(spawner.spawn(crate::tasks::mqtt::uart_task(uart_tx)));
+    #[cfg(feature = "esp-at")]
+    defmt::unwrap!(spawner.spawn(crate::tasks::esp::esp_task(uart_tx)));
+    #[cfg(feature = "modbus")]
+    defmt::unwrap!(spawner.spawn(crate::tasks::modbus::modbus_task(rs485, p.PA1)));
+
+    // protocols and bitrates are fixed for this boot, config changes apply after a restart
+    let (battery, inverter, bitrates) = {
+        use first_test::candump::Bus;
+        let config = crate::statics::GATEWAY.config.lock().await;
+        let bitrates = [config.bitrate(Bus::Bms), config.bitrate(Bus::Inverter)];
+        (config.battery_type(), config.inverter_type(), bitrates)
+    };
+    info!(
+        "Battery: {}, Inverter: {}",
+        Debug2Format(&battery),
+        Debug2Format(&inverter)
+    );
 
-    defmt::unwrap!(spawner.spawn(inverter_rx()));  // switched off whilst debugging BMS
+    defmt::unwrap!(spawner.spawn(crate::tasks::battery::bms_processor(battery)));
 
-    defmt::unwrap!(spawner.spawn(bms_tx_periodic()));
+    defmt::unwrap!(spawner.spawn(crate::tasks::inverter::inverter_rx(inverter)));
 
     // // always start can 1 first
 
-    defmt::unwrap!(spawner.spawn(crate::tasks::can_interfaces::bms_task(can1)));
-    defmt::unwrap!(spawner.spawn(crate::tasks::can_interfaces::inverter_task(can2)));
+    defmt::unwrap!(spawner.spawn(crate::tasks::can_interfaces::bms_task(
+        can1,
+        bitrates[0],
+        battery,
+        inverter
+    )));
+    defmt::unwrap!(spawner.spawn(crate::tasks::can_interfaces::inverter_task(can2, bitrates[1])));
 
     // defmt::unwrap!(spawner.spawn(crate::wdt::init(p.IWDG, 10000000))); // 10 seconds WDT OFF WHILST TESTING
     // defmt::unwrap!(spawner.spawn(crate::async_tasks::one_sec_periodic())); // e_t::Timer test
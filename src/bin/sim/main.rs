@@ -0,0 +1,170 @@
+//! Host-side virtual gateway.
+//!
+//! Runs the real battery and inverter processors from the library against two
+//! in-memory CAN buses, with scripted stand-ins playing the BMS and the inverter
+//! (see `first_test::script` for the script format). Files ending in `.log` are
+//! read as `candump -L` captures, replaying `can0` to the BMS side and `can1` to the
+//! inverter side.
+//!
+//! `cargo sim -- <battery.script> <inverter.script> [battery_type] [inverter_type]`
+#![feature(type_alias_impl_trait)]
+
+use bxcan::Frame;
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::{DynamicReceiver, DynamicSender};
+use embassy_time::{Duration, Instant, Timer};
+use first_test::candump::Bus;
+use first_test::config::{BatteryType, InverterType};
+use first_test::gateway::{battery::run_battery, contactor, inverter::run_inverter, Gateway};
+use first_test::mqtt::schedule::Scheduler;
+use first_test::{frame, script::Script};
+use log::{error, info, warn};
+
+type Sim = Gateway<CriticalSectionRawMutex>;
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        error!(
+            "usage: {} <battery.script> <inverter.script> [battery_type] [inverter_type]",
+            args[0]
+        );
+        std::process::exit(2);
+    }
+    let battery_script = load(&args[1], Bus::Bms);
+    let inverter_script = load(&args[2], Bus::Inverter);
+    let battery: BatteryType = args.get(3).map(|a| parse_type(a)).unwrap_or_default();
+    let inverter: InverterType = args.get(4).map(|a| parse_type(a)).unwrap_or_default();
+    info!("Battery: {:?}, Inverter: {:?}", battery, inverter);
+
+    let gateway: &'static Sim = Box::leak(Box::new(Sim::new()));
+
+    spawner.must_spawn(battery_processor(gateway, battery));
+    spawner.must_spawn(inverter_processor(gateway, inverter));
+    // CAN1: BMS stand-in <-> gateway
+    spawner.must_spawn(stand_in(
+        "bms",
+        battery_script,
+        gateway.bms_rx.sender().into(),
+        gateway.bms_tx.receiver().into(),
+    ));
+    // CAN2: inverter stand-in <-> gateway
+    spawner.must_spawn(stand_in(
+        "inverter",
+        inverter_script,
+        gateway.inverter_rx.sender().into(),
+        gateway.inverter_tx.receiver().into(),
+    ));
+    spawner.must_spawn(contactor_processor(gateway));
+    spawner.must_spawn(monitor(gateway));
+}
+
+fn load(path: &str, bus: Bus) -> Script {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        error!("{}: {}", path, e);
+        std::process::exit(2)
+    });
+    let script = match path.ends_with(".log") {
+        true => Script::from_candump(&text, bus),
+        false => Script::parse(&text),
+    };
+    script.unwrap_or_else(|e| {
+        error!("{}:{}: {:?}", path, e.line, e.kind);
+        std::process::exit(2)
+    })
+}
+
+/// `Ze50` -> `BatteryType::Ze50`, using the same names as the JSON config
+fn parse_type<T: miniserde::Deserialize>(name: &str) -> T {
+    miniserde::json::from_str(&format!("\"{}\"", name)).unwrap_or_else(|_| {
+        error!("Unknown protocol {}", name);
+        std::process::exit(2)
+    })
+}
+
+fn show(frame: &Frame) -> String {
+    let mut text = String::new();
+    let _ = frame::write(&mut text, frame);
+    text
+}
+
+#[embassy_executor::task]
+async fn battery_processor(gateway: &'static Sim, battery: BatteryType) {
+    run_battery(gateway, battery).await;
+    std::process::exit(1)
+}
+
+#[embassy_executor::task]
+async fn inverter_processor(gateway: &'static Sim, inverter: InverterType) {
+    run_inverter(gateway, inverter).await;
+    std::process::exit(1)
+}
+
+/// Virtual bus with a scripted device on the far end
+#[embassy_executor::task(pool_size = 2)]
+async fn stand_in(
+    name: &'static str,
+    script: Script,
+    to_gateway: DynamicSender<'static, Frame>,
+    from_gateway: DynamicReceiver<'static, Frame>,
+) {
+    let start = Instant::now();
+    let mut timed = script.timed.iter().peekable();
+    loop {
+        let event = match timed.peek() {
+            Some((at, _)) => {
+                let at = start + Duration::from_millis(*at);
+                select(Timer::at(at), from_gateway.recv()).await
+            }
+            None => Either::Second(from_gateway.recv().await),
+        };
+        match event {
+            Either::First(_) => {
+                let (_, frame) = timed.next().unwrap();
+                info!("{} >> gateway {}", name, show(frame));
+                to_gateway.send(frame.clone()).await;
+            }
+            Either::Second(frame) => {
+                info!("gateway >> {} {}", name, show(&frame));
+                if let Some(reply) = script.reply_to(&frame) {
+                    info!("{} >> gateway {}", name, show(reply));
+                    to_gateway.send(reply.clone()).await;
+                }
+            }
+        }
+    }
+}
+
+/// Contactor state machine with the relay outputs logged instead of driven
+#[embassy_executor::task]
+async fn contactor_processor(gateway: &'static Sim) {
+    contactor::run(gateway, |outputs| {
+        warn!(
+            "Precharge {}, main {:?}",
+            if outputs.precharge { "on" } else { "off" },
+            outputs.main
+        )
+    })
+    .await
+}
+
+/// Report the UART telemetry output
+#[embassy_executor::task]
+async fn monitor(gateway: &'static Sim) {
+    let mut scheduler = Scheduler::new();
+    loop {
+        select(gateway.send_mqtt.wait(), Timer::after(Duration::from_secs(1))).await;
+        for topic in gateway.telemetry_due(&mut scheduler).await {
+            let mut part = 0;
+            while let Some(line) = gateway.render(topic, part).await {
+                info!("MQTT {}", line);
+                part += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,8 @@
+use crate::config::BatteryType;
+use crate::statics::GATEWAY;
+use first_test::gateway::battery::run_battery;
+
+#[embassy_executor::task]
+pub async fn bms_processor(battery: BatteryType) {
+    run_battery(&GATEWAY, battery).await
+}
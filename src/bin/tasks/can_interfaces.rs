@@ -1,96 +1,64 @@
+use crate::config::{BatteryType, InverterType};
 use crate::statics::*;
-use defmt::{warn, Debug2Format};
-use embassy_futures::yield_now;
-use embassy_stm32::can::{bxcan::*, Can};
+use defmt::{error, info, warn};
+use embassy_stm32::can::Can;
 use embassy_stm32::peripherals::*;
-use nb::Error::*;
+use first_test::candump::Bus;
+use first_test::filter;
 
 #[embassy_executor::task]
-pub async fn inverter_task(mut can: Can<'static, CAN2>) {
-    let rx = INVERTER_CHANNEL_RX.sender();
-    let tx = INVERTER_CHANNEL_TX.receiver();
-    // use embassy_stm32::can::bxcan::Id::*;
+pub async fn inverter_task(mut can: Can<'static, CAN2>, bitrate: u32) {
     // Wait for Can1 to initalise
     CAN_READY.wait().await;
 
     can.modify_config()
-        .set_bit_timing(BITTIMINGS) // http://www.bittiming.can-wiki.info/
+        .set_bit_timing(crate::can::bit_timing(Bus::Inverter, bitrate))
         .set_loopback(false) // Receive own frames
         .set_silent(false)
         // .set_automatic_retransmit(false)
         .enable();
     warn!("Starting Inverter Can2");
 
-    // #[cfg(feature = "solax")]
-    // let canid = |frame: &Frame| -> u32 {
-    //     if let Extended(id) = frame.id() {
-    //         id.as_raw()
-    //     } else {
-    //         0
-    //     }
-    // };
-
-    // #[cfg(feature = "pylontech")]
-    // let canid = |frame: &Frame| -> u16 {
-    //     if let Standard(id) = frame.id() {
-    //         id.as_raw()
-    //     } else {
-    //         0
-    //     }
-    // };
-
-    loop {
-        yield_now().await;
-        if let Ok(frame) = can.receive() {
-            // #[cfg(feature = "solax")]
-            // if canid(&frame) == 0x1871 {
-            //     rx.send(frame).await
-            // };
+    let rx = GATEWAY.inverter_rx.sender();
+    let tx = GATEWAY.inverter_tx.receiver();
+    crate::can::run(Bus::Inverter, &mut can, rx, tx).await
+}
 
-            // #[cfg(feature = "pylontech")]
-            rx.send(frame).await
-        };
-        let Ok(frame) = tx.try_recv() else { continue };
-        match can.transmit(&frame) {
-            Ok(_) => {
-                defmt::info!("Inv Tx: {}", Debug2Format(&(frame.id(), frame.data())));
-                while !can.is_transmitter_idle() {
-                    yield_now().await
-                }
-            }
-            Err(WouldBlock) => (),
-            Err(Other(_)) => defmt::error!("Inv Tx error"),
+#[embassy_executor::task]
+pub async fn bms_task(
+    mut can: Can<'static, CAN1>,
+    bitrate: u32,
+    battery: BatteryType,
+    inverter: InverterType,
+) {
+    // CAN1 owns the banks of both buses, see `first_test::filter`
+    let layout = filter::for_protocols(battery, inverter).unwrap_or_else(|e| {
+        error!(
+            "CAN filters need {} of {} banks, accepting all frames",
+            e.needed,
+            filter::BANKS
+        );
+        defmt::unwrap!(filter::compile(filter::ACCEPT_ALL, filter::ACCEPT_ALL).ok())
+    });
+    {
+        let mut banks = can.modify_filters();
+        banks.set_split(layout.split());
+        for (index, bank) in layout.can1().iter().enumerate() {
+            banks.enable_bank(index as u8, bank.fifo(), bank.config());
+        }
+        let mut slave = banks.slave_filters();
+        for (index, bank) in layout.can2().iter().enumerate() {
+            slave.enable_bank(layout.split() + index as u8, bank.fifo(), bank.config());
         }
     }
-}
-#[embassy_executor::task]
-pub async fn bms_task(mut can: Can<'static, CAN1>) {
-    #[cfg(feature = "ze50")]
-    use embassy_stm32::can::bxcan::ExtendedId;
-
-    // BMS Filter ============================================
-    #[cfg(feature = "ze50")]
-    can.modify_filters().set_split(1).enable_bank(
-        0,
-        Fifo::Fifo0,
-        filter::Mask32::frames_with_ext_id(
-            ExtendedId::new(0x18DAF1DB).unwrap(),
-            ExtendedId::new(0x1ffffff).unwrap(),
-        ),
+    info!(
+        "CAN filters: {} banks on CAN1, {} on CAN2",
+        layout.can1().len(),
+        layout.can2().len()
     );
 
-    #[cfg(not(feature = "ze50"))]
-    can.modify_filters()
-        .set_split(1)
-        .enable_bank(0, Fifo::Fifo0, filter::Mask32::accept_all());
-
-    // Inverter Filter ============================================
-    can.modify_filters()
-        .slave_filters()
-        .enable_bank(1, Fifo::Fifo0, filter::Mask32::accept_all());
-
     can.modify_config()
-        .set_bit_timing(BITTIMINGS) // http://www.bittiming.can-wiki.info/
+        .set_bit_timing(crate::can::bit_timing(Bus::Bms, bitrate))
         .set_loopback(false) // Receive own frames
         .set_silent(false)
         .enable();
@@ -99,38 +67,7 @@ pub async fn bms_task(mut can: Can<'static, CAN1>) {
     // Signal to other can bus that filters have been applied
     CAN_READY.signal(true);
 
-    let rx = BMS_CHANNEL_RX.sender();
-    let tx = BMS_CHANNEL_TX.receiver();
-    // let canid = |frame: &Frame| -> u16 {
-    //     match frame.id() {
-    //         Standard(id) => id.as_raw(),
-    //         Id::Extended(_) => 0,
-    //     }
-    // };
-
-    loop {
-        // WDT.signal(true); // temp whilst testing
-        yield_now().await;
-        if let Ok(frame) = can.receive() {
-            // defmt::println!("BMS: Rx {:?}", frame);
-            // if let embassy_stm32::can::bxcan::Id::Extended(id) = frame.id() {
-            // if id.as_raw() == 0x18DAF1DB {
-            // defmt::info!("BMS>>STM {:?}", Debug2Format(&(frame.id(), frame.data())));
-            rx.send(frame).await;
-            // };
-            // }
-        };
-        let Ok(frame) = tx.try_recv() else { continue };
-        match can.transmit(&frame) {
-            Ok(_) => {
-                // defmt::info!("STM>>BMS: {}", Debug2Format(&(frame.id(), frame.data())));
-
-                while !can.is_transmitter_idle() {
-                    yield_now().await
-                }
-            }
-            Err(WouldBlock) => (),
-            Err(Other(_)) => defmt::error!("BMS Tx error"),
-        }
-    }
+    let rx = GATEWAY.bms_rx.sender();
+    let tx = GATEWAY.bms_tx.receiver();
+    crate::can::run(Bus::Bms, &mut can, rx, tx).await
 }
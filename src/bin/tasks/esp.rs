@@ -0,0 +1,179 @@
+use crate::statics::*;
+use defmt::error;
+use defmt::info;
+use defmt::warn;
+use defmt::Debug2Format;
+use embassy_stm32::peripherals::*;
+use embassy_stm32::usart::UartTx;
+use embassy_time::{Duration, Instant, Timer};
+use first_test::config::patch::{Ack, Patch};
+use first_test::esp::{Client, Event, LineBuffer, Settings};
+use first_test::mqtt::discovery::{self, DeviceId};
+use first_test::mqtt::schedule::Scheduler;
+use first_test::mqtt::Topic;
+use heapless::{Deque, Vec};
+use miniserde::__private::String;
+
+/// MQTT over an ESP-AT modem on USART3 in place of the UART JSON bridge.
+/// Telemetry goes to `gateway/<uid>/<topic>`, config patches are taken from
+/// `gateway/<uid>/config/set` and acknowledged on `gateway/<uid>/config/ack`.
+/// Modem output arrives from `uart_rx_task`.
+#[embassy_executor::task]
+pub async fn esp_task(mut tx: UartTx<'static, USART3, DMA1_CH2>) {
+    use embassy_futures::select::{select, Either};
+    let id = *GATEWAY.device_id.lock().await;
+    let base = discovery::base_topic(&id);
+    let settings = settings(&id, &base);
+    let subscribe = settings.subscribe.clone();
+    let mut client = Client::new(settings);
+    let mut lines = LineBuffer::new();
+    let mut scheduler = Scheduler::new();
+    let mut outbox = Outbox::default();
+    let mut payload: Option<String> = None;
+    let mut next_check = 0;
+    loop {
+        let now = Instant::now().as_millis();
+        if let Some(command) = client.poll(now) {
+            write(&mut tx, command.as_bytes()).await;
+        }
+        if client.ready() && outbox.is_empty() && now >= next_check {
+            next_check = now + 1000;
+            outbox.topics = GATEWAY.telemetry_due(&mut scheduler).await;
+        }
+        if client.ready() {
+            if let Some((topic, data, retain)) = outbox.next(&id, &base).await {
+                if let Some(command) = client.publish(&topic, data.len(), retain, now) {
+                    write(&mut tx, command.as_bytes()).await;
+                    payload = Some(data);
+                }
+            }
+        }
+
+        // only the wait is cancelled, bytes stay queued in `UART_RX`
+        let read = select(UART_RX.recv(), Timer::after(Duration::from_millis(100))).await;
+        let Either::First(Ok(chunk)) = read else {
+            continue;
+        };
+        let now = Instant::now().as_millis();
+        for byte in &chunk {
+            let Some(line) = lines.push(*byte) else {
+                continue;
+            };
+            match client.on_line(&line, now) {
+                Event::SendPayload => {
+                    if let Some(data) = payload.take() {
+                        write(&mut tx, data.as_bytes()).await;
+                    }
+                }
+                Event::PublishFailed => warn!("MQTT publish failed"),
+                Event::Received {
+                    topic,
+                    payload: patch,
+                } if topic == subscribe => {
+                    let ack = apply_config(&patch).await;
+                    if outbox.acks.push_back(ack).is_err() {
+                        warn!("MQTT config ack dropped");
+                    }
+                }
+                Event::Connected => {
+                    info!("MQTT connected");
+                    scheduler.request_discovery();
+                }
+                Event::Disconnected => {
+                    payload = None;
+                    outbox = Outbox::default();
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Build-time Wi-Fi and broker settings, the client id is derived from the UID
+fn settings(id: &DeviceId, base: &str) -> Settings {
+    let mut client_id = String::from("gw_");
+    id.write_hex(&mut client_id);
+    let mut subscribe = String::from(base);
+    subscribe.push_str("/config/set");
+    Settings {
+        ssid: option_env!("WIFI_SSID").unwrap_or_default().into(),
+        password: option_env!("WIFI_PASSWORD").unwrap_or_default().into(),
+        host: option_env!("MQTT_HOST")
+            .unwrap_or("homeassistant.local")
+            .into(),
+        port: option_env!("MQTT_PORT")
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(1883),
+        client_id,
+        user: option_env!("MQTT_USER").unwrap_or_default().into(),
+        pass: option_env!("MQTT_PASSWORD").unwrap_or_default().into(),
+        subscribe,
+    }
+}
+
+/// Config acks and the telemetry parts still to publish
+#[derive(Default)]
+struct Outbox {
+    acks: Deque<String, 4>,
+    topics: Vec<Topic, 6>,
+    /// Index into `topics` and the part of it to send next
+    topic: usize,
+    part: usize,
+}
+
+impl Outbox {
+    fn is_empty(&self) -> bool {
+        self.acks.is_empty() && self.topic >= self.topics.len()
+    }
+
+    /// Next `(topic, payload, retain)`, acks first
+    async fn next(&mut self, id: &DeviceId, base: &str) -> Option<(String, String, bool)> {
+        if let Some(ack) = self.acks.pop_front() {
+            let mut topic = String::from(base);
+            topic.push_str("/config/ack");
+            return Some((topic, ack, false));
+        }
+        while let Some(&topic) = self.topics.get(self.topic) {
+            let part = self.part;
+            self.part += 1;
+            let message = match topic {
+                // retained so Home Assistant picks the sensors up whenever it starts
+                Topic::Discovery => discovery::config_topic(id, part)
+                    .zip(discovery::payload(id, part))
+                    .map(|(topic, payload)| (topic, payload, true)),
+                _ => GATEWAY.render_data(topic, part).await.map(|data| {
+                    let mut name = String::from(base);
+                    name.push('/');
+                    name.push_str(topic.name());
+                    (name, data, false)
+                }),
+            };
+            if message.is_some() {
+                return message;
+            }
+            info!("MQTT {} published", topic.name());
+            self.topic += 1;
+            self.part = 0;
+        }
+        self.topics.clear();
+        self.topic = 0;
+        None
+    }
+}
+
+async fn apply_config(payload: &str) -> String {
+    let result = match Patch::from_json(payload) {
+        Ok(patch) => GATEWAY.apply_config(&patch).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = &result {
+        error!("MQTT config rejected: {}", Debug2Format(e));
+    }
+    Ack::new(&result).to_json()
+}
+
+async fn write(tx: &mut UartTx<'static, USART3, DMA1_CH2>, bytes: &[u8]) {
+    if let Err(e) = tx.write(bytes).await {
+        error!("ESP-AT UART send error {}", Debug2Format(&e));
+    }
+}
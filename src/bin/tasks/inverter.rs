@@ -0,0 +1,8 @@
+use crate::config::InverterType;
+use crate::statics::GATEWAY;
+use first_test::gateway::inverter::run_inverter;
+
+#[embassy_executor::task]
+pub async fn inverter_rx(inverter: InverterType) {
+    run_inverter(&GATEWAY, inverter).await
+}
@@ -1,62 +1,84 @@
-use defmt::{info, warn};
-use embassy_stm32::peripherals::{PA15, PC12, TIM2};
+use defmt::{info, warn, Debug2Format};
+use embassy_stm32::peripherals::{DMA1_CH3, PA15, PA8, PC12, TIM2, USART3};
+use embassy_stm32::usart::UartRx;
 
-use crate::statics::CONTACTOR_STATE;
+use crate::statics::{GATEWAY, UART_RX};
+use crate::types::UartChunk;
 
+pub mod battery;
 pub mod can_interfaces;
-
-#[cfg(feature = "kangoo")]
-pub mod can_processors_kangoo;
-
-#[cfg(feature = "ze50")]
-pub mod can_processors_ze50;
-
-#[cfg(feature = "pylontech")]
-pub mod can_processors_pylontech;
-
-#[cfg(feature = "byd")]
-pub mod can_processors_byd;
-
-#[cfg(feature = "solax")]
-pub mod can_processors_solax;
-
+#[cfg(feature = "esp-at")]
+pub mod esp;
+pub mod inverter;
+#[cfg(feature = "modbus")]
+pub mod modbus;
 pub mod mqtt;
 
 // Misc tasks
 
+/// Main contactor PWM on PA15 and precharge relay on PA8, driven by the
+/// `first_test::contactor` state machine
 #[embassy_executor::task]
-pub async fn contactor_task(pin: PA15, timer: TIM2) {
+pub async fn contactor_task(pin: PA15, timer: TIM2, precharge: PA8) {
+    use embassy_stm32::gpio::{Level, Output, Speed};
     use embassy_stm32::pwm::simple_pwm::{PwmPin, SimplePwm};
     use embassy_stm32::pwm::Channel as TimerChannel;
     use embassy_stm32::time::khz;
-    use embassy_time::{Duration, Timer};
+    use first_test::contactor::Drive;
     let ch1 = PwmPin::new_ch1(pin);
     let mut pwm = SimplePwm::new(timer, Some(ch1), None, None, None, khz(1));
     let max = pwm.get_max_duty() - 1;
-    let mut active = false;
-    loop {
-        let state = CONTACTOR_STATE.wait().await;
-        match (state, active) {
-            (false, true) => {
-                warn!("Contactor shutdown");
-                pwm.disable(TimerChannel::Ch1);
-                info!("Contactor disabled");
-                active = false;
-            }
-            (true, false) => {
-                warn!("Activate 100% duty, wait 100ms, set duty to 50%, set active to true");
+    let mut precharge = Output::new(precharge, Level::Low, Speed::Low);
+    first_test::gateway::contactor::run(&GATEWAY, |outputs| {
+        match outputs.precharge {
+            true => precharge.set_high(),
+            false => precharge.set_low(),
+        }
+        match outputs.main {
+            Drive::Off => pwm.disable(TimerChannel::Ch1),
+            Drive::PullIn => {
                 pwm.enable(TimerChannel::Ch1);
-                info!("Contactor enabled");
                 pwm.set_duty(TimerChannel::Ch1, max);
-                info!("Contactor at 100%");
-                Timer::after(Duration::from_millis(100)).await;
+            }
+            Drive::Hold => {
+                pwm.enable(TimerChannel::Ch1);
                 pwm.set_duty(TimerChannel::Ch1, (max / 4) * 3);
-                info!("Contactor at hold 50%");
-                active = true
             }
-            (true, true) => info!("Contactor holding"),
-            _ => (),
         }
+    })
+    .await
+}
+
+/// Sole reader of USART3 for the UART bridge and the ESP-AT client. A read is never
+/// cancelled by the consumer's other events, which would drop the bytes already moved
+/// by DMA; only a consumer two chunks behind loses input, to a hardware overrun that
+/// is passed on so stateful decoders can resynchronise.
+#[embassy_executor::task]
+pub async fn uart_rx_task(mut rx: UartRx<'static, USART3, DMA1_CH3>) {
+    loop {
+        let mut chunk = UartChunk::new();
+        let _ = chunk.resize_default(chunk.capacity());
+        let read = match rx.read_until_idle(&mut chunk).await {
+            Ok(len) => {
+                chunk.truncate(len);
+                Ok(chunk)
+            }
+            Err(e) => {
+                warn!("UART receive error {}", Debug2Format(&e));
+                Err(e)
+            }
+        };
+        UART_RX.send(read).await;
+    }
+}
+
+/// Write the config to flash whenever it is changed over UART
+#[embassy_executor::task]
+pub async fn config_store_task(mut store: crate::types::ConfigStore) {
+    loop {
+        GATEWAY.save_config.wait().await;
+        let config = GATEWAY.config.lock().await;
+        first_test::config::record::save(&mut store, &config);
     }
 }
 
@@ -0,0 +1,30 @@
+use crate::statics::*;
+use defmt::error;
+use defmt::Debug2Format;
+use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_stm32::peripherals::*;
+use embassy_stm32::usart::Uart;
+
+/// Modbus RTU slave on USART2 (PA2 TX, PA3 RX) through an RS485 transceiver, DE on PA1.
+/// A request is a burst of bytes ended by an idle line.
+#[embassy_executor::task]
+pub async fn modbus_task(uart: Uart<'static, USART2, DMA1_CH7, DMA1_CH6>, de: PA1) {
+    let mut de = Output::new(de, Level::Low, Speed::Medium);
+    let (mut tx, mut rx) = uart.split();
+    let mut buf = [0_u8; first_test::modbus::MAX_ADU];
+    loop {
+        let Ok(len) = rx.read_until_idle(&mut buf).await else {
+            continue;
+        };
+        let Some(response) = GATEWAY.modbus(MODBUS_UNIT_ID, &buf[..len]).await else {
+            continue;
+        };
+        de.set_high();
+        if let Err(e) = tx.write(&response).await {
+            error!("Modbus send error {}", Debug2Format(&e));
+        }
+        // DMA is done before the last stop bit, keep driving the bus until TC
+        let _ = tx.blocking_flush();
+        de.set_low();
+    }
+}
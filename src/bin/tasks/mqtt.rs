@@ -1,111 +1,329 @@
 use crate::statics::*;
 use defmt::error;
 use defmt::info;
+use defmt::warn;
 use defmt::Debug2Format;
+use core::sync::atomic::Ordering;
 use embassy_stm32::peripherals::*;
-use embassy_stm32::usart::Uart;
-use embassy_time::Instant;
-use miniserde::__private::String;
-use miniserde::{json, Serialize};
+use embassy_stm32::usart::UartTx;
+use embassy_time::{Duration, Instant, Timer};
+use first_test::candump::{self, Bus, Record};
+use first_test::command;
+use first_test::config::patch::{Ack, Patch};
+use first_test::gvret;
+use first_test::gateway::command::Reply;
+use first_test::mqtt::schedule::Scheduler;
+use first_test::mqtt::Topic;
+use first_test::slcan::{self, Action};
+use gateway_wire::{Decoder, Frame, Packet, MAX_FRAME};
+
+/// Binary transport state, latched by the first host frame that passes its CRC
+struct Binary {
+    decoder: Decoder,
+    seq: u8,
+    frame: Frame,
+    active: bool,
+}
+
+impl Binary {
+    fn next_seq(&mut self) -> u8 {
+        let seq = self.seq;
+        self.seq = seq.wrapping_add(1);
+        seq
+    }
+}
+
+/// UART bridge: commands, config patches, SLCAN, GVRET and binary frames in, telemetry
+/// and captured frames out. Input arrives from `uart_rx_task`, so output events never
+/// cut a read short.
 #[embassy_executor::task]
-pub async fn uart_task(uart: Uart<'static, USART3, DMA1_CH2, DMA1_CH3>) {
-    use embassy_futures::select::{select, Either};
-    let mut uart = uart;
-    if uart.blocking_flush().is_err() {
-        panic!();
+pub async fn uart_task(mut tx: UartTx<'static, USART3, DMA1_CH2>) {
+    use embassy_futures::select::{select, select3, Either3};
+    let mut scheduler = Scheduler::new();
+    let mut slcan = slcan::Session::new();
+    let mut slcan_bus = Bus::Bms;
+    // JSON and replay lines end in \n, SLCAN commands in \r, any can straddle reads
+    let mut lines = candump::LineBuffer::new();
+    // set once SavvyCAN switches to GVRET, binary until reset
+    let mut gvret: Option<gvret::Decoder> = None;
+    // every byte is also tried as COBS until a valid frame switches to binary until reset
+    let mut binary = Binary {
+        decoder: Decoder::new(),
+        seq: 0,
+        frame: [0; MAX_FRAME],
+        active: false,
     };
-    let (mut tx, mut rx) = uart.split();
-    let mut buf = [0_u8; 512];
-    let mut mqtt_frequency = Instant::now();
     loop {
-        match select(rx.read_until_idle(&mut buf), SEND_MQTT.wait()).await {
-            Either::First(read) => match read {
-                Ok(len) => {
-                    let mut config = CONFIG.lock().await;
-                    if let Err(e) = config.update_from_json(&buf[..len]) {
-                        error!("UART deserialise bytes error {}", Debug2Format(&e))
-                    } else {
-                        info!("Config updated from UART")
+        match select3(
+            UART_RX.recv(),
+            // state changes wake immediately, otherwise check the rates once a second
+            select(
+                GATEWAY.send_mqtt.wait(),
+                Timer::after(Duration::from_secs(1)),
+            ),
+            GATEWAY.capture.recv(),
+        )
+        .await
+        {
+            // a GVRET command may have lost bytes, wait for the next `F1`
+            Either3::First(Err(_)) => {
+                if let Some(decoder) = gvret.as_mut() {
+                    decoder.reset();
+                }
+            }
+            Either3::First(Ok(chunk))
+                if gvret.is_some() || chunk.starts_with(&[gvret::ENTER_BINARY]) =>
+            {
+                let decoder = gvret.get_or_insert_with(|| {
+                    info!("GVRET binary mode");
+                    for open in &GATEWAY.bridge_open {
+                        open.store(true, Ordering::Relaxed);
+                    }
+                    gvret::Decoder::new()
+                });
+                for byte in &chunk {
+                    if let Some(request) = decoder.push(*byte) {
+                        gvret_request(request, &mut tx).await;
+                    }
+                }
+            }
+            Either3::First(Ok(chunk)) if binary.active => {
+                binary_input(&mut binary, &chunk, &mut tx).await
+            }
+            Either3::First(Ok(chunk)) => {
+                for (i, byte) in chunk.iter().enumerate() {
+                    // noise or a cut frame fails the CRC and leaves JSON running
+                    if let Some(Ok(packet)) = binary.decoder.push(*byte) {
+                        info!("Binary transport");
+                        binary.active = true;
+                        binary_reply(&mut binary, &packet, &mut tx).await;
+                        binary_input(&mut binary, &chunk[i + 1..], &mut tx).await;
+                        break;
+                    }
+                    let Some(line) = lines.push(*byte) else {
+                        continue;
+                    };
+                    let line = line.trim_ascii();
+                    // text has no control bytes, the start of a binary frame does
+                    let text = line.iter().all(|b| !b.is_ascii_control() || *b == b'\t');
+                    if line.is_empty() || !text {
+                        continue;
+                    }
+                    if line.starts_with(b"(") {
+                        replay_candump(line).await;
+                        continue;
+                    }
+                    if !line.starts_with(b"{") {
+                        slcan_command(&mut slcan, &mut slcan_bus, line, &mut tx).await;
+                        continue;
+                    }
+                    let Some(reply) = handle_line(line).await else {
+                        continue;
                     };
-                    buf = [0_u8; 512];
+                    if let Err(e) = tx.write(reply.text.as_bytes()).await {
+                        error!("UART send bytes error {}", Debug2Format(&e));
+                    }
+                    let _ = tx.write(b"\n").await;
+                    if reply.reboot {
+                        cortex_m::peripheral::SCB::sys_reset();
+                    }
                 }
-                Err(_) => continue,
-            },
-            Either::Second(_) => {
-                if mqtt_frequency.elapsed().as_secs() < LAST_READING_TIMEOUT_SECS {
+            }
+            // JSON telemetry would corrupt the SLCAN or GVRET stream
+            Either3::Second(_) if slcan.is_open() || gvret.is_some() => (),
+            Either3::Second(_) if binary.active => {
+                let due = GATEWAY.telemetry_due(&mut scheduler).await;
+                // the binary summary carries the alarm as well, the rest is JSON only
+                if !due.iter().any(|topic| matches!(topic, Topic::Summary | Topic::Alarm)) {
                     continue;
                 }
-                mqtt_frequency = Instant::now();
-                buf = [0_u8; 512];
-                let mqtt_data = MQTTFMT.lock().await;
-                if let Err(e) = tx.write(mqtt_data.device_update_msg().as_bytes()).await {
+                let seq = binary.next_seq();
+                let len = GATEWAY.binary_summary(seq, &mut binary.frame).await;
+                if let Err(e) = tx.write(&binary.frame[..len]).await {
                     error!("UART send bytes error {}", Debug2Format(&e));
-                } else {
-                    info!("MQTT sent to UART")
-                };
+                }
+            }
+            Either3::Second(_) => {
+                for topic in GATEWAY.telemetry_due(&mut scheduler).await {
+                    let mut part = 0;
+                    while let Some(line) = GATEWAY.render(topic, part).await {
+                        part += 1;
+                        if let Err(e) = tx.write(line.as_bytes()).await {
+                            error!("UART send bytes error {}", Debug2Format(&e));
+                            break;
+                        }
+                        let _ = tx.write(b"\n").await;
+                    }
+                    info!("MQTT {} sent to UART", topic.name())
+                }
+            }
+            // candump lines would corrupt the binary stream
+            Either3::Third(_) if binary.active => (),
+            Either3::Third(record) if gvret.is_some() => {
+                let mut message = gvret::Message::new();
+                gvret::write_frame(&mut message, &record);
+                if let Err(e) = tx.write(&message).await {
+                    error!("UART send bytes error {}", Debug2Format(&e));
+                }
+            }
+            Either3::Third(record) if slcan.is_open() => {
+                let mut line = slcan::Line::new();
+                if record.bus == slcan_bus
+                    && slcan.write_frame(&mut line, &record.frame, record.at_us).is_ok()
+                {
+                    if let Err(e) = tx.write(line.as_bytes()).await {
+                        error!("UART send bytes error {}", Debug2Format(&e));
+                    }
+                }
+            }
+            Either3::Third(record) => {
+                let mut line = candump::Line::new();
+                if candump::write_line(&mut line, &record).is_ok() {
+                    if let Err(e) = tx.write(line.as_bytes()).await {
+                        error!("UART send bytes error {}", Debug2Format(&e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Run a command, or apply a bare JSON object as a config patch
+async fn handle_line(line: &[u8]) -> Option<Reply> {
+    let text = core::str::from_utf8(line).ok()?.trim();
+    if text.is_empty() {
+        return None;
+    }
+    let text = match command::parse(text) {
+        Some(Ok(request)) => return Some(GATEWAY.execute(request).await),
+        Some(Err(e)) => command::error(e.id, e.code, None),
+        None => {
+            let result = match Patch::from_json(text) {
+                Ok(patch) => GATEWAY.apply_config(&patch).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = &result {
+                error!("UART config rejected: {}", Debug2Format(e));
             }
+            Ack::new(&result).to_json()
         }
+    };
+    Some(Reply {
+        text,
+        reboot: false,
+    })
+}
+
+/// Run one SLCAN command, opening the channel on `Config::slcan_bus`
+async fn slcan_command(
+    slcan: &mut slcan::Session,
+    bus: &mut Bus,
+    line: &[u8],
+    tx: &mut UartTx<'static, USART3, DMA1_CH2>,
+) {
+    let Ok(command) = core::str::from_utf8(line) else {
+        return;
+    };
+    if !slcan.is_open() {
+        // `Sn` comes before the open, check it against the bus that will be bridged
+        let bus = GATEWAY.config.lock().await.slcan_bus();
+        slcan.set_bitrate(GATEWAY.bitrate(bus));
+    }
+    let mut reply = slcan::Line::new();
+    match slcan.handle(command.trim(), &mut reply) {
+        Action::Open(mode) => {
+            *bus = GATEWAY.config.lock().await.slcan_bus();
+            GATEWAY.bridge_open[bus.index()].store(true, Ordering::Relaxed);
+            info!("SLCAN open on {}, {}", bus.iface(), Debug2Format(&mode));
+        }
+        Action::Close => {
+            GATEWAY.bridge_open[bus.index()].store(false, Ordering::Relaxed);
+            info!("SLCAN closed");
+        }
+        Action::Transmit(frame) => match bus {
+            Bus::Bms => GATEWAY.bms_tx.send(frame).await,
+            Bus::Inverter => GATEWAY.inverter_tx.send(frame).await,
+        },
+        Action::None => (),
+    }
+    if let Err(e) = tx.write(reply.as_bytes()).await {
+        error!("UART send bytes error {}", Debug2Format(&e));
     }
 }
 
-#[derive(Clone, Copy, Serialize)]
-pub struct MqttFormat {
-    soc: f32,
-    volts: f32,
-    cell_mv_high: f32,
-    cell_mv_low: f32,
-    cell_temp_high: f32,
-    cell_temp_low: f32,
-    // #[serde(with = "BigArray")]
-    // #[serde(skip)]
-    // cells_millivolts: [u16; 96],
-    // #[serde(skip)]
-    // #[serde(with = "BigArray")]
-    // cell_balance: [bool; 96],
-    amps: f32,
-    kwh: f32,
-    charge: f32,
-    discharge: f32,
-    bal: u8,
-    valid: bool,
+/// Act on one GVRET request from SavvyCAN
+async fn gvret_request(request: gvret::Request, tx: &mut UartTx<'static, USART3, DMA1_CH2>) {
+    let mut message = gvret::Message::new();
+    match request {
+        gvret::Request::Transmit { bus, frame } => match bus {
+            Bus::Bms => GATEWAY.bms_tx.send(frame).await,
+            Bus::Inverter => GATEWAY.inverter_tx.send(frame).await,
+        },
+        gvret::Request::Echo { bus, frame } => {
+            let at_us = Instant::now().as_micros();
+            gvret::write_frame(&mut message, &Record { at_us, bus, frame });
+        }
+        gvret::Request::SetupCanbus(speeds) => {
+            for (bus, speed) in [Bus::Bms, Bus::Inverter].into_iter().zip(speeds) {
+                if speed != 0 && speed != GATEWAY.bitrate(bus) {
+                    warn!("GVRET bitrate change on {} ignored, set it in the config", bus.iface());
+                }
+            }
+        }
+        gvret::Request::Query(command) => {
+            let bitrates = [GATEWAY.bitrate(Bus::Bms), GATEWAY.bitrate(Bus::Inverter)];
+            gvret::reply(command, Instant::now().as_micros(), bitrates, &mut message)
+        }
+        gvret::Request::Ignored => (),
+    }
+    if message.is_empty() {
+        return;
+    }
+    if let Err(e) = tx.write(&message).await {
+        error!("UART send bytes error {}", Debug2Format(&e));
+    }
 }
 
-impl MqttFormat {
-    pub fn default() -> Self {
-        Self {
-            soc: 0.0,
-            volts: 0.0,
-            cell_mv_high: 0.0,
-            cell_mv_low: 0.0,
-            cell_temp_high: 0.0,
-            cell_temp_low: 0.0,
-            // cells_millivolts: [0; 96],
-            // cell_balance: [false; 96],
-            amps: 0.0,
-            kwh: 0.0,
-            charge: 0.0,
-            discharge: 0.0,
-            bal: 0,
-            valid: false,
+/// Answer the host's binary frames, see `gateway_wire::message`
+async fn binary_input(
+    binary: &mut Binary,
+    bytes: &[u8],
+    tx: &mut UartTx<'static, USART3, DMA1_CH2>,
+) {
+    for byte in bytes {
+        match binary.decoder.push(*byte) {
+            None => (),
+            Some(Ok(packet)) => binary_reply(binary, &packet, tx).await,
+            Some(Err(e)) => warn!("Binary frame dropped: {}", Debug2Format(&e)),
         }
     }
-    pub fn update(&mut self, bmsdata: kangoo_battery::Bms) {
-        self.soc = bmsdata.soc as f32;
-        self.volts = (bmsdata.pack_volts as f32) * 0.1;
-        self.cell_mv_high = bmsdata.max_volts as f32;
-        self.cell_mv_low = bmsdata.min_volts as f32;
-        self.cell_temp_high = (bmsdata.temp_max as f32) * 0.1;
-        self.cell_temp_low = (bmsdata.temp_min as f32) * 0.1;
-        // self.cells_millivolts = bmsdata.cells;
-        // self.cell_balance = bmsdata.bal_cells;
-        self.amps = (bmsdata.current as f32) * 0.1;
-        self.kwh = (bmsdata.kwh_remaining as f32) * 0.1;
-        self.charge = (bmsdata.charge_max as f32) * 0.1;
-        self.discharge = (bmsdata.discharge_max as f32) * 0.1;
-        self.bal = bmsdata.balancing_cells;
-        self.valid = bmsdata.valid;
+}
+
+/// Answer one host frame
+async fn binary_reply(
+    binary: &mut Binary,
+    packet: &Packet,
+    tx: &mut UartTx<'static, USART3, DMA1_CH2>,
+) {
+    let seq = binary.next_seq();
+    let len = GATEWAY.binary_request(packet, seq, &mut binary.frame).await;
+    if let Err(e) = tx.write(&binary.frame[..len]).await {
+        error!("UART send bytes error {}", Debug2Format(&e));
     }
-    fn device_update_msg(&self) -> String {
-        json::to_string(&self)
+}
+
+/// Feed one `candump -L` line received over UART into the processors as if it came
+/// off the bus
+async fn replay_candump(line: &[u8]) {
+    let Ok(line) = core::str::from_utf8(line) else {
+        warn!("Replay line is not UTF-8");
+        return;
+    };
+    match candump::parse_line(line) {
+        Ok(record) => match record.bus {
+            Bus::Bms => GATEWAY.bms_rx.send(record.frame).await,
+            Bus::Inverter => GATEWAY.inverter_rx.send(record.frame).await,
+        },
+        Err(e) => warn!("Replay line skipped: {}", Debug2Format(&e)),
     }
 }
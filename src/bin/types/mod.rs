@@ -1,29 +1,12 @@
-use crate::config::Config;
-use crate::tasks::mqtt::MqttFormat;
-use embassy_stm32::can::bxcan::Frame;
-use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex as _Mutex;
-use embassy_sync::channel::Channel;
-use embassy_sync::mutex::Mutex;
+pub use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex as _Mutex;
 use embassy_sync::signal::Signal;
-use embassy_time::Instant;
 
-pub type InverterChannelRx = Channel<_Mutex, Frame, 2>;
-pub type InverterChannelTx = Channel<_Mutex, Frame, 20>;
-pub type BmsChannelRx = Channel<_Mutex, Frame, 20>;
-pub type BmsChannelTx = Channel<_Mutex, Frame, 20>;
-pub type Elapsed = Mutex<_Mutex, Instant>;
-pub type MqttFmtMutex = embassy_sync::mutex::Mutex<_Mutex, MqttFormat>;
-pub type ConfigType = embassy_sync::mutex::Mutex<_Mutex, Config>;
 pub type Status = Signal<_Mutex, bool>;
 
-#[cfg(feature = "solax")]
-pub type InverterDataMutex = embassy_sync::mutex::Mutex<_Mutex, solax_can_bus::SolaxBms>;
-#[cfg(feature = "pylontech")]
-pub type InverterDataMutex = embassy_sync::mutex::Mutex<_Mutex, pylontech_protocol::PylontechBms>;
-// #[cfg(feature = "byd")]
-// pub type InverterDataMutex =
-//     embassy_sync::mutex::Mutex<_Mutex, crate::tasks::can_processors_byd::Bms>;
+/// Bytes of one USART3 read, up to an idle line or a full buffer
+pub type UartChunk = heapless::Vec<u8, 512>;
+/// A read that failed, e.g. on overrun, lost its bytes
+pub type UartRead = Result<UartChunk, embassy_stm32::usart::Error>;
 
-#[cfg(feature = "ze50")]
-pub type Ze50DataMutex = embassy_sync::mutex::Mutex<_Mutex, renault_zoe_ph2_battery::Data>;
-pub type Ze50BmsMutex = embassy_sync::mutex::Mutex<_Mutex, renault_zoe_ph2_battery::bms::Bms>;
+/// Wear-levelled config record in the flash pages reserved by `memory.x`
+pub type ConfigStore = first_test::storage::SlotStore<embassy_stm32::flash::Flash<'static>>;
@@ -0,0 +1,215 @@
+//! Linux `candump -L` log lines: `(1436509052.249713) can0 18DAF1DB#0322`.
+//!
+//! CAN1 (BMS) is logged as `can0` and CAN2 (inverter) as `can1`, so captures open
+//! directly in SavvyCAN and can-utils.
+
+use crate::frame::{self, FrameTextError};
+use bxcan::Frame;
+use core::fmt::Write;
+use miniserde::{Deserialize, Serialize};
+
+/// Longest line: `(4294967295.999999) can1 18DAF1DB#0102030405060708\n`
+pub type Line = heapless::String<64>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Bus {
+    /// CAN1
+    Bms,
+    /// CAN2
+    Inverter,
+}
+
+impl Bus {
+    /// 0 for CAN1, 1 for CAN2
+    pub fn index(self) -> usize {
+        match self {
+            Bus::Bms => 0,
+            Bus::Inverter => 1,
+        }
+    }
+
+    pub fn iface(self) -> &'static str {
+        match self {
+            Bus::Bms => "can0",
+            Bus::Inverter => "can1",
+        }
+    }
+
+    pub fn from_iface(iface: &str) -> Option<Self> {
+        match iface {
+            "can0" => Some(Bus::Bms),
+            "can1" => Some(Bus::Inverter),
+            _ => None,
+        }
+    }
+}
+
+/// One captured frame
+#[derive(Clone, Debug)]
+pub struct Record {
+    /// Microseconds since boot (or since the epoch for host captures)
+    pub at_us: u64,
+    pub bus: Bus,
+    pub frame: Frame,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CandumpError {
+    BadTimestamp,
+    UnknownInterface,
+    Frame(FrameTextError),
+}
+
+/// Write one log line including the trailing newline
+pub fn write_line<W: Write>(out: &mut W, record: &Record) -> core::fmt::Result {
+    write!(
+        out,
+        "({}.{:06}) {} ",
+        record.at_us / 1_000_000,
+        record.at_us % 1_000_000,
+        record.bus.iface()
+    )?;
+    frame::write(out, &record.frame)?;
+    out.write_char('\n')
+}
+
+/// Parse one log line, surrounding whitespace is ignored
+pub fn parse_line(line: &str) -> Result<Record, CandumpError> {
+    let mut fields = line.split_whitespace();
+    let at_us = fields
+        .next()
+        .and_then(|t| t.strip_prefix('('))
+        .and_then(|t| t.strip_suffix(')'))
+        .and_then(|t| t.split_once('.'))
+        .and_then(|(secs, frac)| {
+            // candump always writes 6 digits, be lenient with shorter fractions
+            let secs: u64 = secs.parse().ok()?;
+            let micros: u64 = frac.get(..frac.len().min(6))?.parse().ok()?;
+            Some(secs * 1_000_000 + micros * 10u64.pow(6 - frac.len().min(6) as u32))
+        })
+        .ok_or(CandumpError::BadTimestamp)?;
+    let bus = fields
+        .next()
+        .and_then(Bus::from_iface)
+        .ok_or(CandumpError::UnknownInterface)?;
+    let frame = frame::parse(fields.next().unwrap_or("")).map_err(CandumpError::Frame)?;
+    Ok(Record { at_us, bus, frame })
+}
+
+/// Longest input line kept by `LineBuffer`, the size of one UART read
+pub const INPUT_LEN: usize = 512;
+
+/// Joins `\r` or `\n` terminated UART input lines, replayed captures, JSON and SLCAN
+/// commands alike, that arrive split over several reads
+pub struct LineBuffer {
+    buf: heapless::Vec<u8, INPUT_LEN>,
+    overflow: bool,
+    /// `buf` holds the line returned last, cleared by the next byte
+    complete: bool,
+}
+
+impl LineBuffer {
+    pub fn new() -> Self {
+        Self {
+            buf: heapless::Vec::new(),
+            overflow: false,
+            complete: false,
+        }
+    }
+
+    /// Add one byte, returns a complete non-empty line without its ending. Lines
+    /// longer than `INPUT_LEN` are dropped.
+    pub fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        if self.complete {
+            self.buf.clear();
+            self.complete = false;
+        }
+        match byte {
+            b'\r' | b'\n' => {
+                self.complete = true;
+                let overflow = core::mem::replace(&mut self.overflow, false);
+                (!overflow && !self.buf.is_empty()).then_some(&self.buf[..])
+            }
+            _ => {
+                if self.buf.push(byte).is_err() {
+                    self.overflow = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let line = "(12.000345) can1 18DAF1DB#0322900102\n";
+        let record = parse_line(line).unwrap();
+        assert_eq!(record.at_us, 12_000_345);
+        assert_eq!(record.bus, Bus::Inverter);
+        let mut out = Line::new();
+        write_line(&mut out, &record).unwrap();
+        assert_eq!(out.as_str(), line);
+    }
+
+    #[test]
+    fn rejects_unknown_interface() {
+        assert_eq!(
+            parse_line("(1.000000) vcan0 123#00").err(),
+            Some(CandumpError::UnknownInterface)
+        );
+        assert_eq!(
+            parse_line("1.000000 can0 123#00").err(),
+            Some(CandumpError::BadTimestamp)
+        );
+    }
+
+    #[test]
+    fn longest_line_fits() {
+        let record = parse_line("(4294967295.999999) can1 18DAF1DB#0102030405060708").unwrap();
+        let mut out = Line::new();
+        assert!(write_line(&mut out, &record).is_ok());
+    }
+
+    #[test]
+    fn joins_lines_split_between_reads() {
+        let mut lines = LineBuffer::new();
+        let mut out: heapless::String<64> = heapless::String::new();
+        let mut read = |bytes: &[u8]| {
+            for byte in bytes {
+                if let Some(line) = lines.push(*byte) {
+                    out.push_str(core::str::from_utf8(line).unwrap()).unwrap();
+                    out.push('|').unwrap();
+                }
+            }
+        };
+        read(b"t12320102");
+        read(b"\rC");
+        read(b"\r\r{\"id\":1}\n(0.100000) can0 1");
+        read(b"23#00\r\n");
+        assert_eq!(
+            out.as_str(),
+            "t12320102|C|{\"id\":1}|(0.100000) can0 123#00|"
+        );
+    }
+
+    #[test]
+    fn drops_overlong_lines() {
+        let mut lines = LineBuffer::new();
+        for _ in 0..INPUT_LEN + 1 {
+            assert_eq!(lines.push(b'x'), None);
+        }
+        assert_eq!(lines.push(b'\r'), None);
+        assert_eq!(lines.push(b'O'), None);
+        assert_eq!(lines.push(b'\r'), Some(&b"O"[..]));
+    }
+}
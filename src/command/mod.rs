@@ -0,0 +1,256 @@
+//! Request/response command protocol on the UART link.
+//!
+//! One JSON object per line, correlated by `id`:
+//!
+//! ```text
+//! > {"id":7,"cmd":"set_config","data":{"dod":{"max":90}}}
+//! < {"id":7,"ok":true}
+//! > {"id":8,"cmd":"get_status"}
+//! < {"id":8,"ok":true,"data":{"soc":55.0,...}}
+//! > {"id":9,"cmd":"get_cells","data":{"part":1}}
+//! < {"id":9,"ok":true,"data":{"seq":7,"kind":"mv","first":24,"total":96,"values":[...]}}
+//! > {"id":10,"cmd":"autobaud","data":{"bus":"Inverter","save":true}}
+//! < {"id":10,"ok":true,"data":{"bus":"Inverter","search_ms":10000}}
+//! < {"id":11,"ok":false,"error":"unknown_command"}
+//! ```
+//!
+//! Objects without a `cmd` key are not commands, the UART task treats them as bare
+//! config patches.
+
+use crate::candump::Bus;
+use crate::config::patch::Patch;
+use core::fmt::Write;
+use miniserde::__private::String;
+use miniserde::json::{self, Number, Value};
+use miniserde::Deserialize;
+
+#[derive(Debug)]
+pub enum Command {
+    GetConfig,
+    /// Merge-patch, see `Config::update_from_patch`
+    SetConfig(Patch),
+    GetStatus,
+    /// Chunk `part` of the per-cell snapshot, see `crate::mqtt::cells`
+    GetCells {
+        part: usize,
+    },
+    /// Reset the protection trip, contactor faults and a forced open
+    ClearFaults,
+    Reboot,
+    /// Open the contactor and keep it open until `ClearFaults`
+    OpenContactor,
+    /// Resend the Home Assistant discovery payloads
+    Discovery,
+    /// Search `bus` for its bitrate in silent mode, see `crate::autobaud`. `save`
+    /// writes the result into the config.
+    Autobaud {
+        bus: Bus,
+        save: bool,
+    },
+}
+
+#[derive(Debug)]
+pub struct Request {
+    pub id: u32,
+    pub command: Command,
+}
+
+/// Rejected request, `id` is echoed when it could be read
+#[derive(Debug, PartialEq)]
+pub struct RequestError {
+    pub id: Option<u32>,
+    pub code: &'static str,
+}
+
+/// Envelope keys, scalars only so a large `data` never builds a JSON tree
+#[derive(Deserialize)]
+struct Envelope {
+    id: Option<Value>,
+    cmd: Option<Value>,
+}
+
+/// Second pass over the line for the commands that take `data`
+#[derive(Deserialize)]
+struct WithData<T> {
+    data: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct CellsData {
+    part: u8,
+}
+
+#[derive(Deserialize)]
+struct AutobaudData {
+    bus: Bus,
+    save: Option<bool>,
+}
+
+/// Parse one line, `None` if it is not a command envelope
+pub fn parse(line: &str) -> Option<Result<Request, RequestError>> {
+    let Ok(Envelope { id, cmd }) = json::from_str::<Envelope>(line) else {
+        return None;
+    };
+    let cmd = cmd?;
+    let id = match id {
+        Some(Value::Number(Number::U64(id))) => u32::try_from(id).ok(),
+        _ => None,
+    };
+    let error = |code| Some(Err(RequestError { id, code }));
+    let Some(id) = id else {
+        return error("bad_id");
+    };
+    let Value::String(cmd) = cmd else {
+        return error("bad_request");
+    };
+    let command = match cmd.as_str() {
+        "get_config" => Command::GetConfig,
+        "set_config" => match data::<Patch>(line, "invalid_json") {
+            Ok(patch) => Command::SetConfig(patch),
+            Err(code) => return error(code),
+        },
+        "get_status" => Command::GetStatus,
+        "get_cells" => match json::from_str::<WithData<CellsData>>(line) {
+            Ok(WithData { data }) => Command::GetCells {
+                part: data.map_or(0, |data| data.part as usize),
+            },
+            Err(_) => return error("bad_request"),
+        },
+        "clear_faults" => Command::ClearFaults,
+        "reboot" => Command::Reboot,
+        "open_contactor" => Command::OpenContactor,
+        "discovery" => Command::Discovery,
+        "autobaud" => match data::<AutobaudData>(line, "bad_request") {
+            Ok(AutobaudData { bus, save }) => Command::Autobaud {
+                bus,
+                save: save.unwrap_or(false),
+            },
+            Err(code) => return error(code),
+        },
+        _ => return error("unknown_command"),
+    };
+    Some(Ok(Request { id, command }))
+}
+
+/// `data` of `line`, `invalid` is the error code when it does not deserialize as `T`
+fn data<T: Deserialize>(line: &str, invalid: &'static str) -> Result<T, &'static str> {
+    match json::from_str::<WithData<T>>(line) {
+        Ok(WithData { data: Some(data) }) => Ok(data),
+        Ok(WithData { data: None }) => Err("missing_data"),
+        Err(_) => Err(invalid),
+    }
+}
+
+/// `{"id":..,"ok":true}` with `data` spliced in verbatim if given
+pub fn ok(id: u32, data: Option<&str>) -> String {
+    let mut out = String::new();
+    let _ = write!(out, "{{\"id\":{},\"ok\":true", id);
+    if let Some(data) = data {
+        let _ = write!(out, ",\"data\":{}", data);
+    }
+    out.push('}');
+    out
+}
+
+/// `{"id":..,"ok":false,"error":..}` with the offending field if known
+pub fn error(id: Option<u32>, code: &str, field: Option<&str>) -> String {
+    let mut out = String::new();
+    let _ = match id {
+        Some(id) => write!(out, "{{\"id\":{}", id),
+        None => write!(out, "{{\"id\":null"),
+    };
+    let _ = write!(out, ",\"ok\":false,\"error\":{}", json::to_string(code));
+    if let Some(field) = field {
+        let _ = write!(out, ",\"field\":{}", json::to_string(field));
+    }
+    out.push('}');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        let request = parse("{\"id\":3,\"cmd\":\"get_status\"}").unwrap().unwrap();
+        assert_eq!(request.id, 3);
+        assert!(matches!(request.command, Command::GetStatus));
+
+        let request = parse("{\"id\":4,\"cmd\":\"set_config\",\"data\":{\"dod\":{\"max\":90}}}")
+            .unwrap()
+            .unwrap();
+        assert!(matches!(request.command, Command::SetConfig(_)));
+
+        let request = parse("{\"id\":5,\"cmd\":\"get_cells\",\"data\":{\"part\":2}}")
+            .unwrap()
+            .unwrap();
+        assert!(matches!(request.command, Command::GetCells { part: 2 }));
+        let request = parse("{\"id\":5,\"cmd\":\"get_cells\"}").unwrap().unwrap();
+        assert!(matches!(request.command, Command::GetCells { part: 0 }));
+
+        let request = parse("{\"id\":5,\"cmd\":\"autobaud\",\"data\":{\"bus\":\"Inverter\"}}")
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            request.command,
+            Command::Autobaud {
+                bus: Bus::Inverter,
+                save: false
+            }
+        ));
+    }
+
+    #[test]
+    fn bare_patches_are_not_commands() {
+        assert!(parse("{\"dod\":{\"max\":90}}").is_none());
+        assert!(parse("(0.1) can0 123#00").is_none());
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        let err = parse("{\"id\":5,\"cmd\":\"selfdestruct\"}").unwrap().unwrap_err();
+        assert_eq!(
+            err,
+            RequestError {
+                id: Some(5),
+                code: "unknown_command"
+            }
+        );
+        let err = parse("{\"cmd\":\"reboot\"}").unwrap().unwrap_err();
+        assert_eq!(
+            err,
+            RequestError {
+                id: None,
+                code: "bad_id"
+            }
+        );
+        let err = parse("{\"id\":6,\"cmd\":\"set_config\"}").unwrap().unwrap_err();
+        assert_eq!(err.code, "missing_data");
+        let err = parse("{\"id\":6,\"cmd\":\"set_config\",\"data\":{\"dod\":5}}")
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.code, "invalid_json");
+        let err = parse("{\"id\":7,\"cmd\":\"autobaud\",\"data\":{\"bus\":\"can1\"}}")
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.code, "bad_request");
+    }
+
+    #[test]
+    fn formats_responses() {
+        assert_eq!(ok(1, None), "{\"id\":1,\"ok\":true}");
+        assert_eq!(
+            ok(2, Some("{\"a\":1}")),
+            "{\"id\":2,\"ok\":true,\"data\":{\"a\":1}}"
+        );
+        assert_eq!(
+            error(Some(3), "out_of_range", Some("dod")),
+            "{\"id\":3,\"ok\":false,\"error\":\"out_of_range\",\"field\":\"dod\"}"
+        );
+        assert_eq!(
+            error(None, "bad_id", None),
+            "{\"id\":null,\"ok\":false,\"error\":\"bad_id\"}"
+        );
+    }
+}
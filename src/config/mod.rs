@@ -0,0 +1,246 @@
+use crate::candump::Bus;
+use crate::errors::StmError;
+use crate::timing::{BITRATES, DEFAULT_BITRATE};
+use miniserde::__private::String;
+use miniserde::{json, Deserialize, Serialize};
+
+pub mod patch;
+pub mod record;
+
+#[derive(Serialize, Deserialize, Debug, Clone)] // references only
+pub struct Config {
+    pack_volts: MinMax<u16>,
+    cell_millivolts: MinMax<u16>,
+    pack_temperature: MinMax<i16>,
+    cell_temperature: MinMax<i16>,
+    current_amps: MinMax<i16>,
+    pub dod: MinMax<u8>,
+    timeout_secs: u8,
+    mqtt_rate_secs: u32,
+    cells_rate_secs: u32,
+    stats_rate_secs: u32,
+    state: State,
+    battery_type: BatteryType,
+    inverter_type: InverterType,
+    candump: bool,
+    slcan_bus: Bus,
+    bus_off_backoff_ms: MinMax<u16>,
+    bms_bitrate_kbps: u16,
+    inverter_bitrate_kbps: u16,
+}
+
+impl Config {
+    pub fn dump_to_json(&self) -> String {
+        json::to_string(&self)
+    }
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn set_state(&mut self, state: State) {
+        self.state = state;
+    }
+
+    pub fn pack_volts(&self) -> &MinMax<u16> {
+        &self.pack_volts
+    }
+
+    pub fn cell_millivolts(&self) -> &MinMax<u16> {
+        &self.cell_millivolts
+    }
+
+    pub fn pack_temperature(&self) -> &MinMax<i16> {
+        &self.pack_temperature
+    }
+
+    pub fn cell_temperature(&self) -> &MinMax<i16> {
+        &self.cell_temperature
+    }
+
+    pub fn current_amps(&self) -> &MinMax<i16> {
+        &self.current_amps
+    }
+
+    /// Battery protocol spawned at boot
+    pub fn battery_type(&self) -> BatteryType {
+        self.battery_type
+    }
+
+    /// Inverter protocol spawned at boot
+    pub fn inverter_type(&self) -> InverterType {
+        self.inverter_type
+    }
+
+    /// Configured BMS timeout, seconds
+    pub fn timeout_secs(&self) -> u8 {
+        self.timeout_secs
+    }
+
+    /// Seconds between MQTT summary messages
+    pub fn mqtt_rate_secs(&self) -> u32 {
+        self.mqtt_rate_secs
+    }
+
+    /// Seconds between MQTT cell data messages
+    pub fn cells_rate_secs(&self) -> u32 {
+        self.cells_rate_secs
+    }
+
+    /// Seconds covered by each MQTT statistics record
+    pub fn stats_rate_secs(&self) -> u32 {
+        self.stats_rate_secs
+    }
+
+    /// Stream every CAN frame to the UART in `candump -L` format
+    pub fn candump(&self) -> bool {
+        self.candump
+    }
+
+    /// Bus bridged to the UART in SLCAN mode
+    pub fn slcan_bus(&self) -> Bus {
+        self.slcan_bus
+    }
+
+    /// Wait before the first bus-off recovery, doubling per consecutive bus-off up to max
+    pub fn bus_off_backoff_ms(&self) -> &MinMax<u16> {
+        &self.bus_off_backoff_ms
+    }
+
+    /// Bitrate `bus` is started at, bit/s
+    pub fn bitrate(&self, bus: Bus) -> u32 {
+        let kbps = match bus {
+            Bus::Bms => self.bms_bitrate_kbps,
+            Bus::Inverter => self.inverter_bitrate_kbps,
+        };
+        kbps as u32 * 1000
+    }
+
+    /// Store a detected bitrate, bit/s, one of `timing::BITRATES`
+    pub fn set_bitrate(&mut self, bus: Bus, bitrate: u32) -> Result<(), StmError> {
+        if !BITRATES.contains(&bitrate) {
+            return Err(StmError::OutOfRange(match bus {
+                Bus::Bms => "bms_bitrate_kbps",
+                Bus::Inverter => "inverter_bitrate_kbps",
+            }));
+        }
+        let kbps = (bitrate / 1000) as u16;
+        match bus {
+            Bus::Bms => self.bms_bitrate_kbps = kbps,
+            Bus::Inverter => self.inverter_bitrate_kbps = kbps,
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            pack_volts: MinMax::int(300, 400),
+            cell_millivolts: MinMax::int(3000, 4200),
+            pack_temperature: MinMax::signed(-20, 50),
+            cell_temperature: MinMax::signed(-20, 50),
+            current_amps: MinMax::signed(-50, 50),
+            dod: MinMax::int(0, 99),
+            timeout_secs: 60,
+            mqtt_rate_secs: 10,
+            cells_rate_secs: 60,
+            stats_rate_secs: 3600,
+            state: State::Offline,
+            battery_type: BatteryType::default(),
+            inverter_type: InverterType::default(),
+            candump: false,
+            slcan_bus: Bus::Bms,
+            bus_off_backoff_ms: MinMax::int(100, 10_000),
+            bms_bitrate_kbps: (DEFAULT_BITRATE / 1000) as u16,
+            inverter_bitrate_kbps: (DEFAULT_BITRATE / 1000) as u16,
+        }
+    }
+}
+
+/*
+
+{"pack_volts":{"min":300,"max":400},"cell_millivolts":{"min":3000,"max":4200},"pack_temperature":{"min":-20,"max":50},"cell_temperature":{"min":-20,"max":50},"current_amps":{"min":-50,"max":50},"dod":{"min":0,"max":99},"timeout_secs":60,"mqtt_rate_secs":10,"cells_rate_secs":60,"stats_rate_secs":3600,"state":"Offline","battery_type":"Ze50","inverter_type":"Byd","candump":false,"slcan_bus":"Bms","bus_off_backoff_ms":{"min":100,"max":10000},"bms_bitrate_kbps":500,"inverter_bitrate_kbps":500}
+{"pack_volts":{"min":300,"max":400}}   merge-patch, only pack_volts changes
+*/
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct MinMax<T> {
+    min: T,
+    max: T,
+}
+
+impl<T: Copy> MinMax<T> {
+    fn int(min: T, max: T) -> Self {
+        Self {
+            min: min as T,
+            max: max as T,
+        }
+    }
+    fn signed(min: T, max: T) -> Self {
+        Self {
+            min: min as T,
+            max: max as T,
+        }
+    }
+
+    pub fn min(&self) -> T {
+        self.min
+    }
+
+    pub fn max(&self) -> T {
+        self.max
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum State {
+    Online,
+    InvFault,
+    BmsFault,
+    #[default]
+    Offline,
+}
+
+/// Battery protocols selectable at runtime, each needs its cargo feature compiled in
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum BatteryType {
+    #[default]
+    Ze50,
+    Kangoo,
+}
+
+/// Inverter protocols selectable at runtime, each needs its cargo feature compiled in
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum InverterType {
+    #[default]
+    Byd,
+    Pylontech,
+    Solax,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_round_trip() {
+        let mut config = Config::default();
+        config.set_state(State::Online);
+        config.set_bitrate(Bus::Inverter, 250_000).unwrap();
+        let json = config.dump_to_json();
+        let mut parsed = Config::default();
+        parsed.update_from_json(json.as_bytes()).unwrap();
+        // the runtime state is not part of a patch
+        assert!(matches!(parsed.state(), State::Offline));
+        assert_eq!(parsed.bitrate(Bus::Inverter), 250_000);
+        assert_eq!(parsed.pack_volts().max(), 400);
+        assert_eq!(parsed.battery_type(), BatteryType::Ze50);
+    }
+
+    #[test]
+    fn rejects_bad_documents() {
+        let mut config = Config::default();
+        assert!(config.update_from_json(b"{\"dod\":").is_err());
+        assert!(config.update_from_json(&[0xff, 0xfe]).is_err());
+    }
+}
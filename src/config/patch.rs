@@ -0,0 +1,267 @@
+//! JSON merge-patch for `Config`: only the fields present in the document change.
+//!
+//! `{"pack_volts":{"max":410}}` leaves `pack_volts.min` and every other field alone.
+//! The document is deserialized straight into `Patch` rather than a JSON tree, so a
+//! full config dump fits the firmware heap; unknown keys are ignored. The patched
+//! copy is validated as a whole and only then replaces the live config, so a rejected
+//! patch changes nothing.
+//!
+//! `state` is not patchable, it only leaves a fault through `Gateway::clear_faults`.
+
+use super::{BatteryType, Config, InverterType, MinMax};
+use crate::candump::Bus;
+use crate::errors::StmError;
+use crate::timing::BITRATES;
+use miniserde::__private::String;
+use miniserde::{json, Deserialize, Serialize};
+
+/// Config fields a merge-patch may change, `None` where the document has no key
+#[derive(Deserialize, Debug, Default)]
+pub struct Patch {
+    pack_volts: Option<Bounds<u16>>,
+    cell_millivolts: Option<Bounds<u16>>,
+    pack_temperature: Option<Bounds<i16>>,
+    cell_temperature: Option<Bounds<i16>>,
+    current_amps: Option<Bounds<i16>>,
+    dod: Option<Bounds<u8>>,
+    timeout_secs: Option<u8>,
+    mqtt_rate_secs: Option<u32>,
+    cells_rate_secs: Option<u32>,
+    stats_rate_secs: Option<u32>,
+    battery_type: Option<BatteryType>,
+    inverter_type: Option<InverterType>,
+    candump: Option<bool>,
+    slcan_bus: Option<Bus>,
+    bus_off_backoff_ms: Option<Bounds<u16>>,
+    bms_bitrate_kbps: Option<u16>,
+    inverter_bitrate_kbps: Option<u16>,
+}
+
+/// Either end of a `MinMax`
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+struct Bounds<T> {
+    min: Option<T>,
+    max: Option<T>,
+}
+
+impl Patch {
+    pub fn from_json(text: &str) -> Result<Self, StmError> {
+        json::from_str(text).map_err(|_e| StmError::InvalidConfigData)
+    }
+
+    /// Patch setting both ends of the DoD range
+    pub fn dod(min: u8, max: u8) -> Self {
+        Self {
+            dod: Some(Bounds {
+                min: Some(min),
+                max: Some(max),
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn apply(&self, config: &mut Config) {
+        merge(&mut config.pack_volts, self.pack_volts);
+        merge(&mut config.cell_millivolts, self.cell_millivolts);
+        merge(&mut config.pack_temperature, self.pack_temperature);
+        merge(&mut config.cell_temperature, self.cell_temperature);
+        merge(&mut config.current_amps, self.current_amps);
+        merge(&mut config.dod, self.dod);
+        set(&mut config.timeout_secs, self.timeout_secs);
+        set(&mut config.mqtt_rate_secs, self.mqtt_rate_secs);
+        set(&mut config.cells_rate_secs, self.cells_rate_secs);
+        set(&mut config.stats_rate_secs, self.stats_rate_secs);
+        set(&mut config.battery_type, self.battery_type);
+        set(&mut config.inverter_type, self.inverter_type);
+        set(&mut config.candump, self.candump);
+        set(&mut config.slcan_bus, self.slcan_bus);
+        merge(&mut config.bus_off_backoff_ms, self.bus_off_backoff_ms);
+        set(&mut config.bms_bitrate_kbps, self.bms_bitrate_kbps);
+        set(
+            &mut config.inverter_bitrate_kbps,
+            self.inverter_bitrate_kbps,
+        );
+    }
+}
+
+impl Config {
+    /// Apply a JSON merge-patch, validating the result before committing it
+    pub fn update_from_json(&mut self, slice: &[u8]) -> Result<(), StmError> {
+        let text = core::str::from_utf8(slice).map_err(|_e| StmError::InvalidConfigData)?;
+        self.update_from_patch(&Patch::from_json(text)?)
+    }
+
+    /// Apply an already parsed merge-patch
+    pub fn update_from_patch(&mut self, patch: &Patch) -> Result<(), StmError> {
+        let mut patched = self.clone();
+        patch.apply(&mut patched);
+        patched.validate()?;
+        *self = patched;
+        Ok(())
+    }
+
+    /// Check every range is ordered and inside its physical bounds
+    pub fn validate(&self) -> Result<(), StmError> {
+        bounds("pack_volts", &self.pack_volts, 0, 1000)?;
+        bounds("cell_millivolts", &self.cell_millivolts, 1000, 5000)?;
+        bounds("pack_temperature", &self.pack_temperature, -40, 100)?;
+        bounds("cell_temperature", &self.cell_temperature, -40, 100)?;
+        bounds("current_amps", &self.current_amps, -1000, 1000)?;
+        bounds("dod", &self.dod, 0, 100)?;
+        bounds("bus_off_backoff_ms", &self.bus_off_backoff_ms, 10, 60_000)?;
+        for (field, kbps) in [
+            ("bms_bitrate_kbps", self.bms_bitrate_kbps),
+            ("inverter_bitrate_kbps", self.inverter_bitrate_kbps),
+        ] {
+            if !BITRATES.contains(&(kbps as u32 * 1000)) {
+                return Err(StmError::OutOfRange(field));
+            }
+        }
+        if self.timeout_secs == 0 {
+            return Err(StmError::OutOfRange("timeout_secs"));
+        }
+        if !(1..=3600).contains(&self.mqtt_rate_secs) {
+            return Err(StmError::OutOfRange("mqtt_rate_secs"));
+        }
+        if !(1..=3600).contains(&self.cells_rate_secs) {
+            return Err(StmError::OutOfRange("cells_rate_secs"));
+        }
+        if !(60..=86400).contains(&self.stats_rate_secs) {
+            return Err(StmError::OutOfRange("stats_rate_secs"));
+        }
+        Ok(())
+    }
+}
+
+/// UART reply to a config update, `{"ok":true}` or the error code and field
+#[derive(Serialize)]
+pub struct Ack {
+    ok: bool,
+    error: Option<String>,
+    field: Option<String>,
+}
+
+impl Ack {
+    pub fn new(result: &Result<(), StmError>) -> Self {
+        match result {
+            Ok(()) => Self {
+                ok: true,
+                error: None,
+                field: None,
+            },
+            Err(e) => Self {
+                ok: false,
+                error: Some(e.code().into()),
+                field: e.field().map(Into::into),
+            },
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        json::to_string(self)
+    }
+}
+
+fn set<T: Copy>(field: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *field = value;
+    }
+}
+
+fn merge<T: Copy>(range: &mut MinMax<T>, bounds: Option<Bounds<T>>) {
+    if let Some(bounds) = bounds {
+        set(&mut range.min, bounds.min);
+        set(&mut range.max, bounds.max);
+    }
+}
+
+fn bounds<T: PartialOrd>(
+    field: &'static str,
+    range: &MinMax<T>,
+    low: T,
+    high: T,
+) -> Result<(), StmError> {
+    if range.min < low || range.max > high {
+        return Err(StmError::OutOfRange(field));
+    }
+    if range.min >= range.max {
+        return Err(StmError::MinNotBelowMax(field));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::State;
+
+    #[test]
+    fn patch_changes_only_supplied_fields() {
+        let mut config = Config::default();
+        config
+            .update_from_json(b"{\"pack_volts\":{\"max\":410},\"candump\":true}")
+            .unwrap();
+        assert_eq!(config.pack_volts().min(), 300);
+        assert_eq!(config.pack_volts().max(), 410);
+        assert!(config.candump());
+        assert_eq!(config.cell_millivolts().max(), 4200);
+    }
+
+    #[test]
+    fn bitrates_are_standard_rates() {
+        let mut config = Config::default();
+        config
+            .update_from_json(b"{\"inverter_bitrate_kbps\":250}")
+            .unwrap();
+        assert_eq!(config.bitrate(Bus::Inverter), 250_000);
+        assert_eq!(config.bitrate(Bus::Bms), 500_000);
+        let result = config.update_from_json(b"{\"bms_bitrate_kbps\":333}");
+        assert!(matches!(result, Err(StmError::OutOfRange("bms_bitrate_kbps"))));
+    }
+
+    #[test]
+    fn rejected_patch_changes_nothing() {
+        let mut config = Config::default();
+        let result = config.update_from_json(b"{\"dod\":{\"max\":50},\"pack_volts\":{\"min\":500}}");
+        assert!(matches!(result, Err(StmError::MinNotBelowMax("pack_volts"))));
+        assert_eq!(config.dod.max(), 99);
+        assert_eq!(config.pack_volts().min(), 300);
+    }
+
+    #[test]
+    fn errors_name_the_field() {
+        let mut config = Config::default();
+        let result = config.update_from_json(b"{\"cell_temperature\":{\"max\":150}}");
+        assert!(matches!(result, Err(StmError::OutOfRange("cell_temperature"))));
+        assert_eq!(
+            Ack::new(&result).to_json(),
+            "{\"ok\":false,\"error\":\"out_of_range\",\"field\":\"cell_temperature\"}"
+        );
+        assert_eq!(Ack::new(&Ok(())).to_json(), "{\"ok\":true,\"error\":null,\"field\":null}");
+    }
+
+    #[test]
+    fn malformed_values_are_rejected() {
+        let mut config = Config::default();
+        for patch in [
+            &b"{\"dod\":{\"min\":-1}}"[..],
+            b"{\"timeout_secs\":\"60\"}",
+            b"{\"battery_type\":\"Leaf\"}",
+            b"[1]",
+        ] {
+            let result = config.update_from_json(patch);
+            assert!(matches!(result, Err(StmError::InvalidConfigData)));
+        }
+        assert_eq!(config.dod.min(), 0);
+    }
+
+    #[test]
+    fn state_is_not_patchable() {
+        let mut config = Config::default();
+        config.set_state(State::BmsFault);
+        config
+            .update_from_json(b"{\"state\":\"Online\",\"packvolts\":1}")
+            .unwrap();
+        assert_eq!(*config.state(), State::BmsFault);
+    }
+}
@@ -0,0 +1,243 @@
+//! Binary `Config` layout for the flash record store, with migration from older schemas.
+//!
+//! Little-endian, fields in declaration order. `state` is runtime only and not stored.
+//!
+//! * v1: limits, `dod`, `timeout_secs`, `mqtt_rate_secs`
+//! * v2: v1 + `battery_type`, `inverter_type`, `candump`
+//! * v3: v2 + `cells_rate_secs`, `stats_rate_secs`
+//! * v4: v3 + `slcan_bus`
+//! * v5: v4 + `bus_off_backoff_ms`
+//! * v6: v5 + `bms_bitrate_kbps`, `inverter_bitrate_kbps`
+//!
+//! The binary UART transport sends this record as is, bump `gateway_wire`'s
+//! `CONFIG_VERSION` and layout along with `VERSION`.
+
+use super::{BatteryType, Config, InverterType, MinMax};
+use crate::candump::Bus;
+use crate::errors::StmError;
+use crate::fmt::Debug2Format;
+use crate::storage::{Payload, SlotStore, MAX_PAYLOAD};
+use embedded_storage::nor_flash::NorFlash;
+
+/// Schema written by `to_record`
+pub const VERSION: u8 = 6;
+/// Bytes written by `to_record`, a new field has to fit the storage slot
+pub const LEN: usize = 47;
+const _: () = assert!(LEN <= MAX_PAYLOAD, "config record exceeds a slot");
+
+impl Config {
+    pub fn to_record(&self) -> Payload {
+        let mut out = Payload::new();
+        let mut put = |bytes: &[u8]| {
+            out.extend_from_slice(bytes)
+                .expect("config record longer than MAX_PAYLOAD");
+        };
+        for limit in [&self.pack_volts, &self.cell_millivolts] {
+            put(&limit.min.to_le_bytes());
+            put(&limit.max.to_le_bytes());
+        }
+        for limit in [
+            &self.pack_temperature,
+            &self.cell_temperature,
+            &self.current_amps,
+        ] {
+            put(&limit.min.to_le_bytes());
+            put(&limit.max.to_le_bytes());
+        }
+        put(&[self.dod.min, self.dod.max, self.timeout_secs]);
+        put(&self.mqtt_rate_secs.to_le_bytes());
+        put(&[
+            self.battery_type as u8,
+            self.inverter_type as u8,
+            self.candump as u8,
+        ]);
+        put(&self.cells_rate_secs.to_le_bytes());
+        put(&self.stats_rate_secs.to_le_bytes());
+        put(&[self.slcan_bus as u8]);
+        put(&self.bus_off_backoff_ms.min.to_le_bytes());
+        put(&self.bus_off_backoff_ms.max.to_le_bytes());
+        put(&self.bms_bitrate_kbps.to_le_bytes());
+        put(&self.inverter_bitrate_kbps.to_le_bytes());
+        debug_assert_eq!(out.len(), LEN, "update record::LEN with the layout");
+        out
+    }
+
+    /// Decode a record of any known schema version, filling newer fields with defaults
+    pub fn from_record(version: u8, bytes: &[u8]) -> Result<Self, StmError> {
+        let mut reader = Reader(bytes);
+        let mut config = Config::default();
+        if !(1..=VERSION).contains(&version) {
+            return Err(StmError::InvalidStoredConfig);
+        }
+        config.pack_volts = MinMax::int(reader.u16()?, reader.u16()?);
+        config.cell_millivolts = MinMax::int(reader.u16()?, reader.u16()?);
+        config.pack_temperature = MinMax::signed(reader.i16()?, reader.i16()?);
+        config.cell_temperature = MinMax::signed(reader.i16()?, reader.i16()?);
+        config.current_amps = MinMax::signed(reader.i16()?, reader.i16()?);
+        config.dod = MinMax::int(reader.u8()?, reader.u8()?);
+        config.timeout_secs = reader.u8()?;
+        config.mqtt_rate_secs = reader.u32()?;
+        if version >= 2 {
+            config.battery_type = match reader.u8()? {
+                0 => BatteryType::Ze50,
+                1 => BatteryType::Kangoo,
+                _ => return Err(StmError::InvalidStoredConfig),
+            };
+            config.inverter_type = match reader.u8()? {
+                0 => InverterType::Byd,
+                1 => InverterType::Pylontech,
+                2 => InverterType::Solax,
+                _ => return Err(StmError::InvalidStoredConfig),
+            };
+            config.candump = reader.u8()? != 0;
+        }
+        if version >= 3 {
+            config.cells_rate_secs = reader.u32()?;
+            config.stats_rate_secs = reader.u32()?;
+        }
+        if version >= 4 {
+            config.slcan_bus = match reader.u8()? {
+                0 => Bus::Bms,
+                1 => Bus::Inverter,
+                _ => return Err(StmError::InvalidStoredConfig),
+            };
+        }
+        if version >= 5 {
+            config.bus_off_backoff_ms = MinMax::int(reader.u16()?, reader.u16()?);
+        }
+        if version >= 6 {
+            config.bms_bitrate_kbps = reader.u16()?;
+            config.inverter_bitrate_kbps = reader.u16()?;
+        }
+        Ok(config)
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], StmError> {
+        if self.0.len() < N {
+            return Err(StmError::InvalidStoredConfig);
+        }
+        let (head, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(head.try_into().unwrap())
+    }
+    fn u8(&mut self) -> Result<u8, StmError> {
+        Ok(self.take::<1>()?[0])
+    }
+    fn u16(&mut self) -> Result<u16, StmError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+    fn i16(&mut self) -> Result<i16, StmError> {
+        Ok(i16::from_le_bytes(self.take()?))
+    }
+    fn u32(&mut self) -> Result<u32, StmError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+}
+
+/// Load the stored config, rewriting it in the current schema if it was older.
+/// Falls back to defaults only when no valid record exists.
+pub fn load<F: NorFlash>(store: &mut SlotStore<F>) -> Config {
+    let record = match store.load() {
+        Ok(Some(record)) => record,
+        Ok(None) => {
+            warn!("No stored config, using defaults");
+            return Config::default();
+        }
+        Err(e) => {
+            error!("Config flash read failed: {}", Debug2Format(&e));
+            return Config::default();
+        }
+    };
+    let config = match Config::from_record(record.version, &record.payload) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Stored config v{} rejected: {}", record.version, Debug2Format(&e));
+            return Config::default();
+        }
+    };
+    if record.version < VERSION {
+        info!("Migrating stored config v{} to v{}", record.version, VERSION);
+        save(store, &config);
+    }
+    config
+}
+
+/// Write `config` as a new record
+pub fn save<F: NorFlash>(store: &mut SlotStore<F>, config: &Config) {
+    match store.save(VERSION, &config.to_record()) {
+        Ok(()) => info!("Config saved to flash"),
+        Err(e) => error!("Config flash write failed: {}", Debug2Format(&e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::ram::RamFlash;
+
+    #[test]
+    fn round_trip() {
+        let mut config = Config::default();
+        config.dod = MinMax::int(10, 90);
+        config.current_amps = MinMax::signed(-120, 80);
+        config.inverter_type = InverterType::Solax;
+        config.candump = true;
+        let parsed = Config::from_record(VERSION, &config.to_record()).unwrap();
+        assert_eq!(parsed.dump_to_json(), config.dump_to_json());
+    }
+
+    #[test]
+    fn record_fits_a_storage_slot() {
+        let record = Config::default().to_record();
+        assert_eq!(record.len(), LEN);
+        assert!(record.len() <= MAX_PAYLOAD);
+        // the wire layout is the version byte and the record
+        assert_eq!(gateway_wire::Config::LEN, LEN + 1);
+    }
+
+    #[test]
+    fn binary_transport_decodes_the_record() {
+        let mut config = Config::default();
+        config.pack_temperature = MinMax::signed(-10, 45);
+        config.slcan_bus = Bus::Inverter;
+        config.inverter_bitrate_kbps = 250;
+        let mut payload = std::vec![VERSION];
+        payload.extend_from_slice(&config.to_record());
+        let wire = gateway_wire::Config::decode(&payload).unwrap();
+        assert_eq!(wire.pack_temperature, [-10, 45]);
+        assert_eq!(wire.stats_rate_secs, 3600);
+        assert_eq!(wire.slcan_bus, 1);
+        assert_eq!(wire.bus_off_backoff_ms, [100, 10_000]);
+        assert_eq!((wire.bms_bitrate_kbps, wire.inverter_bitrate_kbps), (500, 250));
+    }
+
+    #[test]
+    fn migrates_v1_and_rewrites() {
+        let mut v1 = Config::default();
+        v1.pack_volts = MinMax::int(320, 410);
+        // v1 stops before the protocol, candump, rate, SLCAN, backoff and bitrate fields
+        let record = v1.to_record();
+        let v1_bytes = &record[..record.len() - 20];
+
+        let mut store = SlotStore::new(RamFlash::new(), 0, 2);
+        store.load().unwrap();
+        store.save(1, v1_bytes).unwrap();
+
+        let config = load(&mut store);
+        assert_eq!(config.pack_volts().max(), 410);
+        assert_eq!(config.battery_type(), BatteryType::default());
+        assert_eq!(store.load().unwrap().unwrap().version, VERSION);
+    }
+
+    #[test]
+    fn invalid_record_falls_back_to_defaults() {
+        assert!(Config::from_record(VERSION, &[1, 2, 3]).is_err());
+        assert!(Config::from_record(VERSION + 1, &Config::default().to_record()).is_err());
+        let mut store = SlotStore::new(RamFlash::new(), 0, 2);
+        assert_eq!(load(&mut store).dump_to_json(), Config::default().dump_to_json());
+    }
+}
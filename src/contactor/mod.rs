@@ -0,0 +1,274 @@
+//! Contactor state machine: precharge, pull-in, economised hold, current limit
+//! ramp-down before opening, and weld detection after opening.
+//!
+//! Pure logic, `step` is called periodically with the live readings and returns the
+//! relay/PWM drive. Faults are latched until a `Command::Reset`.
+
+use miniserde::Serialize;
+
+/// Give up on precharge after this long
+pub const PRECHARGE_TIMEOUT_MS: u64 = 5000;
+/// Precharge time when the inverter does not report its DC-link voltage
+pub const PRECHARGE_FALLBACK_MS: u64 = 2000;
+/// DC-link must reach this fraction of pack voltage before closing
+pub const PRECHARGE_COMPLETE_RATIO: f32 = 0.95;
+/// Full duty on the main contactor coil before dropping to hold
+pub const PULL_IN_MS: u64 = 100;
+/// Current limits are ramped to zero over this time before opening
+pub const RAMP_DOWN_MS: u64 = 2000;
+/// Below this the contactor can open without waiting for the ramp
+pub const OPEN_CURRENT_A: f32 = 2.0;
+/// Time after opening before checking for a weld
+pub const WELD_CHECK_MS: u64 = 500;
+/// Current still flowing after opening means a welded contactor
+pub const WELD_CURRENT_A: f32 = 2.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// Close via precharge
+    Close,
+    /// Ramp the current limits down, then open
+    Open,
+    /// Open immediately, e.g. on a protection trip
+    ForceOpen,
+    /// Clear a latched fault
+    Reset,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub enum ContactorState {
+    #[default]
+    Open,
+    Precharging,
+    PullIn,
+    Closed,
+    RampDown,
+    Opening,
+    /// Latched: DC-link never reached pack voltage
+    PrechargeFailed,
+    /// Latched: current kept flowing after opening
+    Welded,
+}
+
+impl ContactorState {
+    pub fn is_fault(self) -> bool {
+        matches!(self, ContactorState::PrechargeFailed | ContactorState::Welded)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Drive {
+    Off,
+    /// 100% duty
+    PullIn,
+    /// Economised duty
+    Hold,
+}
+
+/// Live readings used by `step`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Inputs {
+    pub pack_volts: f32,
+    /// Inverter side voltage, `None` if the inverter protocol does not report it
+    pub dc_link_volts: Option<f32>,
+    pub current: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Outputs {
+    pub precharge: bool,
+    pub main: Drive,
+    /// Scale applied to the charge/discharge limits sent to the inverter, 0..=1
+    pub limit_scale: f32,
+}
+
+#[derive(Default)]
+pub struct Contactor {
+    state: ContactorState,
+    since_ms: u64,
+}
+
+impl Contactor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn state(&self) -> ContactorState {
+        self.state
+    }
+
+    fn enter(&mut self, state: ContactorState, now_ms: u64) {
+        self.state = state;
+        self.since_ms = now_ms;
+    }
+
+    pub fn command(&mut self, command: Command, now_ms: u64) {
+        use ContactorState::*;
+        match (command, self.state) {
+            (Command::Close, Open) => self.enter(Precharging, now_ms),
+            (Command::Close, RampDown) => self.enter(Closed, now_ms),
+            (Command::Open, Precharging) => self.enter(Open, now_ms),
+            (Command::Open, PullIn | Closed) => self.enter(RampDown, now_ms),
+            (Command::ForceOpen, Precharging) => self.enter(Open, now_ms),
+            (Command::ForceOpen, PullIn | Closed | RampDown) => self.enter(Opening, now_ms),
+            (Command::Reset, PrechargeFailed | Welded) => self.enter(Open, now_ms),
+            _ => (),
+        }
+    }
+
+    pub fn step(&mut self, inputs: &Inputs, now_ms: u64) -> Outputs {
+        use ContactorState::*;
+        let elapsed = now_ms.saturating_sub(self.since_ms);
+        match self.state {
+            Precharging => {
+                let complete = match inputs.dc_link_volts {
+                    Some(volts) => {
+                        inputs.pack_volts > 0.0
+                            && volts >= inputs.pack_volts * PRECHARGE_COMPLETE_RATIO
+                    }
+                    None => elapsed >= PRECHARGE_FALLBACK_MS,
+                };
+                if complete {
+                    self.enter(PullIn, now_ms);
+                } else if elapsed >= PRECHARGE_TIMEOUT_MS {
+                    self.enter(PrechargeFailed, now_ms);
+                }
+            }
+            PullIn if elapsed >= PULL_IN_MS => self.enter(Closed, now_ms),
+            RampDown if elapsed >= RAMP_DOWN_MS || inputs.current.abs() < OPEN_CURRENT_A => {
+                self.enter(Opening, now_ms)
+            }
+            Opening if elapsed >= WELD_CHECK_MS => match inputs.current.abs() > WELD_CURRENT_A {
+                true => self.enter(Welded, now_ms),
+                false => self.enter(Open, now_ms),
+            },
+            _ => (),
+        }
+        self.outputs(now_ms)
+    }
+
+    fn outputs(&self, now_ms: u64) -> Outputs {
+        use ContactorState::*;
+        let (precharge, main, limit_scale) = match self.state {
+            Precharging => (true, Drive::Off, 0.0),
+            PullIn => (true, Drive::PullIn, 0.0),
+            Closed => (false, Drive::Hold, 1.0),
+            RampDown => {
+                let elapsed = now_ms.saturating_sub(self.since_ms) as f32;
+                let scale = 1.0 - elapsed / RAMP_DOWN_MS as f32;
+                (false, Drive::Hold, scale.clamp(0.0, 1.0))
+            }
+            Open | Opening | PrechargeFailed | Welded => (false, Drive::Off, 0.0),
+        };
+        Outputs {
+            precharge,
+            main,
+            limit_scale,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ContactorState::*;
+
+    const PACK: Inputs = Inputs {
+        pack_volts: 380.0,
+        dc_link_volts: Some(0.0),
+        current: 0.0,
+    };
+
+    fn closed() -> Contactor {
+        let mut contactor = Contactor::new();
+        contactor.command(Command::Close, 0);
+        let charged = Inputs {
+            dc_link_volts: Some(370.0),
+            ..PACK
+        };
+        contactor.step(&charged, 500);
+        contactor.step(&charged, 600);
+        assert_eq!(contactor.state(), Closed);
+        contactor
+    }
+
+    #[test]
+    fn precharges_before_closing() {
+        let mut contactor = Contactor::new();
+        contactor.command(Command::Close, 0);
+        let out = contactor.step(&PACK, 100);
+        assert_eq!(contactor.state(), Precharging);
+        assert_eq!((out.precharge, out.main), (true, Drive::Off));
+
+        let charged = Inputs {
+            dc_link_volts: Some(365.0),
+            ..PACK
+        };
+        let out = contactor.step(&charged, 1000);
+        assert_eq!(contactor.state(), PullIn);
+        assert_eq!((out.precharge, out.main), (true, Drive::PullIn));
+
+        let out = contactor.step(&charged, 1100);
+        assert_eq!(contactor.state(), Closed);
+        assert_eq!((out.precharge, out.main, out.limit_scale), (false, Drive::Hold, 1.0));
+    }
+
+    #[test]
+    fn precharge_timeout_latches() {
+        let mut contactor = Contactor::new();
+        contactor.command(Command::Close, 0);
+        contactor.step(&PACK, PRECHARGE_TIMEOUT_MS);
+        assert_eq!(contactor.state(), PrechargeFailed);
+        contactor.command(Command::Close, 6000);
+        assert_eq!(contactor.step(&PACK, 6000).main, Drive::Off);
+        contactor.command(Command::Reset, 7000);
+        assert_eq!(contactor.state(), Open);
+    }
+
+    #[test]
+    fn timed_precharge_without_dc_link() {
+        let mut contactor = Contactor::new();
+        contactor.command(Command::Close, 0);
+        let unknown = Inputs {
+            dc_link_volts: None,
+            ..PACK
+        };
+        contactor.step(&unknown, PRECHARGE_FALLBACK_MS - 1);
+        assert_eq!(contactor.state(), Precharging);
+        contactor.step(&unknown, PRECHARGE_FALLBACK_MS);
+        assert_eq!(contactor.state(), PullIn);
+    }
+
+    #[test]
+    fn ramps_limits_down_before_opening() {
+        let mut contactor = closed();
+        let loaded = Inputs {
+            current: 30.0,
+            ..PACK
+        };
+        contactor.command(Command::Open, 1000);
+        let out = contactor.step(&loaded, 1500);
+        assert_eq!(contactor.state(), RampDown);
+        assert_eq!(out.limit_scale, 0.75);
+        assert_eq!(out.main, Drive::Hold);
+
+        contactor.step(&loaded, 1000 + RAMP_DOWN_MS);
+        assert_eq!(contactor.state(), Opening);
+        contactor.step(&PACK, 1000 + RAMP_DOWN_MS + WELD_CHECK_MS);
+        assert_eq!(contactor.state(), Open);
+    }
+
+    #[test]
+    fn detects_weld() {
+        let mut contactor = closed();
+        let stuck = Inputs {
+            current: 15.0,
+            ..PACK
+        };
+        contactor.command(Command::ForceOpen, 1000);
+        assert_eq!(contactor.step(&stuck, 1000).main, Drive::Off);
+        contactor.step(&stuck, 1000 + WELD_CHECK_MS);
+        assert_eq!(contactor.state(), Welded);
+        assert!(contactor.state().is_fault());
+    }
+}
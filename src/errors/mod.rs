@@ -0,0 +1,41 @@
+#[derive(Debug)]
+pub enum StmError {
+    InvalidConfigData,
+    InvalidStoredConfig,
+    /// Value outside the physical bounds of the field
+    OutOfRange(&'static str),
+    /// Range with `min` not below `max`
+    MinNotBelowMax(&'static str),
+}
+
+impl StmError {
+    /// Short machine readable code for UART replies
+    pub fn code(&self) -> &'static str {
+        match self {
+            StmError::InvalidConfigData => "invalid_json",
+            StmError::InvalidStoredConfig => "invalid_record",
+            StmError::OutOfRange(_) => "out_of_range",
+            StmError::MinNotBelowMax(_) => "min_not_below_max",
+        }
+    }
+
+    /// Config field the error refers to
+    pub fn field(&self) -> Option<&str> {
+        match self {
+            StmError::OutOfRange(field) | StmError::MinNotBelowMax(field) => Some(field),
+            _ => None,
+        }
+    }
+}
+
+impl core::error::Error for StmError {}
+impl core::fmt::Display for StmError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            StmError::InvalidConfigData => write!(f, "Invalid UART config data"),
+            StmError::InvalidStoredConfig => write!(f, "Invalid config record in flash"),
+            StmError::OutOfRange(field) => write!(f, "{} out of range", field),
+            StmError::MinNotBelowMax(field) => write!(f, "{} min must be below max", field),
+        }
+    }
+}
@@ -0,0 +1,575 @@
+//! MQTT over an ESP8266/ESP32 running stock ESP-AT firmware.
+//!
+//! Sans-IO: the UART task feeds received lines from `LineBuffer` into
+//! `Client::on_line`, writes whatever `Client::poll` returns and the payload when
+//! `on_line` asks for it, so the whole exchange can be scripted on the host.
+//!
+//! ```text
+//! > AT                                   < OK
+//! > ATE0                                 < OK
+//! > AT+CWMODE=1                          < OK
+//! > AT+CWJAP="ssid","password"           < WIFI CONNECTED, WIFI GOT IP, OK
+//! > AT+MQTTUSERCFG=0,1,"gw_..","u","p",0,0,""   < OK
+//! > AT+MQTTCONN=0,"broker",1883,0        < +MQTTCONNECTED:.., OK
+//! > AT+MQTTSUB=0,"gateway/<uid>/config/set",1   < OK
+//! > AT+MQTTPUBRAW=0,"gateway/<uid>/summary",42,0,0   < OK, >
+//! > {...42 bytes...}                     < +MQTTPUB:OK
+//! ```
+//!
+//! Any `ERROR`/`FAIL` or timeout during setup restarts it from `AT` after a backoff
+//! doubling from 1 s to 60 s. A broker drop resumes from `AT+MQTTCONN`, a Wi-Fi drop
+//! from `AT+CWJAP`.
+
+use core::fmt::Write;
+use heapless::Vec;
+use miniserde::__private::String;
+
+/// Longest line kept by `LineBuffer`, longer ones are dropped
+pub const LINE_LEN: usize = 512;
+/// Reply timeout for everything but the Wi-Fi join
+const COMMAND_TIMEOUT_MS: u64 = 5_000;
+const JOIN_TIMEOUT_MS: u64 = 20_000;
+const BACKOFF_MIN_MS: u64 = 1_000;
+const BACKOFF_MAX_MS: u64 = 60_000;
+
+/// Wi-Fi and broker settings
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    pub ssid: String,
+    pub password: String,
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub user: String,
+    pub pass: String,
+    /// Topic routed back as `Event::Received`, e.g. `gateway/<uid>/config/set`
+    pub subscribe: String,
+}
+
+/// Setup commands in the order they are sent
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Step {
+    Attention,
+    EchoOff,
+    StationMode,
+    JoinWifi,
+    UserConfig,
+    Connect,
+    Subscribe,
+}
+
+impl Step {
+    fn next(self) -> Option<Self> {
+        match self {
+            Step::Attention => Some(Step::EchoOff),
+            Step::EchoOff => Some(Step::StationMode),
+            Step::StationMode => Some(Step::JoinWifi),
+            Step::JoinWifi => Some(Step::UserConfig),
+            Step::UserConfig => Some(Step::Connect),
+            Step::Connect => Some(Step::Subscribe),
+            Step::Subscribe => None,
+        }
+    }
+
+    fn timeout_ms(self) -> u64 {
+        match self {
+            Step::JoinWifi => JOIN_TIMEOUT_MS,
+            _ => COMMAND_TIMEOUT_MS,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    /// `step` is sent on the next `poll`
+    Send(Step),
+    Waiting {
+        step: Step,
+        deadline: u64,
+    },
+    Backoff {
+        until: u64,
+    },
+    Ready,
+    /// `AT+MQTTPUBRAW` sent, waiting for the `>` prompt
+    Prompt {
+        deadline: u64,
+    },
+    /// Payload sent, waiting for `+MQTTPUB:OK`
+    Publishing {
+        deadline: u64,
+    },
+}
+
+/// What the UART task should do after a line
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    None,
+    /// Write the payload given to `publish`, without a line ending
+    SendPayload,
+    Published,
+    PublishFailed,
+    /// A message arrived on the subscribed topic
+    Received {
+        topic: String,
+        payload: String,
+    },
+    Connected,
+    Disconnected,
+}
+
+/// ESP-AT MQTT client state machine
+pub struct Client {
+    settings: Settings,
+    phase: Phase,
+    backoff_ms: u64,
+}
+
+impl Client {
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            settings,
+            phase: Phase::Send(Step::Attention),
+            backoff_ms: BACKOFF_MIN_MS,
+        }
+    }
+
+    /// Connected to the broker and not publishing
+    pub fn ready(&self) -> bool {
+        self.phase == Phase::Ready
+    }
+
+    /// Next command to write, with its line ending, or `None` if there is nothing to send
+    pub fn poll(&mut self, now_ms: u64) -> Option<String> {
+        match self.phase {
+            Phase::Send(step) => {
+                self.phase = Phase::Waiting {
+                    step,
+                    deadline: now_ms + step.timeout_ms(),
+                };
+                Some(self.command(step))
+            }
+            Phase::Backoff { until } if now_ms >= until => {
+                self.phase = Phase::Send(Step::Attention);
+                self.poll(now_ms)
+            }
+            Phase::Waiting { step, deadline } if now_ms >= deadline => {
+                warn!("ESP-AT: no reply to {}", crate::fmt::Debug2Format(&step));
+                self.fail(now_ms);
+                None
+            }
+            Phase::Prompt { deadline } | Phase::Publishing { deadline } if now_ms >= deadline => {
+                warn!("ESP-AT: publish timed out");
+                self.fail(now_ms);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Start publishing `len` payload bytes to `topic`, `None` unless `ready()`. Write the
+    /// returned command, then the payload once `on_line` returns `Event::SendPayload`.
+    pub fn publish(
+        &mut self,
+        topic: &str,
+        len: usize,
+        retain: bool,
+        now_ms: u64,
+    ) -> Option<String> {
+        if !self.ready() {
+            return None;
+        }
+        self.phase = Phase::Prompt {
+            deadline: now_ms + COMMAND_TIMEOUT_MS,
+        };
+        let mut out = String::from("AT+MQTTPUBRAW=0,");
+        quote(&mut out, topic);
+        let _ = write!(out, ",{},0,{}\r\n", len, retain as u8);
+        Some(out)
+    }
+
+    /// Handle one line from the modem, without its line ending
+    pub fn on_line(&mut self, line: &str, now_ms: u64) -> Event {
+        let line = line.trim_end();
+        if let Some(rest) = line.strip_prefix("+MQTTSUBRECV:") {
+            return match parse_received(rest) {
+                Some((topic, payload)) => Event::Received {
+                    topic: topic.into(),
+                    payload: payload.into(),
+                },
+                None => {
+                    warn!("ESP-AT: bad subscription message");
+                    Event::None
+                }
+            };
+        }
+        if line.starts_with("+MQTTDISCONNECTED") {
+            return self.lost(Step::Connect);
+        }
+        if line == "WIFI DISCONNECT" {
+            return self.lost(Step::JoinWifi);
+        }
+        match self.phase {
+            Phase::Waiting { step, .. } => match line {
+                "OK" => match step.next() {
+                    Some(next) => {
+                        self.phase = Phase::Send(next);
+                        Event::None
+                    }
+                    None => {
+                        info!("ESP-AT: MQTT connected");
+                        self.phase = Phase::Ready;
+                        self.backoff_ms = BACKOFF_MIN_MS;
+                        Event::Connected
+                    }
+                },
+                "ERROR" | "FAIL" => {
+                    warn!("ESP-AT: {} failed", crate::fmt::Debug2Format(&step));
+                    self.fail(now_ms);
+                    Event::None
+                }
+                _ => Event::None,
+            },
+            Phase::Prompt { deadline } => match line {
+                ">" => {
+                    self.phase = Phase::Publishing { deadline };
+                    Event::SendPayload
+                }
+                "ERROR" => {
+                    self.phase = Phase::Ready;
+                    Event::PublishFailed
+                }
+                _ => Event::None,
+            },
+            Phase::Publishing { .. } => match line {
+                "+MQTTPUB:OK" => {
+                    self.phase = Phase::Ready;
+                    Event::Published
+                }
+                "+MQTTPUB:FAIL" | "ERROR" => {
+                    self.phase = Phase::Ready;
+                    Event::PublishFailed
+                }
+                _ => Event::None,
+            },
+            _ => Event::None,
+        }
+    }
+
+    fn command(&self, step: Step) -> String {
+        let s = &self.settings;
+        let mut out = String::new();
+        match step {
+            Step::Attention => out.push_str("AT"),
+            Step::EchoOff => out.push_str("ATE0"),
+            Step::StationMode => out.push_str("AT+CWMODE=1"),
+            Step::JoinWifi => {
+                out.push_str("AT+CWJAP=");
+                quote(&mut out, &s.ssid);
+                out.push(',');
+                quote(&mut out, &s.password);
+            }
+            Step::UserConfig => {
+                out.push_str("AT+MQTTUSERCFG=0,1,");
+                quote(&mut out, &s.client_id);
+                out.push(',');
+                quote(&mut out, &s.user);
+                out.push(',');
+                quote(&mut out, &s.pass);
+                out.push_str(",0,0,\"\"");
+            }
+            Step::Connect => {
+                out.push_str("AT+MQTTCONN=0,");
+                quote(&mut out, &s.host);
+                let _ = write!(out, ",{},0", s.port);
+            }
+            Step::Subscribe => {
+                out.push_str("AT+MQTTSUB=0,");
+                quote(&mut out, &s.subscribe);
+                out.push_str(",1");
+            }
+        }
+        out.push_str("\r\n");
+        out
+    }
+
+    /// Setup or publish failed, start over from `AT` after the backoff
+    fn fail(&mut self, now_ms: u64) {
+        self.phase = Phase::Backoff {
+            until: now_ms + self.backoff_ms,
+        };
+        self.backoff_ms = (self.backoff_ms * 2).min(BACKOFF_MAX_MS);
+    }
+
+    /// The modem dropped the link, resume setup from `step`
+    fn lost(&mut self, step: Step) -> Event {
+        warn!(
+            "ESP-AT: link lost, resuming from {}",
+            crate::fmt::Debug2Format(&step)
+        );
+        match self.phase {
+            // a setup step in progress has its own timeout and backoff
+            Phase::Send(_) | Phase::Waiting { .. } | Phase::Backoff { .. } => Event::None,
+            _ => {
+                self.phase = Phase::Send(step);
+                Event::Disconnected
+            }
+        }
+    }
+}
+
+/// `"value"` with ESP-AT's backslash escapes for `"`, `,` and `\`
+fn quote(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        if matches!(c, '"' | ',' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+}
+
+/// `0,"topic",len,data` from `+MQTTSUBRECV`
+fn parse_received(rest: &str) -> Option<(&str, &str)> {
+    let (_link, rest) = rest.split_once(',')?;
+    let rest = rest.strip_prefix('"')?;
+    let (topic, rest) = rest.split_once("\",")?;
+    let (len, data) = rest.split_once(',')?;
+    let len: usize = len.parse().ok()?;
+    data.get(..len).map(|payload| (topic, payload))
+}
+
+/// Splits modem output into lines, yielding the bare `>` publish prompt as a line
+pub struct LineBuffer {
+    buf: Vec<u8, LINE_LEN>,
+    overflow: bool,
+}
+
+impl LineBuffer {
+    pub fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            overflow: false,
+        }
+    }
+
+    /// Add one byte, returns a complete non-empty line without its line ending
+    pub fn push(&mut self, byte: u8) -> Option<String> {
+        match byte {
+            b'\r' => None,
+            b'\n' => {
+                let line = match self.overflow {
+                    true => None,
+                    false => core::str::from_utf8(&self.buf)
+                        .ok()
+                        .filter(|line| !line.is_empty())
+                        .map(String::from),
+                };
+                self.buf.clear();
+                self.overflow = false;
+                line
+            }
+            b'>' if self.buf.is_empty() => Some(String::from(">")),
+            _ => {
+                if self.buf.push(byte).is_err() && !self.overflow {
+                    warn!("ESP-AT: line longer than {} bytes dropped", LINE_LEN);
+                    self.overflow = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers each expected command with canned lines
+    struct FakeModem {
+        script: std::vec::Vec<(&'static str, &'static [&'static str])>,
+    }
+
+    impl FakeModem {
+        fn new(script: &[(&'static str, &'static [&'static str])]) -> Self {
+            Self {
+                script: script.iter().rev().copied().collect(),
+            }
+        }
+
+        /// Reply to `command`, fed through a `LineBuffer` byte by byte
+        fn reply(&mut self, command: &str) -> std::vec::Vec<String> {
+            let (expected, lines) = self.script.pop().expect("unexpected command");
+            assert_eq!(command, expected);
+            let mut buffer = LineBuffer::new();
+            lines
+                .iter()
+                .flat_map(|line| {
+                    let ending: &[u8] = if *line == ">" { b"" } else { b"\r\n" };
+                    line.bytes().chain(ending.iter().copied())
+                })
+                .filter_map(|byte| buffer.push(byte))
+                .collect()
+        }
+
+        fn done(&self) -> bool {
+            self.script.is_empty()
+        }
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            ssid: "home,net".into(),
+            password: "pa\"ss".into(),
+            host: "broker.local".into(),
+            port: 1883,
+            client_id: "gw_0102".into(),
+            user: "user".into(),
+            pass: "secret".into(),
+            subscribe: "gateway/0102/config/set".into(),
+        }
+    }
+
+    const SETUP: &[(&str, &[&str])] = &[
+        ("AT\r\n", &["OK"]),
+        ("ATE0\r\n", &["ATE0", "OK"]),
+        ("AT+CWMODE=1\r\n", &["OK"]),
+        (
+            "AT+CWJAP=\"home\\,net\",\"pa\\\"ss\"\r\n",
+            &["WIFI CONNECTED", "WIFI GOT IP", "", "OK"],
+        ),
+        (
+            "AT+MQTTUSERCFG=0,1,\"gw_0102\",\"user\",\"secret\",0,0,\"\"\r\n",
+            &["OK"],
+        ),
+        (
+            "AT+MQTTCONN=0,\"broker.local\",1883,0\r\n",
+            &["+MQTTCONNECTED:0,1,\"broker.local\",\"1883\",\"\",1", "OK"],
+        ),
+        ("AT+MQTTSUB=0,\"gateway/0102/config/set\",1\r\n", &["OK"]),
+    ];
+
+    /// Run `client` against `modem` until it stops sending, collecting events
+    fn drive(client: &mut Client, modem: &mut FakeModem, now_ms: u64) -> std::vec::Vec<Event> {
+        let mut events = std::vec::Vec::new();
+        while let Some(command) = client.poll(now_ms) {
+            for line in modem.reply(&command) {
+                events.push(client.on_line(&line, now_ms));
+            }
+        }
+        events.retain(|event| *event != Event::None);
+        events
+    }
+
+    #[test]
+    fn connects_and_subscribes() {
+        let mut modem = FakeModem::new(SETUP);
+        let mut client = Client::new(settings());
+        assert_eq!(drive(&mut client, &mut modem, 0), [Event::Connected]);
+        assert!(modem.done());
+        assert!(client.ready());
+    }
+
+    #[test]
+    fn publishes_after_the_prompt() {
+        let mut modem = FakeModem::new(SETUP);
+        let mut client = Client::new(settings());
+        drive(&mut client, &mut modem, 0);
+
+        let payload = "{\"soc\":55.0}";
+        let command = client
+            .publish("gateway/0102/summary", payload.len(), false, 10)
+            .unwrap();
+        assert_eq!(
+            command,
+            "AT+MQTTPUBRAW=0,\"gateway/0102/summary\",12,0,0\r\n"
+        );
+        assert!(!client.ready());
+        assert!(client.publish("other", 1, false, 10).is_none());
+        assert_eq!(client.on_line("OK", 10), Event::None);
+        assert_eq!(client.on_line(">", 10), Event::SendPayload);
+        assert_eq!(client.on_line("+MQTTPUB:OK", 20), Event::Published);
+        assert!(client.ready());
+    }
+
+    #[test]
+    fn routes_subscribed_messages() {
+        let mut client = Client::new(settings());
+        let event = client.on_line(
+            "+MQTTSUBRECV:0,\"gateway/0102/config/set\",17,{\"dod\":{\"max\":9}}",
+            0,
+        );
+        assert_eq!(
+            event,
+            Event::Received {
+                topic: "gateway/0102/config/set".into(),
+                payload: "{\"dod\":{\"max\":9}}".into(),
+            }
+        );
+        assert_eq!(
+            client.on_line("+MQTTSUBRECV:0,\"t\",99,short", 0),
+            Event::None
+        );
+    }
+
+    #[test]
+    fn backs_off_and_reconnects() {
+        let mut client = Client::new(settings());
+        let mut modem = FakeModem::new(&[
+            ("AT\r\n", &["OK"]),
+            ("ATE0\r\n", &["OK"]),
+            ("AT+CWMODE=1\r\n", &["OK"]),
+            (
+                "AT+CWJAP=\"home\\,net\",\"pa\\\"ss\"\r\n",
+                &["+CWJAP:3", "FAIL"],
+            ),
+        ]);
+        assert!(drive(&mut client, &mut modem, 0).is_empty());
+        // first retry after 1 s
+        assert!(client.poll(999).is_none());
+        assert_eq!(client.poll(1_000).unwrap(), "AT\r\n");
+        // no reply, the timeout doubles the backoff
+        assert!(client.poll(1_000 + COMMAND_TIMEOUT_MS).is_none());
+        let retry = 1_000 + COMMAND_TIMEOUT_MS + 2_000;
+        assert!(client.poll(retry - 1).is_none());
+
+        let mut modem = FakeModem::new(SETUP);
+        assert_eq!(drive(&mut client, &mut modem, retry), [Event::Connected]);
+
+        // broker drop resumes from the connect step
+        assert_eq!(
+            client.on_line("+MQTTDISCONNECTED:0", retry),
+            Event::Disconnected
+        );
+        let mut modem = FakeModem::new(&SETUP[5..]);
+        assert_eq!(drive(&mut client, &mut modem, retry), [Event::Connected]);
+
+        // Wi-Fi drop rejoins first
+        assert_eq!(
+            client.on_line("WIFI DISCONNECT", retry),
+            Event::Disconnected
+        );
+        let mut modem = FakeModem::new(&SETUP[3..]);
+        assert_eq!(drive(&mut client, &mut modem, retry), [Event::Connected]);
+        assert!(modem.done());
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let mut client = Client::new(settings());
+        let mut now = 0;
+        for _ in 0..10 {
+            assert_eq!(client.poll(now).unwrap(), "AT\r\n");
+            client.on_line("ERROR", now);
+            now += client.backoff_ms.min(BACKOFF_MAX_MS);
+        }
+        assert_eq!(client.backoff_ms, BACKOFF_MAX_MS);
+    }
+}
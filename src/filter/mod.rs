@@ -0,0 +1,320 @@
+//! Hardware acceptance filters, compiled from the ID tables the protocols declare.
+//!
+//! CAN1 and CAN2 share 28 filter banks, CAN1 takes the banks below the split and CAN2
+//! the rest. Each bank is packed as densely as its rules allow:
+//!
+//! ```text
+//! exact standard IDs   16-bit list   4 per bank   FIFO 0
+//! standard id/mask     16-bit mask   2 per bank   FIFO 0
+//! exact extended IDs   32-bit list   2 per bank   FIFO 1
+//! extended id/mask     32-bit mask   1 per bank   FIFO 1
+//! ```
+//!
+//! Part-filled banks repeat their last entry. Frames injected by the candump replay
+//! never pass the hardware, so drivers still check IDs where it matters.
+
+use crate::config::{BatteryType, InverterType};
+use bxcan::filter::{BankConfig, ListEntry16, ListEntry32, Mask16, Mask32};
+use bxcan::{ExtendedId, Fifo, Id, StandardId};
+use heapless::Vec;
+
+/// Filter banks shared by CAN1 and CAN2
+pub const BANKS: usize = 28;
+
+const STANDARD_MASK: u16 = 0x7ff;
+const EXTENDED_MASK: u32 = 0x1fff_ffff;
+
+/// One accepted ID or ID range, mask bits that are set must match
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rule {
+    Standard { id: u16, mask: u16 },
+    Extended { id: u32, mask: u32 },
+}
+
+impl Rule {
+    /// Exactly `id`, standard
+    pub const fn standard(id: u16) -> Self {
+        Rule::Standard {
+            id,
+            mask: STANDARD_MASK,
+        }
+    }
+
+    /// Exactly `id`, extended
+    pub const fn extended(id: u32) -> Self {
+        Rule::Extended {
+            id,
+            mask: EXTENDED_MASK,
+        }
+    }
+
+    pub fn accepts(&self, id: Id) -> bool {
+        match (self, id) {
+            (Rule::Standard { id, mask }, Id::Standard(other)) => (id ^ other.as_raw()) & mask == 0,
+            (Rule::Extended { id, mask }, Id::Extended(other)) => (id ^ other.as_raw()) & mask == 0,
+            _ => false,
+        }
+    }
+}
+
+/// Every frame, for protocols without an ID table
+pub const ACCEPT_ALL: &[Rule] = &[
+    Rule::Standard { id: 0, mask: 0 },
+    Rule::Extended { id: 0, mask: 0 },
+];
+
+/// True when any rule of `rules` accepts `id`
+pub fn accepts(rules: &[Rule], id: Id) -> bool {
+    rules.iter().any(|rule| rule.accepts(id))
+}
+
+/// One filter bank, standard banks feed FIFO 0 and extended banks FIFO 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bank {
+    List16([u16; 4]),
+    Mask16([(u16, u16); 2]),
+    List32([u32; 2]),
+    Mask32(u32, u32),
+}
+
+impl Bank {
+    pub fn fifo(&self) -> Fifo {
+        match self {
+            Bank::List16(_) | Bank::Mask16(_) => Fifo::Fifo0,
+            Bank::List32(_) | Bank::Mask32(..) => Fifo::Fifo1,
+        }
+    }
+
+    /// Register layout for `FilterBanks::enable_bank`
+    pub fn config(&self) -> BankConfig {
+        let standard = |id: u16| StandardId::new(id & STANDARD_MASK).unwrap();
+        let extended = |id: u32| ExtendedId::new(id & EXTENDED_MASK).unwrap();
+        match *self {
+            Bank::List16(ids) => {
+                BankConfig::List16(ids.map(|id| ListEntry16::data_frames_with_id(standard(id))))
+            }
+            Bank::Mask16(masks) => BankConfig::Mask16(
+                masks.map(|(id, mask)| Mask16::frames_with_std_id(standard(id), standard(mask))),
+            ),
+            Bank::List32(ids) => {
+                BankConfig::List32(ids.map(|id| ListEntry32::data_frames_with_id(extended(id))))
+            }
+            Bank::Mask32(id, mask) => {
+                BankConfig::Mask32(Mask32::frames_with_ext_id(extended(id), extended(mask)))
+            }
+        }
+    }
+}
+
+/// The protocols need more banks than the hardware has
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TooManyBanks {
+    pub needed: usize,
+}
+
+/// Banks of both buses, CAN1 first
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layout {
+    banks: Vec<Bank, BANKS>,
+    split: usize,
+}
+
+impl Layout {
+    /// First CAN2 bank, for `FilterBanks::set_split`
+    pub fn split(&self) -> u8 {
+        self.split as u8
+    }
+
+    pub fn can1(&self) -> &[Bank] {
+        &self.banks[..self.split]
+    }
+
+    pub fn can2(&self) -> &[Bank] {
+        &self.banks[self.split..]
+    }
+}
+
+/// Banks `rules` take up once packed
+pub fn banks_needed(rules: &[Rule]) -> usize {
+    let (mut list16, mut mask16, mut list32, mut mask32) = (0, 0, 0, 0);
+    for rule in rules {
+        match rule {
+            Rule::Standard { mask, .. } if *mask == STANDARD_MASK => list16 += 1,
+            Rule::Standard { .. } => mask16 += 1,
+            Rule::Extended { mask, .. } if *mask == EXTENDED_MASK => list32 += 1,
+            Rule::Extended { .. } => mask32 += 1,
+        }
+    }
+    (list16 + 3) / 4 + (mask16 + 1) / 2 + (list32 + 1) / 2 + mask32
+}
+
+/// Pack the CAN1 and CAN2 tables into the shared banks
+pub fn compile(can1: &[Rule], can2: &[Rule]) -> Result<Layout, TooManyBanks> {
+    let split = banks_needed(can1);
+    let needed = split + banks_needed(can2);
+    if needed > BANKS {
+        return Err(TooManyBanks { needed });
+    }
+    let mut banks = Vec::new();
+    pack(can1, &mut banks);
+    pack(can2, &mut banks);
+    Ok(Layout { banks, split })
+}
+
+/// Layout for the protocols of this boot, the battery on CAN1 and the inverter on CAN2
+pub fn for_protocols(battery: BatteryType, inverter: InverterType) -> Result<Layout, TooManyBanks> {
+    compile(
+        crate::battery::filters(battery),
+        crate::inverter::filters(inverter),
+    )
+}
+
+fn pack(rules: &[Rule], banks: &mut Vec<Bank, BANKS>) {
+    // counted by `compile`, every push fits
+    let mut push = |bank| {
+        let _ = banks.push(bank);
+    };
+    let list16 = rules.iter().filter_map(|rule| match *rule {
+        Rule::Standard { id, mask } if mask == STANDARD_MASK => Some(id),
+        _ => None,
+    });
+    chunks(list16, |ids| push(Bank::List16(ids)));
+    let mask16 = rules.iter().filter_map(|rule| match *rule {
+        Rule::Standard { id, mask } if mask != STANDARD_MASK => Some((id, mask)),
+        _ => None,
+    });
+    chunks(mask16, |masks| push(Bank::Mask16(masks)));
+    let list32 = rules.iter().filter_map(|rule| match *rule {
+        Rule::Extended { id, mask } if mask == EXTENDED_MASK => Some(id),
+        _ => None,
+    });
+    chunks(list32, |ids| push(Bank::List32(ids)));
+    for rule in rules {
+        if let Rule::Extended { id, mask } = *rule {
+            if mask != EXTENDED_MASK {
+                push(Bank::Mask32(id, mask));
+            }
+        }
+    }
+}
+
+/// Group `items` by `N`, padding the last group with its last item
+fn chunks<T: Copy, const N: usize>(items: impl Iterator<Item = T>, mut emit: impl FnMut([T; N])) {
+    let mut group: Option<[T; N]> = None;
+    let mut len = 0;
+    for item in items {
+        let entries = group.get_or_insert([item; N]);
+        entries[len] = item;
+        len += 1;
+        if len == N {
+            emit(*entries);
+            group = None;
+            len = 0;
+        }
+    }
+    if let Some(mut entries) = group {
+        let last = entries[len - 1];
+        entries[len..].fill(last);
+        emit(entries);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_match_under_their_mask() {
+        let rule = Rule::Standard {
+            id: 0x350,
+            mask: 0x7f0,
+        };
+        assert!(rule.accepts(Id::Standard(StandardId::new(0x35e).unwrap())));
+        assert!(!rule.accepts(Id::Standard(StandardId::new(0x360).unwrap())));
+        assert!(!rule.accepts(Id::Extended(ExtendedId::new(0x350).unwrap())));
+        assert!(accepts(ACCEPT_ALL, Id::Extended(ExtendedId::MAX)));
+        assert!(Rule::extended(0x1871).accepts(Id::Extended(ExtendedId::new(0x1871).unwrap())));
+    }
+
+    #[test]
+    fn packs_each_rule_kind_densely() {
+        let can1 = [
+            Rule::standard(0x155),
+            Rule::standard(0x424),
+            Rule::standard(0x425),
+            Rule::standard(0x4ae),
+            Rule::standard(0x7bb),
+            Rule::Standard {
+                id: 0x350,
+                mask: 0x7f0,
+            },
+        ];
+        let can2 = [
+            Rule::extended(0x1871),
+            Rule::Extended {
+                id: 0x1800_0000,
+                mask: 0x1f00_0000,
+            },
+        ];
+        let layout = compile(&can1, &can2).unwrap();
+        assert_eq!(layout.split(), 3);
+        assert_eq!(
+            layout.can1(),
+            &[
+                Bank::List16([0x155, 0x424, 0x425, 0x4ae]),
+                Bank::List16([0x7bb; 4]),
+                Bank::Mask16([(0x350, 0x7f0); 2]),
+            ]
+        );
+        assert_eq!(
+            layout.can2(),
+            &[
+                Bank::List32([0x1871; 2]),
+                Bank::Mask32(0x1800_0000, 0x1f00_0000)
+            ]
+        );
+        assert_eq!(layout.can2()[0].fifo(), Fifo::Fifo1);
+    }
+
+    #[test]
+    fn reports_tables_that_do_not_fit() {
+        let masks: [Rule; 20] = core::array::from_fn(|i| Rule::Extended {
+            id: i as u32,
+            mask: 0xff,
+        });
+        assert_eq!(banks_needed(&masks), 20);
+        assert_eq!(compile(&masks, &masks[..8]).map(|l| l.split()), Ok(20));
+        assert_eq!(
+            compile(&masks, &masks[..9]),
+            Err(TooManyBanks { needed: 29 })
+        );
+    }
+
+    #[test]
+    fn every_protocol_pair_fits() {
+        for battery in [BatteryType::Ze50, BatteryType::Kangoo] {
+            for inverter in [
+                InverterType::Byd,
+                InverterType::Pylontech,
+                InverterType::Solax,
+            ] {
+                let layout = for_protocols(battery, inverter).unwrap();
+                assert!(!layout.can1().is_empty() && !layout.can2().is_empty());
+            }
+        }
+        let kangoo = for_protocols(BatteryType::Kangoo, InverterType::Solax).unwrap();
+        assert_eq!(kangoo.split(), 2);
+        assert_eq!(kangoo.can1()[1], Bank::List16([0x7bb; 4]));
+        assert_eq!(kangoo.can2(), &[Bank::List32([0x1871; 2])]);
+    }
+
+    #[test]
+    fn empty_table_takes_no_banks() {
+        let layout = compile(&[], ACCEPT_ALL).unwrap();
+        assert_eq!(layout.split(), 0);
+        assert_eq!(
+            layout.can2(),
+            &[Bank::Mask16([(0, 0); 2]), Bank::Mask32(0, 0)]
+        );
+    }
+}
@@ -0,0 +1,62 @@
+//! Logging shim: defmt on target, the `log` crate on the host.
+//!
+//! Only use `{}`/`{:?}` placeholders with primitives or `Debug2Format` so the same
+//! format string is valid for both backends.
+#![allow(unused_macros)]
+
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        #[cfg(target_os = "none")]
+        ::defmt::debug!($($arg)*);
+        #[cfg(not(target_os = "none"))]
+        ::log::debug!($($arg)*);
+    }};
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {{
+        #[cfg(target_os = "none")]
+        ::defmt::info!($($arg)*);
+        #[cfg(not(target_os = "none"))]
+        ::log::info!($($arg)*);
+    }};
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => {{
+        #[cfg(target_os = "none")]
+        ::defmt::warn!($($arg)*);
+        #[cfg(not(target_os = "none"))]
+        ::log::warn!($($arg)*);
+    }};
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => {{
+        #[cfg(target_os = "none")]
+        ::defmt::error!($($arg)*);
+        #[cfg(not(target_os = "none"))]
+        ::log::error!($($arg)*);
+    }};
+}
+
+#[cfg(target_os = "none")]
+pub use defmt::Debug2Format;
+
+/// Host stand-in for `defmt::Debug2Format`, prints with `Debug` for either placeholder
+#[cfg(not(target_os = "none"))]
+pub struct Debug2Format<'a, T: core::fmt::Debug + ?Sized>(pub &'a T);
+
+#[cfg(not(target_os = "none"))]
+impl<T: core::fmt::Debug + ?Sized> core::fmt::Debug for Debug2Format<'_, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl<T: core::fmt::Debug + ?Sized> core::fmt::Display for Debug2Format<'_, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
@@ -0,0 +1,102 @@
+//! Text form of CAN frames as used by can-utils: `123#DEADBEEF`, `18DAF1DB#0322`.
+//!
+//! Three hex digits are a standard ID, eight an extended ID, `R` after `#` marks a
+//! remote frame.
+
+use bxcan::{ExtendedId, Frame, Id, StandardId};
+use core::fmt::Write;
+
+#[derive(Debug, PartialEq)]
+pub enum FrameTextError {
+    MissingSeparator,
+    BadId,
+    BadData,
+}
+
+/// Parse `id#data`
+pub fn parse(text: &str) -> Result<Frame, FrameTextError> {
+    let (id, data) = text
+        .trim()
+        .split_once('#')
+        .ok_or(FrameTextError::MissingSeparator)?;
+    // from_str_radix accepts a sign, and slicing `data` needs one byte per char
+    if !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(FrameTextError::BadId);
+    }
+    let raw = u32::from_str_radix(id, 16).map_err(|_| FrameTextError::BadId)?;
+    let id: Id = match id.len() {
+        3 => StandardId::new(raw as u16)
+            .ok_or(FrameTextError::BadId)?
+            .into(),
+        8 => ExtendedId::new(raw).ok_or(FrameTextError::BadId)?.into(),
+        _ => return Err(FrameTextError::BadId),
+    };
+    if let Some(dlc) = data.strip_prefix('R') {
+        let dlc = if dlc.is_empty() {
+            0
+        } else {
+            dlc.parse::<u8>().map_err(|_| FrameTextError::BadData)?
+        };
+        if dlc > 8 {
+            return Err(FrameTextError::BadData);
+        }
+        return Ok(Frame::new_remote(id, dlc));
+    }
+    if !data.bytes().all(|b| b.is_ascii_hexdigit()) || data.len() % 2 != 0 || data.len() > 16 {
+        return Err(FrameTextError::BadData);
+    }
+    let mut bytes = [0u8; 8];
+    for (i, byte) in bytes.iter_mut().take(data.len() / 2).enumerate() {
+        *byte = u8::from_str_radix(&data[i * 2..i * 2 + 2], 16)
+            .map_err(|_| FrameTextError::BadData)?;
+    }
+    let data = bxcan::Data::new(&bytes[..data.len() / 2]).ok_or(FrameTextError::BadData)?;
+    Ok(Frame::new_data(id, data))
+}
+
+/// Write `id#data` into `out`
+pub fn write<W: Write>(out: &mut W, frame: &Frame) -> core::fmt::Result {
+    match frame.id() {
+        Id::Standard(id) => write!(out, "{:03X}#", id.as_raw())?,
+        Id::Extended(id) => write!(out, "{:08X}#", id.as_raw())?,
+    }
+    match frame.data() {
+        Some(data) => {
+            for byte in data.iter() {
+                write!(out, "{:02X}", byte)?;
+            }
+            Ok(())
+        }
+        None => write!(out, "R{}", frame.dlc()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(text: &str) -> String {
+        let mut out = String::new();
+        write(&mut out, &parse(text).unwrap()).unwrap();
+        out
+    }
+
+    #[test]
+    fn parses_standard_and_extended() {
+        assert_eq!(round_trip("373#0102030405060708"), "373#0102030405060708");
+        assert_eq!(round_trip("18DAF1DB#0322"), "18DAF1DB#0322");
+        assert_eq!(round_trip("7bb#"), "7BB#");
+        assert_eq!(round_trip("155#R4"), "155#R4");
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        assert_eq!(parse("373"), Err(FrameTextError::MissingSeparator));
+        assert_eq!(parse("37#00"), Err(FrameTextError::BadId));
+        assert_eq!(parse("900#00"), Err(FrameTextError::BadId));
+        assert_eq!(parse("373#0"), Err(FrameTextError::BadData));
+        assert_eq!(parse("373#000102030405060708"), Err(FrameTextError::BadData));
+        assert_eq!(parse("+12#00"), Err(FrameTextError::BadId));
+        assert_eq!(parse("123#a\u{e9}1"), Err(FrameTextError::BadData));
+    }
+}
@@ -0,0 +1,57 @@
+use super::Gateway;
+use crate::autobaud::{CANDIDATES, DWELL_MS};
+use crate::candump::Bus;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use miniserde::Serialize;
+
+/// Immediate answer to the `autobaud` command
+#[derive(Serialize)]
+pub struct AutobaudStarted {
+    pub bus: Bus,
+    /// The `autobaud` report follows after about this long
+    pub search_ms: u64,
+}
+
+/// Outcome of a search, published once as the `autobaud` topic
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct AutobaudReport {
+    pub bus: Bus,
+    /// Detected bit/s, `None` when no candidate was clean
+    pub bitrate: Option<u32>,
+    /// Written into the config
+    pub saved: bool,
+    /// Frames aborted in the TX mailboxes when the search started
+    pub dropped: u8,
+}
+
+impl<M: RawMutex> Gateway<M> {
+    /// Have the CAN task of `bus` search for its bitrate, it answers through
+    /// `finish_autobaud`. With `save` the rate is also stored in the config.
+    pub fn start_autobaud(&self, bus: Bus, save: bool) -> AutobaudStarted {
+        warn!("{} bitrate search, transmit paused", bus.iface());
+        self.autobaud[bus.index()].signal(save);
+        AutobaudStarted {
+            bus,
+            search_ms: DWELL_MS * CANDIDATES.len() as u64,
+        }
+    }
+
+    /// The search on `bus` is over and the bus runs at `bitrate`, or at its old rate
+    /// when `None`. Saves the rate if asked to and queues the report.
+    pub async fn finish_autobaud(&self, bus: Bus, save: bool, bitrate: Option<u32>, dropped: u8) {
+        let mut saved = false;
+        if let (Some(bitrate), true) = (bitrate, save) {
+            saved = self
+                .update_config(|config| config.set_bitrate(bus, bitrate))
+                .await
+                .is_ok();
+        }
+        *self.autobaud_report.lock().await = Some(AutobaudReport {
+            bus,
+            bitrate,
+            saved,
+            dropped,
+        });
+        self.send_mqtt.signal(true);
+    }
+}
@@ -0,0 +1,117 @@
+use super::Gateway;
+use crate::battery::{BatteryDriver, Ingest};
+use crate::config::BatteryType;
+use crate::fmt::Debug2Format;
+use embassy_futures::join::join;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Instant, Timer};
+
+/// Upper bound on request slots per driver
+const MAX_SLOTS: usize = 4;
+
+/// Run the driver for `battery`, returns only if it is not compiled in
+pub async fn run_battery<M: RawMutex>(gateway: &Gateway<M>, battery: BatteryType) {
+    match battery {
+        #[cfg(feature = "ze50")]
+        BatteryType::Ze50 => run(gateway, crate::battery::ze50::Ze50::new()).await,
+        #[cfg(feature = "kangoo")]
+        BatteryType::Kangoo => run(gateway, crate::battery::kangoo::Kangoo::new()).await,
+        #[allow(unreachable_patterns)]
+        other => error!("Battery {} not compiled in", Debug2Format(&other)),
+    }
+}
+
+/// Drive a battery driver over the gateway's BMS channels
+pub async fn run<M: RawMutex, D: BatteryDriver>(gateway: &Gateway<M>, driver: D) {
+    let driver = Mutex::<M, D>::new(driver);
+    join(bms_rx(gateway, &driver), bms_tx_periodic(gateway, &driver)).await;
+}
+
+async fn bms_rx<M: RawMutex, D: BatteryDriver>(gateway: &Gateway<M>, driver: &Mutex<M, D>) {
+    let rx = gateway.bms_rx.receiver();
+    let tx = gateway.bms_tx.sender();
+    warn!("Starting BMS Rx Processor");
+    loop {
+        let frame = rx.recv().await;
+        let mut driver = driver.lock().await;
+        match driver.ingest(&frame) {
+            Ok(Ingest::Ignored) | Ok(Ingest::Pending) => (),
+            Ok(Ingest::Alive) => {
+                *gateway.last_bms_message.lock().await = Instant::now();
+                info!("BMS watchdog reset")
+            }
+            Ok(Ingest::Reply(next_tx_frame)) => tx.send(next_tx_frame).await,
+            Err(e) => debug!("BMS frame error: {:?}", Debug2Format(&e)),
+        }
+        publish(gateway, &mut *driver).await;
+    }
+}
+
+async fn bms_tx_periodic<M: RawMutex, D: BatteryDriver>(
+    gateway: &Gateway<M>,
+    driver: &Mutex<M, D>,
+) {
+    let tx = gateway.bms_tx.sender();
+    let sender = |frame| {
+        if let Err(_e) = tx.try_send(frame) {
+            error!("Periodic queue buf error")
+        };
+    };
+
+    let (startup_delay, init_interval, init_frames, schedule) = {
+        let mut driver = driver.lock().await;
+        (
+            driver.startup_delay(),
+            driver.init_interval(),
+            driver.init_frames(),
+            driver.schedule(),
+        )
+    };
+    Timer::after(startup_delay).await;
+    warn!("Starting BMS TX periodic");
+
+    // send init
+    for frame in init_frames {
+        Timer::after(init_interval).await;
+        sender(frame);
+    }
+
+    let start = Instant::now();
+    let mut due: heapless::Vec<Instant, MAX_SLOTS> = heapless::Vec::new();
+    for interval in schedule {
+        if due.push(start + *interval).is_err() {
+            error!("BMS schedule has more than {} slots, the rest are skipped", MAX_SLOTS);
+            break;
+        }
+    }
+    loop {
+        let Some((slot, at)) = due.iter().copied().enumerate().min_by_key(|(_, at)| *at) else {
+            return;
+        };
+        Timer::at(at).await;
+        due[slot] = at + schedule[slot];
+        let mut driver = driver.lock().await;
+        if let Some(frame) = driver.request(slot) {
+            sender(frame);
+        }
+        publish(gateway, &mut *driver).await;
+    }
+}
+
+/// Push a completed reading out and refresh the DoD limits on the driver
+async fn publish<M: RawMutex, D: BatteryDriver>(gateway: &Gateway<M>, driver: &mut D) {
+    match driver.take_state() {
+        Ok(Some(state)) => {
+            if let Some(cells) = driver.cells() {
+                gateway.cells.lock().await.update(cells);
+            }
+            gateway.push_battery_state(state).await;
+            info!("Pushed values to battery state store");
+            let config = gateway.config.lock().await;
+            driver.set_dod(config.dod.min(), config.dod.max());
+        }
+        Ok(None) => (),
+        Err(e) => error!("BMS value parsing failed: {}", Debug2Format(&e)),
+    }
+}
@@ -0,0 +1,81 @@
+use super::Gateway;
+use crate::config::{record, Config};
+use crate::errors::StmError;
+use crate::fmt::Debug2Format;
+use crate::modbus::map::{signed, unsigned};
+use core::sync::atomic::Ordering;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use gateway_wire::message::{self, Ack, Message, Summary};
+use gateway_wire::{Frame, Packet, MAX_PAYLOAD};
+use heapless::Vec;
+
+impl<M: RawMutex> Gateway<M> {
+    /// Live readings as binary frame `seq`, returns the frame length
+    pub async fn binary_summary(&self, seq: u8, out: &mut Frame) -> usize {
+        let state = *self.battery_state.lock().await;
+        let (alarm, tripped) = {
+            let protection = self.protection.lock().await;
+            (protection.level(), protection.trip().is_some())
+        };
+        let summary = Summary {
+            soc: unsigned(state.soc, 10.0),
+            pack_volts: unsigned(state.pack_volts, 10.0),
+            current: signed(state.current, 10.0) as i16,
+            cell_mv_high: state.cell_mv_high,
+            cell_mv_low: state.cell_mv_low,
+            cell_temp_high: signed(state.cell_temp_high, 10.0) as i16,
+            cell_temp_low: signed(state.cell_temp_low, 10.0) as i16,
+            temp_avg: signed(state.temp_avg, 10.0) as i16,
+            kwh_remaining: unsigned(state.kwh_remaining, 100.0),
+            charge_max: unsigned(state.charge_max, 10.0),
+            discharge_max: unsigned(state.discharge_max, 10.0),
+            balancing_cells: state.balancing_cells,
+            valid: state.valid,
+            state: *self.config.lock().await.state() as u8,
+            contactor: self.contactor.lock().await.state as u8,
+            alarm: alarm as u8,
+            tripped,
+            forced_open: self.forced_open.load(Ordering::Relaxed),
+        };
+        Message::Summary(summary).encode(seq, out)
+    }
+
+    /// Answer one host frame with frame `seq`, returns the reply length
+    pub async fn binary_request(&self, packet: &Packet, seq: u8, out: &mut Frame) -> usize {
+        let status = match packet.kind {
+            message::START => Ack::Ok,
+            message::GET_CONFIG => {
+                let mut payload: Vec<u8, MAX_PAYLOAD> = Vec::new();
+                let _ = payload.push(record::VERSION);
+                let _ = payload.extend_from_slice(&self.config.lock().await.to_record());
+                return gateway_wire::encode(message::CONFIG, seq, &payload, out).unwrap_or(0);
+            }
+            message::SET_CONFIG => match self.replace_config(packet.payload()).await {
+                Ok(()) => Ack::Ok,
+                Err(e) => {
+                    error!("Binary config rejected: {}", Debug2Format(&e));
+                    Ack::Rejected
+                }
+            },
+            _ => Ack::Unsupported,
+        };
+        let ack = Message::Ack {
+            request: packet.seq,
+            status,
+        };
+        ack.encode(seq, out)
+    }
+
+    /// Replace the whole config with a `<version> <record>` payload, keeping the state
+    async fn replace_config(&self, payload: &[u8]) -> Result<(), StmError> {
+        let (&version, bytes) = payload.split_first().ok_or(StmError::InvalidConfigData)?;
+        let mut replacement = Config::from_record(version, bytes)?;
+        replacement.validate()?;
+        self.update_config(|config| {
+            replacement.set_state(*config.state());
+            *config = replacement;
+            Ok(())
+        })
+        .await
+    }
+}
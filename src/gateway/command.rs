@@ -0,0 +1,99 @@
+use super::Gateway;
+use crate::command::{self, Command, Request};
+use crate::config::patch::Patch;
+use crate::config::{Config, State};
+use crate::contactor;
+use crate::errors::StmError;
+use core::sync::atomic::Ordering;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use miniserde::__private::String;
+use miniserde::json;
+
+/// Response to one command
+pub struct Reply {
+    pub text: String,
+    /// The caller should reset the device once `text` is sent
+    pub reboot: bool,
+}
+
+impl<M: RawMutex> Gateway<M> {
+    /// Merge-patch the config, then sync capture and queue a flash write
+    pub async fn apply_config(&self, patch: &Patch) -> Result<(), StmError> {
+        self.update_config(|config| config.update_from_patch(patch)).await
+    }
+
+    /// Change the config through `update`, then sync capture and queue a flash write.
+    /// `update` must leave the config untouched when it fails.
+    pub async fn update_config(
+        &self,
+        update: impl FnOnce(&mut Config) -> Result<(), StmError>,
+    ) -> Result<(), StmError> {
+        let mut config = self.config.lock().await;
+        let protocols = (config.battery_type(), config.inverter_type());
+        update(&mut config)?;
+        info!("Config updated");
+        if protocols != (config.battery_type(), config.inverter_type()) {
+            warn!("Battery/inverter type changed, restart to apply")
+        }
+        self.capture_enabled.store(config.candump(), Ordering::Relaxed);
+        self.save_config.signal(());
+        Ok(())
+    }
+
+    /// Reset the protection trip, latched contactor faults and a forced open
+    pub async fn clear_faults(&self) {
+        warn!("Faults cleared");
+        self.protection.lock().await.reset();
+        self.forced_open.store(false, Ordering::Relaxed);
+        self.config.lock().await.set_state(State::Offline);
+        self.contactor_reset.store(true, Ordering::Relaxed);
+    }
+
+    /// Open the contactor and hold it open until `clear_faults`
+    pub fn force_open(&self) {
+        warn!("Contactor forced open");
+        self.forced_open.store(true, Ordering::Relaxed);
+        self.contactor_state.signal(contactor::Command::ForceOpen);
+    }
+
+    pub async fn execute(&self, request: Request) -> Reply {
+        let id = request.id;
+        let mut reboot = false;
+        let text = match request.command {
+            Command::GetConfig => command::ok(id, Some(&self.config.lock().await.dump_to_json())),
+            Command::SetConfig(patch) => match self.apply_config(&patch).await {
+                Ok(()) => command::ok(id, None),
+                Err(e) => command::error(Some(id), e.code(), e.field()),
+            },
+            Command::GetStatus => {
+                command::ok(id, Some(&self.mqtt.lock().await.device_update_msg()))
+            }
+            Command::GetCells { part } => match self.cells.lock().await.chunk(part) {
+                Some(chunk) => command::ok(id, Some(&chunk)),
+                None => command::error(Some(id), "no_such_part", None),
+            },
+            Command::ClearFaults => {
+                self.clear_faults().await;
+                command::ok(id, None)
+            }
+            Command::Reboot => {
+                warn!("Reboot requested over UART");
+                reboot = true;
+                command::ok(id, None)
+            }
+            Command::OpenContactor => {
+                self.force_open();
+                command::ok(id, None)
+            }
+            Command::Discovery => {
+                self.discovery_requested.store(true, Ordering::Relaxed);
+                command::ok(id, None)
+            }
+            Command::Autobaud { bus, save } => {
+                let started = self.start_autobaud(bus, save);
+                command::ok(id, Some(&json::to_string(&started)))
+            }
+        };
+        Reply { text, reboot }
+    }
+}
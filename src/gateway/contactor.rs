@@ -0,0 +1,64 @@
+use super::{ContactorStatus, Gateway};
+use crate::contactor::{Command, Contactor, Inputs, Outputs};
+use crate::fmt::Debug2Format;
+use core::sync::atomic::Ordering;
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::{Duration, Instant, Timer};
+
+/// Contactor state machine step interval
+pub const STEP_MS: u64 = 20;
+
+/// Run the contactor state machine, `drive` applies the outputs to the relays
+pub async fn run<M: RawMutex, F: FnMut(&Outputs)>(gateway: &Gateway<M>, mut drive: F) -> ! {
+    warn!("Starting Contactor");
+    let mut contactor = Contactor::new();
+    // relay drive last applied, the limit scale changes every step while ramping
+    let mut last = None;
+    loop {
+        let before = contactor.state();
+        let step = Timer::after(Duration::from_millis(STEP_MS));
+        let command = match select(gateway.contactor_state.wait(), step).await {
+            Either::First(command) => Some(command),
+            Either::Second(_) => None,
+        };
+        // the inverter loop overwrites the signal every cycle, a `ForceOpen` or `Reset`
+        // sent on it can be replaced by `Open`/`Close`, so both are read from their source
+        let now = Instant::now().as_millis();
+        if gateway.contactor_reset.swap(false, Ordering::Relaxed) {
+            contactor.command(Command::Reset, now);
+        }
+        if gateway.hold_open().await {
+            contactor.command(Command::ForceOpen, now);
+        } else if let Some(command) = command {
+            contactor.command(command, now);
+        }
+
+        let inputs = {
+            let state = gateway.battery_state.lock().await;
+            Inputs {
+                pack_volts: state.pack_volts,
+                dc_link_volts: *gateway.dc_link_volts.lock().await,
+                current: state.current,
+            }
+        };
+        let outputs = contactor.step(&inputs, Instant::now().as_millis());
+        if last != Some((outputs.precharge, outputs.main)) {
+            drive(&outputs);
+            last = Some((outputs.precharge, outputs.main));
+        }
+        *gateway.contactor.lock().await = ContactorStatus {
+            state: contactor.state(),
+            limit_scale: outputs.limit_scale,
+        };
+
+        if contactor.state() != before {
+            match contactor.state().is_fault() {
+                true => error!("Contactor fault: {}", Debug2Format(&contactor.state())),
+                false => info!("Contactor {}", Debug2Format(&contactor.state())),
+            }
+            gateway.mqtt.lock().await.update_contactor(contactor.state());
+            gateway.send_mqtt.signal(true);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use super::Gateway;
+use crate::candump::Bus;
+use crate::config::State;
+use crate::fmt::Debug2Format;
+use crate::health::{Esr, Monitor};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::Instant;
+
+impl<M: RawMutex> Gateway<M> {
+    /// Feed one ESR reading of `bus` through its `monitor`, publish the counters and
+    /// move `State` on error passive/bus-off transitions. Returns true when the
+    /// controller should be restarted to leave bus-off.
+    pub async fn bus_error_status(&self, bus: Bus, monitor: &mut Monitor, esr: Esr) -> bool {
+        let mut config = self.config.lock().await;
+        let update = monitor.update(esr, config.bus_off_backoff_ms(), Instant::now().as_millis());
+        let health = monitor.health();
+        self.bus_health.lock().await[bus.index()] = health;
+        self.mqtt.lock().await.update_bus(bus, health);
+
+        if update.changed {
+            warn!(
+                "{} error state {}, TEC {} REC {} LEC {}",
+                bus.iface(),
+                Debug2Format(&health.state),
+                health.tec,
+                health.rec,
+                Debug2Format(&health.lec)
+            );
+            let fault = match bus {
+                Bus::Bms => State::BmsFault,
+                Bus::Inverter => State::InvFault,
+            };
+            if health.state.is_fault() {
+                // a BMS fault, e.g. a protection trip, outranks the inverter bus
+                if *config.state() != State::BmsFault {
+                    config.set_state(fault);
+                }
+            } else if *config.state() == fault && !self.tripped().await {
+                config.set_state(State::Offline);
+            }
+            self.send_mqtt.signal(true);
+        }
+        if update.recover {
+            warn!("{} bus-off, restarting the controller", bus.iface());
+        }
+        update.recover
+    }
+}
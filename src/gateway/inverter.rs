@@ -0,0 +1,95 @@
+use super::Gateway;
+use crate::config::InverterType;
+use crate::contactor::Command;
+use crate::fmt::Debug2Format;
+use crate::inverter::{FaultAction, InverterProtocol};
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::Ticker;
+
+/// Run the protocol for `inverter`, returns only if it is not compiled in
+pub async fn run_inverter<M: RawMutex>(gateway: &Gateway<M>, inverter: InverterType) {
+    match inverter {
+        #[cfg(feature = "byd")]
+        InverterType::Byd => run(gateway, crate::inverter::byd::Byd::default()).await,
+        #[cfg(feature = "pylontech")]
+        InverterType::Pylontech => {
+            run(gateway, crate::inverter::pylontech::Pylontech::default()).await
+        }
+        #[cfg(feature = "solax")]
+        InverterType::Solax => run(gateway, crate::inverter::solax::Solax::default()).await,
+        #[allow(unreachable_patterns)]
+        other => error!("Inverter {} not compiled in", Debug2Format(&other)),
+    }
+}
+
+/// Drive an inverter protocol over the gateway's inverter channels
+pub async fn run<M: RawMutex, P: InverterProtocol>(gateway: &Gateway<M>, mut protocol: P) -> ! {
+    warn!("Starting Inverter Processor");
+    let recv = gateway.inverter_rx.receiver();
+    let trans = gateway.inverter_tx.sender();
+    let mut ticker = protocol.period().map(Ticker::every);
+
+    loop {
+        // None = periodic tick
+        let frame = match ticker.as_mut() {
+            Some(ticker) => match select(recv.recv(), ticker.next()).await {
+                Either::First(frame) => Some(frame),
+                Either::Second(_) => None,
+            },
+            None => Some(recv.recv().await),
+        };
+
+        if gateway.last_bms_message.lock().await.elapsed().as_secs() > protocol.bms_timeout_secs()
+        {
+            error!("BMS last update timeout, inverter communications stopped");
+            gateway.contactor_state.signal(Command::Open);
+            continue;
+        };
+
+        // limits follow the contactor, zero until closed and ramped down before opening
+        let mut state = { *gateway.battery_state.lock().await };
+        let limit_scale = gateway.contactor.lock().await.limit_scale;
+        state.charge_max *= limit_scale;
+        state.discharge_max *= limit_scale;
+        let response = match frame {
+            Some(frame) => protocol.on_frame(&frame, &state),
+            None => protocol.on_tick(&state),
+        };
+        *gateway.dc_link_volts.lock().await = protocol.dc_link_volts();
+
+        let inverter_comms_valid = match response {
+            Ok(frames) if frames.is_empty() => continue,
+            Ok(frames) => {
+                info!("Sending {} frames to inverter", frames.len());
+                for frame in frames {
+                    trans.send(frame).await;
+                }
+                // Send signal to push json data to UART
+                gateway.send_mqtt.signal(true);
+                true
+            }
+            Err(e) => match protocol.fault(&e) {
+                FaultAction::Continue => {
+                    warn!("Inverter protocol: {:?}", Debug2Format(&e));
+                    true
+                }
+                FaultAction::Skip => {
+                    error!("Inverter protocol error: {:?}", Debug2Format(&e));
+                    continue;
+                }
+                FaultAction::OpenContactor => {
+                    error!("Critical inverter fault: {:?}", Debug2Format(&e));
+                    false // disable contactor
+                }
+            },
+        };
+
+        // a protection trip or forced open overrides the inverter side until cleared
+        let command = match inverter_comms_valid && !gateway.hold_open().await {
+            true => Command::Close,
+            false => Command::Open,
+        };
+        gateway.contactor_state.signal(command);
+    }
+}
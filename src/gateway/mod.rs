@@ -0,0 +1,203 @@
+//! Shared state and the generic processor loops.
+//!
+//! The firmware holds one `Gateway` in a static, the host simulator builds its own,
+//! both run the same `battery::run_battery` and `inverter::run_inverter` loops.
+
+use crate::battery::BatteryState;
+use crate::candump::{Bus, Record};
+use crate::config::{Config, State};
+use crate::contactor::{Command, ContactorState};
+use crate::fmt::Debug2Format;
+use crate::health::BusHealth;
+use crate::mqtt::cells::CellSnapshot;
+use crate::mqtt::discovery::DeviceId;
+use crate::mqtt::stats::Stats;
+use crate::mqtt::MqttFormat;
+use crate::protection::Protection;
+use crate::timing::DEFAULT_BITRATE;
+use bxcan::Frame;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use embassy_time::Instant;
+
+pub mod autobaud;
+pub mod battery;
+pub mod binary;
+pub mod command;
+pub mod contactor;
+pub mod health;
+pub mod inverter;
+pub mod modbus;
+pub mod telemetry;
+
+/// Channels and stores shared between the CAN interfaces, processors and UART
+pub struct Gateway<M: RawMutex> {
+    /// Frames received from the inverter bus
+    pub inverter_rx: Channel<M, Frame, 2>,
+    /// Frames queued for the inverter bus
+    pub inverter_tx: Channel<M, Frame, 20>,
+    /// Frames received from the BMS bus
+    pub bms_rx: Channel<M, Frame, 20>,
+    /// Frames queued for the BMS bus
+    pub bms_tx: Channel<M, Frame, 20>,
+    /// Latest reading from the battery driver
+    pub battery_state: Mutex<M, BatteryState>,
+    /// Per-cell data behind `battery_state`
+    pub cells: Mutex<M, CellSnapshot>,
+    /// Time of the last valid BMS frame, the BMS watchdog
+    pub last_bms_message: Mutex<M, Instant>,
+    /// Requests for the contactor state machine
+    pub contactor_state: Signal<M, Command>,
+    /// Reported back by the contactor task
+    pub contactor: Mutex<M, ContactorStatus>,
+    /// Contactor held open from the UART until faults are cleared
+    pub forced_open: AtomicBool,
+    /// Clear latched contactor faults on the next contactor step
+    pub contactor_reset: AtomicBool,
+    /// Inverter side voltage for the precharge check, if the protocol reports it
+    pub dc_link_volts: Mutex<M, Option<f32>>,
+    /// Fresh data is ready for the UART
+    pub send_mqtt: Signal<M, bool>,
+    pub mqtt: Mutex<M, MqttFormat>,
+    /// Home Assistant device id, set from the MCU UID at boot
+    pub device_id: Mutex<M, DeviceId>,
+    /// Resend the Home Assistant discovery payloads
+    pub discovery_requested: AtomicBool,
+    /// Statistics for the current telemetry period
+    pub stats: Mutex<M, Stats>,
+    pub config: Mutex<M, Config>,
+    /// Config changed, persist it
+    pub save_config: Signal<M, ()>,
+    /// Limit checks on every reading, a latched trip keeps the contactor open
+    pub protection: Mutex<M, Protection>,
+    /// Frames seen on either bus, for candump output over UART
+    pub capture: Channel<M, Record, 32>,
+    /// Mirrors `Config::candump`, checked for every frame
+    pub capture_enabled: AtomicBool,
+    /// An SLCAN or GVRET bridge on the UART is forwarding frames of the bus, indexed
+    /// by `Bus::index`
+    pub bridge_open: [AtomicBool; 2],
+    /// CAN error counters and state, indexed by `Bus::index`
+    pub bus_health: Mutex<M, [BusHealth; 2]>,
+    /// Bit/s each bus runs at, indexed by `Bus::index`, set when its controller starts
+    pub bitrates: [AtomicU32; 2],
+    /// Start a bitrate search on the bus, indexed by `Bus::index`, true to save the result
+    pub autobaud: [Signal<M, bool>; 2],
+    /// Finished search waiting to be published
+    pub autobaud_report: Mutex<M, Option<autobaud::AutobaudReport>>,
+}
+
+/// Contactor state and the current limit scale applied while it is ramping down
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ContactorStatus {
+    pub state: ContactorState,
+    pub limit_scale: f32,
+}
+
+impl<M: RawMutex> Gateway<M> {
+    pub fn new() -> Self {
+        Self {
+            inverter_rx: Channel::new(),
+            inverter_tx: Channel::new(),
+            bms_rx: Channel::new(),
+            bms_tx: Channel::new(),
+            battery_state: Mutex::new(BatteryState::default()),
+            cells: Mutex::new(CellSnapshot::default()),
+            last_bms_message: Mutex::new(Instant::now()),
+            contactor_state: Signal::new(),
+            contactor: Mutex::new(ContactorStatus::default()),
+            forced_open: AtomicBool::new(false),
+            contactor_reset: AtomicBool::new(false),
+            dc_link_volts: Mutex::new(None),
+            send_mqtt: Signal::new(),
+            mqtt: Mutex::new(MqttFormat::default()),
+            device_id: Mutex::new(DeviceId::default()),
+            discovery_requested: AtomicBool::new(false),
+            stats: Mutex::new(Stats::new(Instant::now().as_millis())),
+            config: Mutex::new(Config::default()),
+            save_config: Signal::new(),
+            protection: Mutex::new(Protection::new()),
+            capture: Channel::new(),
+            capture_enabled: AtomicBool::new(false),
+            bridge_open: [AtomicBool::new(false), AtomicBool::new(false)],
+            bus_health: Mutex::new([BusHealth::default(); 2]),
+            bitrates: [
+                AtomicU32::new(DEFAULT_BITRATE),
+                AtomicU32::new(DEFAULT_BITRATE),
+            ],
+            autobaud: [Signal::new(), Signal::new()],
+            autobaud_report: Mutex::new(None),
+        }
+    }
+
+    /// Running bitrate of `bus`, bit/s
+    pub fn bitrate(&self, bus: Bus) -> u32 {
+        self.bitrates[bus.index()].load(Ordering::Relaxed)
+    }
+
+    /// Frames of `bus` go out over the UART, candump or a bridge, so its hardware
+    /// filters should let every ID through
+    pub fn sniffing(&self, bus: Bus) -> bool {
+        self.capture_enabled.load(Ordering::Relaxed)
+            || self.bridge_open[bus.index()].load(Ordering::Relaxed)
+    }
+
+    /// Queue a received or transmitted frame for capture or SLCAN, dropped if the queue is full
+    pub fn capture(&self, bus: Bus, frame: &Frame) {
+        if !self.sniffing(bus) {
+            return;
+        }
+        let record = Record {
+            at_us: Instant::now().as_micros(),
+            bus,
+            frame: frame.clone(),
+        };
+        if self.capture.try_send(record).is_err() {
+            debug!("Capture queue full, frame dropped");
+        }
+    }
+
+    /// Publish a fresh BMS reading to the inverter processors and the MQTT store,
+    /// opening the contactor if it trips the protection
+    pub async fn push_battery_state(&self, state: BatteryState) {
+        *self.battery_state.lock().await = state;
+        let status = if state.valid {
+            let now_ms = Instant::now().as_millis();
+            self.stats.lock().await.record(&state, now_ms);
+            let mut config = self.config.lock().await;
+            let status = self.protection.lock().await.evaluate(&config, &state, now_ms);
+            if status.tripped_now {
+                config.set_state(State::BmsFault);
+                self.contactor_state.signal(Command::ForceOpen);
+                error!("Protection trip: {}", Debug2Format(&status.cause));
+            }
+            Some(status)
+        } else {
+            None
+        };
+        let mut mqtt = self.mqtt.lock().await;
+        mqtt.update(&state);
+        if let Some(status) = status {
+            mqtt.update_protection(&status, self.protection.lock().await.trip());
+        }
+    }
+
+    /// A latched protection trip is holding the contactor open
+    pub async fn tripped(&self) -> bool {
+        self.protection.lock().await.trip().is_some()
+    }
+
+    /// The contactor must stay open whatever the inverter side wants
+    pub async fn hold_open(&self) -> bool {
+        self.forced_open.load(Ordering::Relaxed) || self.tripped().await
+    }
+}
+
+impl<M: RawMutex> Default for Gateway<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
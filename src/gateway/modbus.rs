@@ -0,0 +1,42 @@
+use super::Gateway;
+use crate::modbus::map::{ContactorRequest, Snapshot, Status};
+use crate::modbus::{self, Adu};
+use core::sync::atomic::Ordering;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+
+impl<M: RawMutex> Gateway<M> {
+    /// Answer one Modbus RTU request for `unit`, applying any DoD or contactor writes
+    pub async fn modbus(&self, unit: u8, request: &[u8]) -> Option<Adu> {
+        let mut snapshot = self.modbus_snapshot().await;
+        let response = modbus::handle(unit, request, &mut snapshot);
+        if let Some(patch) = snapshot.config_patch() {
+            if let Err(e) = self.apply_config(&patch).await {
+                error!(
+                    "Modbus DoD write rejected: {}",
+                    crate::fmt::Debug2Format(&e)
+                );
+            }
+        }
+        match snapshot.contactor {
+            Some(ContactorRequest::Open) => self.force_open(),
+            Some(ContactorRequest::ClearFaults) => self.clear_faults().await,
+            None => (),
+        }
+        response
+    }
+
+    async fn modbus_snapshot(&self) -> Snapshot {
+        let (alarm, tripped) = {
+            let protection = self.protection.lock().await;
+            (protection.level(), protection.trip().is_some())
+        };
+        let status = Status {
+            contactor: self.contactor.lock().await.state,
+            alarm,
+            tripped,
+            forced_open: self.forced_open.load(Ordering::Relaxed),
+        };
+        let state = *self.battery_state.lock().await;
+        Snapshot::new(&state, &*self.config.lock().await, status)
+    }
+}
@@ -0,0 +1,69 @@
+use super::Gateway;
+use crate::mqtt::schedule::{Alarm, Scheduler};
+use crate::mqtt::{discovery, Topic};
+use core::sync::atomic::Ordering;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::Instant;
+use heapless::Vec;
+use miniserde::__private::String;
+use miniserde::json;
+
+impl<M: RawMutex> Gateway<M> {
+    /// Topics due for publishing, see `Scheduler::due`, and a pending bitrate search
+    /// report, which stays pending until a JSON output renders it
+    pub async fn telemetry_due(&self, scheduler: &mut Scheduler) -> Vec<Topic, 6> {
+        if self.discovery_requested.swap(false, Ordering::Relaxed) {
+            scheduler.request_discovery();
+        }
+        let alarm = self.alarm().await;
+        let mut topics = {
+            let config = self.config.lock().await;
+            scheduler.due(&config, alarm, Instant::now().as_millis())
+        };
+        if self.autobaud_report.lock().await.is_some() {
+            let _ = topics.push(Topic::Autobaud);
+        }
+        topics
+    }
+
+    /// Telemetry line `part` of `topic`, `{"topic":..,"data":..}`. Only `Cells` and
+    /// `Discovery` have more than one part, render and send them one at a time until `None`.
+    pub async fn render(&self, topic: Topic, part: usize) -> Option<String> {
+        let data = self.render_data(topic, part).await?;
+        Some(topic.envelope(&data))
+    }
+
+    /// `data` of `render` without the envelope, for publishing to `<base>/<topic>`
+    pub async fn render_data(&self, topic: Topic, part: usize) -> Option<String> {
+        if part > 0 && !matches!(topic, Topic::Cells | Topic::Discovery) {
+            return None;
+        }
+        Some(match topic {
+            Topic::Summary => self.mqtt.lock().await.device_update_msg(),
+            Topic::Cells => self.cells.lock().await.chunk(part)?,
+            Topic::Alarm => json::to_string(&self.alarm().await),
+            Topic::Discovery => discovery::render(&*self.device_id.lock().await, part)?,
+            Topic::Stats => {
+                let period = self.stats.lock().await.take(Instant::now().as_millis());
+                json::to_string(&period)
+            }
+            Topic::Autobaud => json::to_string(&self.autobaud_report.lock().await.take()?),
+        })
+    }
+
+    async fn alarm(&self) -> Alarm {
+        let (alarm, trip) = {
+            let protection = self.protection.lock().await;
+            (protection.level(), protection.trip())
+        };
+        let [bms_bus, inverter_bus] = self.bus_health.lock().await.map(|bus| bus.state);
+        Alarm {
+            alarm,
+            trip,
+            contactor: self.contactor.lock().await.state,
+            state: *self.config.lock().await.state(),
+            bms_bus,
+            inverter_bus,
+        }
+    }
+}
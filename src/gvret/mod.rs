@@ -0,0 +1,304 @@
+//! GVRET, SavvyCAN's native binary protocol, with CAN1 (battery) as bus 0 and CAN2
+//! (inverter) as bus 1.
+//!
+//! The host sends `E7 E7` to switch to binary mode, then commands `F1 <cmd> <args>`.
+//! Multi-byte fields are little-endian, IDs carry bit 31 for extended frames.
+//!
+//! ```text
+//! host -> F1 00 <id:4> <bus> <len> <data..> <0>       transmit
+//! gw   -> F1 00 <us:4> <id:4> <len | bus << 4> <data..> <0>
+//! host -> F1 06                                        bus params
+//! gw   -> F1 06 <flags> <speed:4> <flags> <speed:4>
+//! ```
+
+use crate::candump::{Bus, Record};
+use bxcan::{Data, ExtendedId, Frame, Id, StandardId};
+use heapless::Vec;
+
+/// First byte sent by SavvyCAN, switches the UART to GVRET
+pub const ENTER_BINARY: u8 = 0xE7;
+const START: u8 = 0xF1;
+const EXTENDED: u32 = 1 << 31;
+
+const BUILD_CAN_FRAME: u8 = 0x00;
+const TIME_SYNC: u8 = 0x01;
+const DIG_INPUTS: u8 = 0x02;
+const ANA_INPUTS: u8 = 0x03;
+const SET_DIG_OUT: u8 = 0x04;
+const SETUP_CANBUS: u8 = 0x05;
+const GET_CANBUS_PARAMS: u8 = 0x06;
+const GET_DEVICE_INFO: u8 = 0x07;
+const SET_SINGLEWIRE_MODE: u8 = 0x08;
+const KEEPALIVE: u8 = 0x09;
+const SET_SYSTYPE: u8 = 0x0A;
+const ECHO_CAN_FRAME: u8 = 0x0B;
+const GET_NUMBUSES: u8 = 0x0C;
+const GET_EXT_BUSES: u8 = 0x0D;
+const SET_EXT_BUSES: u8 = 0x0E;
+
+/// Longest message either way, a frame with 8 data bytes
+pub type Message = Vec<u8, 20>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Request {
+    /// Send `frame` on `bus`
+    Transmit { bus: Bus, frame: Frame },
+    /// Send `frame` back to the host as if it was received on `bus`
+    Echo { bus: Bus, frame: Frame },
+    /// Requested speed per bus, 0 if unchanged
+    SetupCanbus([u32; 2]),
+    /// Needs the reply from `reply`
+    Query(u8),
+    /// Accepted and ignored, e.g. digital outputs
+    Ignored,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    Command,
+    Args { command: u8, need: usize },
+}
+
+/// Splits the host byte stream into requests
+pub struct Decoder {
+    state: State,
+    args: Vec<u8, 16>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            state: State::Idle,
+            args: Vec::new(),
+        }
+    }
+
+    /// Drop a partly received command, after input was lost the next byte cannot be
+    /// trusted to belong to it
+    pub fn reset(&mut self) {
+        self.state = State::Idle;
+    }
+
+    pub fn push(&mut self, byte: u8) -> Option<Request> {
+        match self.state {
+            // `E7` and anything else outside a command is skipped
+            State::Idle => {
+                if byte == START {
+                    self.state = State::Command;
+                }
+                None
+            }
+            State::Command => {
+                self.args.clear();
+                let need = match byte {
+                    BUILD_CAN_FRAME | ECHO_CAN_FRAME => 6,
+                    SET_DIG_OUT | SET_SINGLEWIRE_MODE | SET_SYSTYPE => 1,
+                    SETUP_CANBUS => 8,
+                    SET_EXT_BUSES => 12,
+                    _ => 0,
+                };
+                self.state = State::Args {
+                    command: byte,
+                    need,
+                };
+                if need == 0 {
+                    return self.finish(byte);
+                }
+                None
+            }
+            State::Args { command, need } => {
+                let _ = self.args.push(byte);
+                if self.args.len() < need {
+                    return None;
+                }
+                // the frame header gives the data length, then a checksum byte follows
+                if matches!(command, BUILD_CAN_FRAME | ECHO_CAN_FRAME) && need == 6 {
+                    let need = need + (self.args[5] & 0x0F).min(8) as usize + 1;
+                    self.state = State::Args { command, need };
+                    return None;
+                }
+                self.finish(command)
+            }
+        }
+    }
+
+    fn finish(&mut self, command: u8) -> Option<Request> {
+        self.state = State::Idle;
+        let args = &self.args;
+        let word =
+            |at: usize| u32::from_le_bytes([args[at], args[at + 1], args[at + 2], args[at + 3]]);
+        Some(match command {
+            BUILD_CAN_FRAME | ECHO_CAN_FRAME => {
+                let bus = match args[4] {
+                    0 => Bus::Bms,
+                    1 => Bus::Inverter,
+                    _ => return None,
+                };
+                let len = args.len() - 7;
+                let frame = frame(word(0), &args[6..6 + len])?;
+                match command {
+                    BUILD_CAN_FRAME => Request::Transmit { bus, frame },
+                    _ => Request::Echo { bus, frame },
+                }
+            }
+            SETUP_CANBUS => Request::SetupCanbus([word(0) & 0xF_FFFF, word(4) & 0xF_FFFF]),
+            TIME_SYNC | DIG_INPUTS | ANA_INPUTS | GET_CANBUS_PARAMS | GET_DEVICE_INFO
+            | KEEPALIVE | GET_NUMBUSES | GET_EXT_BUSES => Request::Query(command),
+            _ => Request::Ignored,
+        })
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn frame(raw: u32, data: &[u8]) -> Option<Frame> {
+    let id: Id = match raw & EXTENDED {
+        0 => StandardId::new(raw as u16)?.into(),
+        _ => ExtendedId::new(raw & !EXTENDED)?.into(),
+    };
+    Some(Frame::new_data(id, Data::new(data)?))
+}
+
+/// Answer to `Request::Query(command)`, `now_us` for time sync and `bitrates` of
+/// CAN1, CAN2 for the bus parameters
+pub fn reply(command: u8, now_us: u64, bitrates: [u32; 2], out: &mut Message) {
+    out.clear();
+    let _ = out.extend_from_slice(&[START, command]);
+    let _ = match command {
+        TIME_SYNC => out.extend_from_slice(&(now_us as u32).to_le_bytes()),
+        DIG_INPUTS => out.extend_from_slice(&[0, 0]),
+        ANA_INPUTS => out.extend_from_slice(&[0; 15]),
+        GET_CANBUS_PARAMS => {
+            // enabled, not listen-only
+            let mut params = [0; 10];
+            for (bus, bitrate) in params.chunks_mut(5).zip(bitrates) {
+                bus[0] = 1;
+                bus[1..].copy_from_slice(&bitrate.to_le_bytes());
+            }
+            out.extend_from_slice(&params)
+        }
+        // build number, EEPROM version, file output type, auto start, single wire
+        GET_DEVICE_INFO => out.extend_from_slice(&[0x6A, 0x01, 0, 0, 0, 0]),
+        KEEPALIVE => out.extend_from_slice(&[0xDE, 0xAD]),
+        GET_NUMBUSES => out.push(2).map_err(|_| ()),
+        GET_EXT_BUSES => out.extend_from_slice(&[0; 16]),
+        _ => Ok(()),
+    };
+}
+
+/// Frame message for a captured frame. Remote frames are sent without data.
+pub fn write_frame(out: &mut Message, record: &Record) {
+    out.clear();
+    let _ = out.extend_from_slice(&[START, BUILD_CAN_FRAME]);
+    let _ = out.extend_from_slice(&(record.at_us as u32).to_le_bytes());
+    let id = match record.frame.id() {
+        Id::Standard(id) => id.as_raw() as u32,
+        Id::Extended(id) => id.as_raw() | EXTENDED,
+    };
+    let _ = out.extend_from_slice(&id.to_le_bytes());
+    let data = record.frame.data().map(|data| &data[..]).unwrap_or(&[]);
+    let bus = match record.bus {
+        Bus::Bms => 0,
+        Bus::Inverter => 1,
+    };
+    let _ = out.push(data.len() as u8 | bus << 4);
+    let _ = out.extend_from_slice(data);
+    let _ = out.push(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> std::vec::Vec<Request> {
+        let mut decoder = Decoder::new();
+        bytes
+            .iter()
+            .filter_map(|byte| decoder.push(*byte))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_transmit_and_queries() {
+        let requests = decode(&[
+            0xE7, 0xE7, 0xF1, 0x09, // keepalive
+            0xF1, 0x00, 0xDB, 0xF1, 0xDA, 0x98, 0x00, 0x02, 0x03, 0x22, 0x00, // frame
+            0xF1, 0x06, // params
+        ]);
+        let frame = Frame::new_data(ExtendedId::new(0x18DAF1DB).unwrap(), [0x03, 0x22]);
+        assert_eq!(
+            requests,
+            [
+                Request::Query(KEEPALIVE),
+                Request::Transmit {
+                    bus: Bus::Bms,
+                    frame
+                },
+                Request::Query(GET_CANBUS_PARAMS),
+            ]
+        );
+    }
+
+    #[test]
+    fn reset_resynchronises_after_lost_bytes() {
+        let mut decoder = Decoder::new();
+        // a frame whose last three bytes were lost
+        for byte in [0xF1, 0x00, 0xDB, 0xF1, 0xDA, 0x98, 0x00, 0x02, 0x03] {
+            assert_eq!(decoder.push(byte), None);
+        }
+        decoder.reset();
+        let requests: std::vec::Vec<_> = [0xF1, 0x09, 0xF1, 0x0C]
+            .iter()
+            .filter_map(|byte| decoder.push(*byte))
+            .collect();
+        assert_eq!(
+            requests,
+            [Request::Query(KEEPALIVE), Request::Query(GET_NUMBUSES)]
+        );
+    }
+
+    #[test]
+    fn decodes_setup_and_skips_ignored_args() {
+        let requests = decode(&[
+            0xF1, 0x05, 0x20, 0xA1, 0x07, 0xC0, 0x90, 0xD0, 0x03, 0xC0, // 500k, 250k
+            0xF1, 0x0A, 0x01, // systype
+            0xF1, 0x0C,
+        ]);
+        assert_eq!(
+            requests,
+            [
+                Request::SetupCanbus([500_000, 250_000]),
+                Request::Ignored,
+                Request::Query(GET_NUMBUSES),
+            ]
+        );
+    }
+
+    #[test]
+    fn encodes_frames_and_replies() {
+        let record = Record {
+            at_us: 0x0102_0304,
+            bus: Bus::Inverter,
+            frame: Frame::new_data(StandardId::new(0x351).unwrap(), [0xAA]),
+        };
+        let mut out = Message::new();
+        write_frame(&mut out, &record);
+        assert_eq!(
+            out,
+            [0xF1, 0x00, 0x04, 0x03, 0x02, 0x01, 0x51, 0x03, 0, 0, 0x11, 0xAA, 0]
+        );
+
+        reply(GET_CANBUS_PARAMS, 0, [500_000, 250_000], &mut out);
+        assert_eq!(
+            out,
+            [0xF1, 0x06, 1, 0x20, 0xA1, 0x07, 0, 1, 0x90, 0xD0, 0x03, 0]
+        );
+        reply(KEEPALIVE, 0, [500_000; 2], &mut out);
+        assert_eq!(out, [0xF1, 0x09, 0xDE, 0xAD]);
+    }
+}
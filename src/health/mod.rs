@@ -0,0 +1,238 @@
+//! CAN error monitoring from the bxCAN error status register (ESR), with bus-off recovery.
+//!
+//! The controller counts transmit and receive errors (TEC/REC): error warning from 96,
+//! error passive from 128 and bus-off once TEC passes 255. Automatic bus-off management
+//! stays disabled, so a bus-off controller is restarted by `Monitor` after a backoff that
+//! starts at `Config::bus_off_backoff_ms` min and doubles on each attempt up to max. A
+//! bus that stays error active for the max backoff starts over at min.
+
+use crate::config::MinMax;
+use miniserde::Serialize;
+
+/// Fault confinement state, worst flag set in ESR
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub enum ErrorState {
+    #[default]
+    Active,
+    Warning,
+    Passive,
+    BusOff,
+}
+
+impl ErrorState {
+    /// Passive or bus-off, the bus is not usable for the protocol
+    pub fn is_fault(self) -> bool {
+        matches!(self, ErrorState::Passive | ErrorState::BusOff)
+    }
+}
+
+/// ESR last error code
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub enum LastError {
+    #[default]
+    None,
+    Stuff,
+    Form,
+    Ack,
+    BitRecessive,
+    BitDominant,
+    Crc,
+    /// Set by software, not seen from the hardware
+    Software,
+}
+
+/// Decoded ESR
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Esr {
+    pub tec: u8,
+    pub rec: u8,
+    pub lec: LastError,
+    pub state: ErrorState,
+}
+
+impl Esr {
+    pub fn from_bits(bits: u32) -> Self {
+        let state = if bits & 0b100 != 0 {
+            ErrorState::BusOff
+        } else if bits & 0b010 != 0 {
+            ErrorState::Passive
+        } else if bits & 0b001 != 0 {
+            ErrorState::Warning
+        } else {
+            ErrorState::Active
+        };
+        let lec = match (bits >> 4) & 0b111 {
+            0 => LastError::None,
+            1 => LastError::Stuff,
+            2 => LastError::Form,
+            3 => LastError::Ack,
+            4 => LastError::BitRecessive,
+            5 => LastError::BitDominant,
+            6 => LastError::Crc,
+            _ => LastError::Software,
+        };
+        Self {
+            tec: (bits >> 16) as u8,
+            rec: (bits >> 24) as u8,
+            lec,
+            state,
+        }
+    }
+}
+
+/// Published per bus in the summary
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct BusHealth {
+    pub state: ErrorState,
+    pub tec: u8,
+    pub rec: u8,
+    pub lec: LastError,
+    /// Bus-off events since boot
+    pub bus_offs: u16,
+}
+
+/// Result of one ESR reading
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Update {
+    /// `BusHealth::state` changed
+    pub changed: bool,
+    /// Restart the controller now to leave bus-off
+    pub recover: bool,
+}
+
+/// Error state and bus-off recovery for one bus
+pub struct Monitor {
+    health: BusHealth,
+    /// Current bus-off began, or the last recovery attempt
+    off_since_ms: Option<u64>,
+    active_since_ms: Option<u64>,
+    backoff_ms: Option<u32>,
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Self {
+            health: BusHealth::default(),
+            off_since_ms: None,
+            active_since_ms: None,
+            backoff_ms: None,
+        }
+    }
+
+    pub fn health(&self) -> BusHealth {
+        self.health
+    }
+
+    pub fn update(&mut self, esr: Esr, backoff: &MinMax<u16>, now_ms: u64) -> Update {
+        let previous = self.health.state;
+        self.health = BusHealth {
+            state: esr.state,
+            tec: esr.tec,
+            rec: esr.rec,
+            lec: esr.lec,
+            bus_offs: self.health.bus_offs,
+        };
+        let (min, max) = (backoff.min() as u32, backoff.max() as u32);
+        let mut recover = false;
+        if esr.state == ErrorState::BusOff {
+            self.active_since_ms = None;
+            if previous != ErrorState::BusOff {
+                self.health.bus_offs = self.health.bus_offs.saturating_add(1);
+            }
+            let since = *self.off_since_ms.get_or_insert(now_ms);
+            let wait = *self.backoff_ms.get_or_insert(min);
+            if now_ms.saturating_sub(since) >= wait as u64 {
+                recover = true;
+                self.off_since_ms = Some(now_ms);
+                self.backoff_ms = Some((wait * 2).min(max));
+            }
+        } else {
+            self.off_since_ms = None;
+            if esr.state == ErrorState::Active {
+                let since = *self.active_since_ms.get_or_insert(now_ms);
+                if now_ms.saturating_sub(since) >= max as u64 {
+                    self.backoff_ms = None;
+                }
+            } else {
+                self.active_since_ms = None;
+            }
+        }
+        Update {
+            changed: previous != esr.state,
+            recover,
+        }
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    /// BOFF, EPVF and EWGF set, LEC ack, TEC 248
+    const BUS_OFF: u32 = 0x00F8_0037;
+    const ACTIVE: u32 = 0x0200_0000;
+
+    #[test]
+    fn decodes_esr() {
+        let esr = Esr::from_bits(0x0380_0033);
+        assert_eq!(esr.state, ErrorState::Passive);
+        assert_eq!((esr.tec, esr.rec), (0x80, 0x03));
+        assert_eq!(esr.lec, LastError::Ack);
+        assert_eq!(Esr::from_bits(BUS_OFF).state, ErrorState::BusOff);
+        assert_eq!(Esr::from_bits(ACTIVE).state, ErrorState::Active);
+    }
+
+    #[test]
+    fn recovery_backs_off() {
+        let config = Config::default(); // 100 ms .. 10 s
+        let backoff = config.bus_off_backoff_ms();
+        let mut monitor = Monitor::new();
+        let bus_off = Esr::from_bits(BUS_OFF);
+
+        let update = monitor.update(bus_off, backoff, 0);
+        assert_eq!(
+            update,
+            Update {
+                changed: true,
+                recover: false
+            }
+        );
+        assert!(!monitor.update(bus_off, backoff, 99).recover);
+        assert!(monitor.update(bus_off, backoff, 100).recover);
+        // still off after the restart, next attempt waits twice as long
+        assert!(!monitor.update(bus_off, backoff, 299).recover);
+        assert!(monitor.update(bus_off, backoff, 300).recover);
+        assert_eq!(monitor.health().bus_offs, 1);
+
+        // recovered briefly, then off again: the backoff keeps growing
+        monitor.update(Esr::from_bits(ACTIVE), backoff, 310);
+        monitor.update(bus_off, backoff, 320);
+        assert!(!monitor.update(bus_off, backoff, 719).recover);
+        assert!(monitor.update(bus_off, backoff, 720).recover);
+        assert_eq!(monitor.health().bus_offs, 2);
+    }
+
+    #[test]
+    fn backoff_resets_after_a_quiet_period() {
+        let config = Config::default();
+        let backoff = config.bus_off_backoff_ms();
+        let mut monitor = Monitor::new();
+        let bus_off = Esr::from_bits(BUS_OFF);
+        monitor.update(bus_off, backoff, 0);
+        monitor.update(bus_off, backoff, 100);
+        monitor.update(bus_off, backoff, 300);
+
+        monitor.update(Esr::from_bits(ACTIVE), backoff, 1_000);
+        let update = monitor.update(Esr::from_bits(ACTIVE), backoff, 11_000);
+        assert!(!update.changed);
+        monitor.update(bus_off, backoff, 12_000);
+        assert!(monitor.update(bus_off, backoff, 12_100).recover);
+    }
+}
@@ -0,0 +1,159 @@
+use super::{frame_builder, FaultAction, InverterProtocol};
+use crate::battery::BatteryState;
+use crate::filter::Rule;
+use crate::Frames;
+use bxcan::Frame;
+use embassy_time::Duration;
+
+const SEND_EVERY_SECS: u64 = 1;
+const CHARGER_VOLTS_HIGH_VAL: u16 = 6500;
+const CHARGER_VOLTS_LOW_VAL: u16 = 4800;
+
+/// BYD HVS emulation, broadcasts the full frame set every second
+#[derive(Default)]
+pub struct Byd;
+
+impl InverterProtocol for Byd {
+    type Error = core::convert::Infallible;
+
+    // inverter status and identification, 0x151 carries the model
+    const FILTERS: &'static [Rule] = &[
+        Rule::standard(0x091),
+        Rule::standard(0x0d1),
+        Rule::standard(0x111),
+        Rule::standard(0x151),
+    ];
+
+    fn period(&self) -> Option<Duration> {
+        Some(Duration::from_secs(SEND_EVERY_SECS))
+    }
+
+    fn on_frame(&mut self, _frame: &Frame, _state: &BatteryState) -> Result<Frames, Self::Error> {
+        // inverter frames are not parsed yet
+        Ok(Frames::new())
+    }
+
+    fn on_tick(&mut self, state: &BatteryState) -> Result<Frames, Self::Error> {
+        // BYD wire format is 0.1 units, big endian
+        let deci = |v: f32| (v * 10.0) as i16;
+        let charge_volts_high = CHARGER_VOLTS_HIGH_VAL.to_le_bytes();
+        let charge_volts_low = CHARGER_VOLTS_LOW_VAL.to_le_bytes();
+        let charge_current = deci(state.charge_max).to_le_bytes();
+        let discharge_current = deci(state.discharge_max).to_le_bytes();
+        let soc = deci(state.kwh_remaining).to_le_bytes();
+        #[cfg(feature = "v65")]
+        let pack_volts = (deci(state.pack_volts) / 6).to_le_bytes();
+        #[cfg(not(feature = "v65"))]
+        let pack_volts = deci(state.pack_volts).to_le_bytes();
+        let current = deci(state.current).to_le_bytes();
+        let temp = deci(state.temp_avg).to_le_bytes();
+
+        let cap = (52000u16 / 65).to_le_bytes(); // capacity vs voltage
+        let temp_high = deci(state.cell_temp_high).to_le_bytes();
+        let temp_low = deci(state.cell_temp_low).to_le_bytes();
+
+        let frames: [Frame; 10] = [
+            frame_builder(0x618, &[0x0, b'B', b'Y', b'D', 0x0, 0x0, 0x0, 0x0]),
+            frame_builder(0x5d8, &[0x0, b'B', b'Y', b'D', 0x0, 0x0, 0x0, 0x0]),
+            frame_builder(0x558, &[0x3, 0x13, 0x0, 0x4, cap[1], cap[0], 0x5, 0x7]),
+            frame_builder(0x598, &[0x0, 0x0, 0x12, 0x34, 0x0, 0x0, 0x4, 0x4f]),
+            frame_builder(
+                0x358,
+                &[
+                    charge_volts_high[1],
+                    charge_volts_high[0],
+                    charge_volts_low[1],
+                    charge_volts_low[0],
+                    discharge_current[1],
+                    discharge_current[0],
+                    charge_current[1],
+                    charge_current[0],
+                ],
+            ),
+            frame_builder(
+                0x3d8,
+                &[soc[1], soc[0], soc[1], soc[0], 0x0, 0x0, 0xf9, 0x0],
+            ), // 4 & 5 are ampsecond * 0.00277 ??
+            frame_builder(0x458, &[0x0, 0x0, 0x12, 0x34, 0x0, 0x0, 0x56, 0x78]),
+            frame_builder(
+                0x518,
+                &[
+                    temp_high[1],
+                    temp_high[0],
+                    temp_low[1],
+                    temp_low[0],
+                    0xff,
+                    0xff,
+                    0xff,
+                    0xff,
+                ],
+            ),
+            frame_builder(
+                0x4d8,
+                &[
+                    pack_volts[1],
+                    pack_volts[0],
+                    current[1],
+                    current[0],
+                    temp[1],
+                    temp[0],
+                    0x3,
+                    0x8,
+                ],
+            ),
+            frame_builder(0x158, &[0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0, 0x0]),
+        ];
+        Ok(frames.into_iter().collect())
+    }
+
+    fn fault(&self, _error: &Self::Error) -> FaultAction {
+        FaultAction::Skip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bxcan::{Id, StandardId};
+
+    fn find(frames: &Frames, id: u16) -> [u8; 8] {
+        let frame = frames
+            .iter()
+            .find(|f| f.id() == Id::Standard(StandardId::new(id).unwrap()))
+            .unwrap();
+        frame.data().unwrap().as_ref().try_into().unwrap()
+    }
+
+    #[test]
+    fn encodes_limits_big_endian() {
+        let state = BatteryState {
+            charge_max: 25.0,
+            discharge_max: 40.5,
+            ..Default::default()
+        };
+        let frames = Byd.on_tick(&state).unwrap();
+        assert_eq!(frames.len(), 10);
+        let limits = find(&frames, 0x358);
+        assert_eq!(&limits[..4], &[0x19, 0x64, 0x12, 0xc0]);
+        assert_eq!(&limits[4..6], &405i16.to_be_bytes());
+        assert_eq!(&limits[6..], &250i16.to_be_bytes());
+    }
+
+    #[test]
+    fn encodes_pack_values() {
+        let state = BatteryState {
+            pack_volts: 390.0,
+            current: -12.5,
+            temp_avg: 21.0,
+            ..Default::default()
+        };
+        let frames = Byd.on_tick(&state).unwrap();
+        let pack = find(&frames, 0x4d8);
+        #[cfg(feature = "v65")]
+        assert_eq!(&pack[..2], &650i16.to_be_bytes());
+        #[cfg(not(feature = "v65"))]
+        assert_eq!(&pack[..2], &3900i16.to_be_bytes());
+        assert_eq!(&pack[2..4], &(-125i16).to_be_bytes());
+        assert_eq!(&pack[4..6], &210i16.to_be_bytes());
+    }
+}
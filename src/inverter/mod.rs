@@ -0,0 +1,85 @@
+use crate::battery::BatteryState;
+use crate::config::InverterType;
+use crate::filter::{self, Rule};
+use crate::Frames;
+use crate::LAST_READING_TIMEOUT_SECS;
+use bxcan::Frame;
+use embassy_time::Duration;
+use embedded_hal::can::{Id, StandardId};
+
+#[cfg(feature = "byd")]
+pub mod byd;
+
+#[cfg(feature = "pylontech")]
+pub mod pylontech;
+
+#[cfg(feature = "solax")]
+pub mod solax;
+
+/// What the inverter task should do with the contactor after a protocol error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultAction {
+    /// Non-critical, inverter comms are still valid
+    Continue,
+    /// Drop this step without touching the contactor
+    Skip,
+    /// Critical, open the contactor
+    OpenContactor,
+}
+
+/// An inverter-side CAN protocol, driven by `tasks::inverter::run`.
+///
+/// Implementations only translate between `BatteryState` and frames, the task owns
+/// the channels, timing, BMS timeout and contactor signalling.
+pub trait InverterProtocol {
+    type Error: core::fmt::Debug;
+
+    /// IDs read from the inverter, compiled into the CAN2 acceptance filters
+    const FILTERS: &'static [Rule] = filter::ACCEPT_ALL;
+
+    /// Interval for unsolicited frames, `None` for purely request/response protocols
+    fn period(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Seconds without a BMS update before inverter comms are stopped
+    fn bms_timeout_secs(&self) -> u64 {
+        LAST_READING_TIMEOUT_SECS
+    }
+
+    /// Handle a frame received from the inverter, returning any replies
+    fn on_frame(&mut self, frame: &Frame, state: &BatteryState) -> Result<Frames, Self::Error>;
+
+    /// Build the periodic frames, called every `period()`
+    fn on_tick(&mut self, _state: &BatteryState) -> Result<Frames, Self::Error> {
+        Ok(Frames::new())
+    }
+
+    /// Inverter DC-link voltage, used to check precharge before closing the contactor.
+    /// `None` falls back to a fixed precharge time.
+    fn dc_link_volts(&self) -> Option<f32> {
+        None
+    }
+
+    /// Map a protocol error onto a contactor action
+    fn fault(&self, error: &Self::Error) -> FaultAction;
+}
+
+/// Acceptance filter table of `inverter`, everything when it is not compiled in
+pub fn filters(inverter: InverterType) -> &'static [Rule] {
+    match inverter {
+        #[cfg(feature = "byd")]
+        InverterType::Byd => <byd::Byd as InverterProtocol>::FILTERS,
+        #[cfg(feature = "pylontech")]
+        InverterType::Pylontech => <pylontech::Pylontech as InverterProtocol>::FILTERS,
+        #[cfg(feature = "solax")]
+        InverterType::Solax => <solax::Solax as InverterProtocol>::FILTERS,
+        #[allow(unreachable_patterns)]
+        _ => filter::ACCEPT_ALL,
+    }
+}
+
+pub(crate) fn frame_builder(id: u16, framedata: &[u8]) -> Frame {
+    use embedded_hal::can::Frame as _;
+    Frame::new(Id::Standard(StandardId::new(id).unwrap()), framedata).unwrap()
+}
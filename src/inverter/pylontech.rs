@@ -0,0 +1,58 @@
+use super::{FaultAction, InverterProtocol};
+use crate::battery::BatteryState;
+use crate::filter::Rule;
+use crate::Frames;
+use bxcan::Frame;
+use embassy_time::Duration;
+use pylontech_protocol::messages::CanError;
+use pylontech_protocol::PylontechBms;
+
+/// Pylontech high voltage protocol, broadcasts every second
+#[derive(Default)]
+pub struct Pylontech {
+    data: PylontechBms,
+}
+
+impl InverterProtocol for Pylontech {
+    type Error = CanError;
+
+    // inverter heartbeat and identification
+    const FILTERS: &'static [Rule] = &[Rule::standard(0x305), Rule::standard(0x307)];
+
+    fn period(&self) -> Option<Duration> {
+        Some(Duration::from_secs(1))
+    }
+
+    fn on_frame(&mut self, _frame: &Frame, _state: &BatteryState) -> Result<Frames, Self::Error> {
+        Ok(Frames::new())
+    }
+
+    fn on_tick(&mut self, state: &BatteryState) -> Result<Frames, Self::Error> {
+        update_inverter_data(&mut self.data, state);
+        Ok(self.data.parser()?.into_iter().collect())
+    }
+
+    fn fault(&self, _error: &Self::Error) -> FaultAction {
+        // encoding errors leave the previous frame set on the bus
+        FaultAction::Skip
+    }
+}
+
+#[inline]
+fn update_inverter_data(inverter_data: &mut PylontechBms, state: &BatteryState) {
+    inverter_data.pack_voltage_max = state.pack_volts_max;
+    inverter_data.charge_voltage_max = state.charge_volts_max;
+    inverter_data.charge_voltage_min = state.charge_volts_min;
+    inverter_data.charge_max = state.charge_max;
+    inverter_data.discharge_max = state.discharge_max;
+    inverter_data.voltage = state.pack_volts;
+    inverter_data.current = state.current;
+    inverter_data.capacity = state.soc as u16;
+    inverter_data.kwh = state.kwh_remaining;
+    inverter_data.int_temp = state.temp_avg;
+    inverter_data.wh_total = 10000;
+    inverter_data.contactor = true;
+    inverter_data.v_max = state.cell_mv_high as f32; // cell as millivolts
+    inverter_data.v_min = state.cell_mv_low as f32;
+    inverter_data.valid = state.valid;
+}
@@ -0,0 +1,70 @@
+use super::{FaultAction, InverterProtocol};
+use crate::battery::BatteryState;
+use crate::filter::Rule;
+use crate::Frames;
+use bxcan::Frame;
+use solax_can_bus::{SolaxBms, SolaxError};
+
+/// Solax request/response protocol, replies to each inverter poll
+#[derive(Default)]
+pub struct Solax {
+    data: SolaxBms,
+}
+
+impl InverterProtocol for Solax {
+    type Error = SolaxError;
+
+    // the inverter polls on a single ID, the reply depends on the payload
+    const FILTERS: &'static [Rule] = &[Rule::extended(0x1871)];
+
+    fn on_frame(&mut self, frame: &Frame, state: &BatteryState) -> Result<Frames, Self::Error> {
+        update_inverter_data(&mut self.data, state);
+        Ok(self.data.parser(frame.clone())?.into_iter().collect())
+    }
+
+    fn fault(&self, error: &Self::Error) -> FaultAction {
+        match error {
+            SolaxError::InvalidData => FaultAction::Continue,
+            SolaxError::BadId(_) => FaultAction::OpenContactor,
+            SolaxError::InvalidFrameEncode(_) => FaultAction::OpenContactor,
+            SolaxError::TimeStamp(_) => FaultAction::Continue,
+            SolaxError::InvalidTimeData => FaultAction::Continue, // not critical
+            SolaxError::UnwantedFrame => FaultAction::Continue,
+        }
+    }
+}
+
+#[inline]
+fn update_inverter_data(inverter_data: &mut SolaxBms, state: &BatteryState) {
+    inverter_data.pack_voltage_max = state.pack_volts_max;
+    inverter_data.slave_voltage_max = state.charge_volts_max;
+    inverter_data.slave_voltage_min = state.charge_volts_min;
+    inverter_data.charge_max = state.charge_max;
+    inverter_data.discharge_max = state.discharge_max;
+    inverter_data.voltage = state.pack_volts;
+    inverter_data.current = state.current;
+    inverter_data.capacity = state.soc as u16;
+    inverter_data.kwh = state.kwh_remaining;
+    inverter_data.cell_temp_min = state.cell_temp_low;
+    inverter_data.cell_temp_max = state.cell_temp_high;
+    inverter_data.int_temp = state.temp_avg;
+    inverter_data.cell_voltage_min = state.cell_mv_low; //cell as millivolts
+    inverter_data.cell_voltage_max = state.cell_mv_high;
+    inverter_data.wh_total = 10000;
+    inverter_data.contactor = true;
+    inverter_data.v_max = state.cell_mv_high as f32; // cell as millivolts
+    inverter_data.v_min = state.cell_mv_low as f32;
+    inverter_data.valid = state.valid;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn critical_errors_open_contactor() {
+        let solax = Solax::default();
+        assert_eq!(solax.fault(&SolaxError::BadId(0x1871)), FaultAction::OpenContactor);
+        assert_eq!(solax.fault(&SolaxError::UnwantedFrame), FaultAction::Continue);
+    }
+}
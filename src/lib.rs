@@ -1,37 +1,59 @@
-#![no_main]
-#![no_std]
+#![cfg_attr(not(test), no_std)]
+#![feature(error_in_core)]
 
+//! Protocol core shared by the firmware and host tools.
+//!
+//! Frame encoders/decoders, `Config` handling, `MqttFormat` and the battery/inverter
+//! state machines live here so they build for both `thumbv7m-none-eabi` and the host.
+//! Run the unit tests off-target with `cargo test-host`.
+
+#[cfg(target_os = "none")]
 use defmt_rtt as _; // global logger
 
-// TODO(5) adjust HAL import
-// use some_hal as _; // memory layout
+// must come first, the logging macros are used by the modules below
+#[macro_use]
+mod fmt;
+
+pub mod autobaud;
+pub mod battery;
+pub mod candump;
+pub mod command;
+pub mod config;
+pub mod contactor;
+pub mod errors;
+pub mod esp;
+pub mod filter;
+pub mod frame;
+pub mod gvret;
+pub mod gateway;
+pub mod health;
+pub mod inverter;
+pub mod modbus;
+pub mod mqtt;
+pub mod protection;
+pub mod script;
+pub mod slcan;
+pub mod storage;
+pub mod timing;
+
+/// Frames produced by one protocol step, sent in order
+pub type Frames = heapless::Vec<bxcan::Frame, 16>;
 
-// use panic_probe as _;
+/// Seconds without a BMS update before inverter comms are stopped
+pub const LAST_READING_TIMEOUT_SECS: u64 = 10;
 
 // same panicking *behavior* as `panic-probe` but doesn't print a panic message
 // this prevents the panic message being printed *twice* when `defmt::panic` is invoked
+#[cfg(target_os = "none")]
 #[defmt::panic_handler]
 fn panic() -> ! {
     cortex_m::asm::udf()
 }
 
 /// Terminates the application and makes `probe-run` exit with exit-code = 0
+#[cfg(target_os = "none")]
 pub fn exit() -> ! {
     loop {
         cortex_m::asm::bkpt();
     }
 }
-
-// defmt-test 0.3.0 has the limitation that this `#[tests]` attribute can only be used
-// once within a crate. the module can be in any file but there can only be at most
-// one `#[tests]` module in this library crate
-#[cfg(test)]
-#[defmt_test::tests]
-mod unit_tests {
-    use defmt::assert;
-
-    #[test]
-    fn it_works() {
-        assert!(true)
-    }
-}
@@ -0,0 +1,241 @@
+//! The gateway's Modbus register map, see the README for the full table.
+//!
+//! Input registers are live readings scaled to integers, signed values are two's
+//! complement. Holding registers 0-2 (DoD and the contactor command) are writable,
+//! the rest mirror `Config` read-only. Enums are their declaration index.
+
+use super::{Exception, Registers};
+use crate::battery::BatteryState;
+use crate::config::patch::Patch;
+use crate::config::Config;
+use crate::contactor::ContactorState;
+use crate::protection::Level;
+
+pub const INPUT_COUNT: usize = 17;
+pub const HOLDING_COUNT: usize = 17;
+
+pub const DOD_MIN: u16 = 0;
+pub const DOD_MAX: u16 = 1;
+pub const CONTACTOR: u16 = 2;
+/// Holding registers from here on are read-only
+const WRITABLE: u16 = 3;
+
+/// Value written to the `CONTACTOR` holding register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContactorRequest {
+    /// 1: open and hold open, as the `open_contactor` command
+    Open,
+    /// 2: clear faults and release a forced open, as `clear_faults`
+    ClearFaults,
+}
+
+/// Live values behind the input registers that are not in `BatteryState`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Status {
+    pub contactor: ContactorState,
+    pub alarm: Level,
+    pub tripped: bool,
+    pub forced_open: bool,
+}
+
+/// Registers captured for one request, writes are collected for the caller to apply
+pub struct Snapshot {
+    input: [u16; INPUT_COUNT],
+    holding: [u16; HOLDING_COUNT],
+    config: Config,
+    dod_written: bool,
+    pub contactor: Option<ContactorRequest>,
+}
+
+/// `value * scale` rounded, saturating at the `u16` range
+pub(crate) fn unsigned(value: f32, scale: f32) -> u16 {
+    (value * scale + 0.5) as u16
+}
+
+/// `value * scale` rounded, as a two's complement `i16`
+pub(crate) fn signed(value: f32, scale: f32) -> u16 {
+    let value = value * scale;
+    let rounded = if value < 0.0 {
+        value - 0.5
+    } else {
+        value + 0.5
+    };
+    rounded as i16 as u16
+}
+
+impl Snapshot {
+    pub fn new(state: &BatteryState, config: &Config, status: Status) -> Self {
+        let input = [
+            unsigned(state.soc, 10.0),
+            unsigned(state.pack_volts, 10.0),
+            signed(state.current, 10.0),
+            state.cell_mv_high,
+            state.cell_mv_low,
+            signed(state.cell_temp_high, 10.0),
+            signed(state.cell_temp_low, 10.0),
+            signed(state.temp_avg, 10.0),
+            unsigned(state.kwh_remaining, 100.0),
+            unsigned(state.charge_max, 10.0),
+            unsigned(state.discharge_max, 10.0),
+            state.balancing_cells as u16,
+            state.valid as u16,
+            *config.state() as u16,
+            status.contactor as u16,
+            status.alarm as u16,
+            status.tripped as u16,
+        ];
+        let holding = [
+            config.dod.min() as u16,
+            config.dod.max() as u16,
+            status.forced_open as u16,
+            config.pack_volts().min(),
+            config.pack_volts().max(),
+            config.cell_millivolts().min(),
+            config.cell_millivolts().max(),
+            config.pack_temperature().min() as u16,
+            config.pack_temperature().max() as u16,
+            config.cell_temperature().min() as u16,
+            config.cell_temperature().max() as u16,
+            config.current_amps().min() as u16,
+            config.current_amps().max() as u16,
+            config.timeout_secs() as u16,
+            config.mqtt_rate_secs().min(u16::MAX as u32) as u16,
+            config.battery_type() as u16,
+            config.inverter_type() as u16,
+        ];
+        Self {
+            input,
+            holding,
+            config: config.clone(),
+            dod_written: false,
+            contactor: None,
+        }
+    }
+
+    /// Merge-patch for the written DoD, `None` if it was not written
+    pub fn config_patch(&self) -> Option<Patch> {
+        self.dod_written
+            .then(|| Patch::dod(self.holding[0] as u8, self.holding[1] as u8))
+    }
+}
+
+impl Registers for Snapshot {
+    fn input(&self, addr: u16) -> Option<u16> {
+        self.input.get(addr as usize).copied()
+    }
+
+    fn holding(&self, addr: u16) -> Option<u16> {
+        self.holding.get(addr as usize).copied()
+    }
+
+    fn write(&mut self, addr: u16, values: &[u16]) -> Result<(), Exception> {
+        let end = addr as usize + values.len();
+        if end > WRITABLE as usize {
+            return Err(Exception::IllegalAddress);
+        }
+        let mut holding = self.holding;
+        holding[addr as usize..end].copy_from_slice(values);
+        let contactor = match (addr..end as u16).contains(&CONTACTOR) {
+            false => self.contactor,
+            true => match holding[CONTACTOR as usize] {
+                0 => None,
+                1 => Some(ContactorRequest::Open),
+                2 => Some(ContactorRequest::ClearFaults),
+                _ => return Err(Exception::IllegalValue),
+            },
+        };
+        let dod_written = addr <= DOD_MAX;
+        if dod_written {
+            // same checks as a config patch over UART
+            let (min, max) = (holding[DOD_MIN as usize], holding[DOD_MAX as usize]);
+            if min > u8::MAX as u16 || max > u8::MAX as u16 {
+                return Err(Exception::IllegalValue);
+            }
+            self.config
+                .clone()
+                .update_from_patch(&Patch::dod(min as u8, max as u8))
+                .map_err(|_| Exception::IllegalValue)?;
+        }
+        self.holding = holding;
+        self.dod_written |= dod_written;
+        self.contactor = contactor;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modbus::{crc16, handle, Adu};
+
+    fn frame(bytes: &[u8]) -> Adu {
+        let mut out = Adu::from_slice(bytes).unwrap();
+        out.extend_from_slice(&crc16(bytes).to_le_bytes()).unwrap();
+        out
+    }
+
+    fn snapshot() -> Snapshot {
+        let state = BatteryState {
+            soc: 55.4,
+            pack_volts: 380.04,
+            current: -12.3,
+            cell_temp_low: -4.0,
+            valid: true,
+            ..Default::default()
+        };
+        let status = Status {
+            contactor: ContactorState::Closed,
+            ..Default::default()
+        };
+        Snapshot::new(&state, &Config::default(), status)
+    }
+
+    #[test]
+    fn scales_live_values() {
+        let snapshot = snapshot();
+        assert_eq!(snapshot.input(0), Some(554));
+        assert_eq!(snapshot.input(1), Some(3800));
+        assert_eq!(snapshot.input(2), Some(-123_i16 as u16));
+        assert_eq!(snapshot.input(6), Some(-40_i16 as u16));
+        assert_eq!(snapshot.input(12), Some(1));
+        assert_eq!(snapshot.input(14), Some(ContactorState::Closed as u16));
+        assert_eq!(snapshot.input(INPUT_COUNT as u16), None);
+        assert_eq!(snapshot.holding(DOD_MAX), Some(99));
+        assert_eq!(snapshot.holding(7), Some(-20_i16 as u16));
+    }
+
+    #[test]
+    fn writes_dod_and_contactor() {
+        let mut snapshot = snapshot();
+        let response = handle(
+            1,
+            &frame(&[1, 0x10, 0, 0, 0, 2, 4, 0, 10, 0, 90]),
+            &mut snapshot,
+        );
+        assert_eq!(response.unwrap(), frame(&[1, 0x10, 0, 0, 0, 2]));
+        let mut config = Config::default();
+        config
+            .update_from_patch(&snapshot.config_patch().unwrap())
+            .unwrap();
+        assert_eq!((config.dod.min(), config.dod.max()), (10, 90));
+
+        handle(1, &frame(&[1, 0x06, 0, 2, 0, 1]), &mut snapshot);
+        assert_eq!(snapshot.contactor, Some(ContactorRequest::Open));
+    }
+
+    #[test]
+    fn rejects_bad_writes() {
+        let mut snapshot = snapshot();
+        // max above 100
+        let response = handle(1, &frame(&[1, 0x06, 0, 1, 0, 101]), &mut snapshot);
+        assert_eq!(response.unwrap(), frame(&[1, 0x86, 0x03]));
+        // read-only config
+        let response = handle(1, &frame(&[1, 0x06, 0, 3, 0, 1]), &mut snapshot);
+        assert_eq!(response.unwrap(), frame(&[1, 0x86, 0x02]));
+        // unknown contactor command
+        let response = handle(1, &frame(&[1, 0x06, 0, 2, 0, 7]), &mut snapshot);
+        assert_eq!(response.unwrap(), frame(&[1, 0x86, 0x03]));
+        assert!(snapshot.config_patch().is_none());
+        assert!(snapshot.contactor.is_none());
+    }
+}
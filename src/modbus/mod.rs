@@ -0,0 +1,234 @@
+//! Modbus RTU slave framing.
+//!
+//! Handles read holding registers (0x03), read input registers (0x04), write single
+//! register (0x06) and write multiple registers (0x10) against a `Registers` map, see
+//! `map` for the gateway's. Frames with a bad CRC or for another unit are ignored,
+//! broadcast (unit 0) writes are applied without a response.
+
+use heapless::Vec;
+
+pub mod map;
+
+/// Largest RTU frame
+pub const MAX_ADU: usize = 256;
+pub const BROADCAST: u8 = 0;
+/// Largest register count in one read or write request
+const MAX_READ: u16 = 125;
+const MAX_WRITE: u16 = 123;
+
+pub type Adu = Vec<u8, MAX_ADU>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exception {
+    IllegalFunction = 0x01,
+    IllegalAddress = 0x02,
+    IllegalValue = 0x03,
+}
+
+/// Register map served by `handle`
+pub trait Registers {
+    /// Input register `addr`, `None` if it does not exist
+    fn input(&self, addr: u16) -> Option<u16>;
+    /// Holding register `addr`, `None` if it does not exist
+    fn holding(&self, addr: u16) -> Option<u16>;
+    /// Write `values` to the holding registers from `addr`, all or nothing
+    fn write(&mut self, addr: u16, values: &[u16]) -> Result<(), Exception>;
+}
+
+/// Modbus CRC-16, polynomial 0xA001 reflected, initial 0xFFFF, sent low byte first
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0xFFFF_u16;
+    for byte in bytes {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xA001,
+                _ => crc >> 1,
+            };
+        }
+    }
+    crc
+}
+
+/// Response to one request frame, `None` if nothing should be sent
+pub fn handle(unit: u8, request: &[u8], registers: &mut impl Registers) -> Option<Adu> {
+    if request.len() < 4 {
+        return None;
+    }
+    let (body, crc) = request.split_at(request.len() - 2);
+    if crc16(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+        debug!("Modbus CRC error");
+        return None;
+    }
+    let (address, function, pdu) = (body[0], body[1], &body[2..]);
+    let write = matches!(function, 0x06 | 0x10);
+    if address != unit && !(address == BROADCAST && write) {
+        return None;
+    }
+    let mut out = Adu::new();
+    let _ = out.extend_from_slice(&[address, function]);
+    let result = match function {
+        0x03 => read(pdu, &mut out, |addr| registers.holding(addr)),
+        0x04 => read(pdu, &mut out, |addr| registers.input(addr)),
+        0x06 => write_single(pdu, &mut out, registers),
+        0x10 => write_multiple(pdu, &mut out, registers),
+        _ => Err(Exception::IllegalFunction),
+    };
+    if address == BROADCAST {
+        return None;
+    }
+    if let Err(exception) = result {
+        out.truncate(1);
+        let _ = out.extend_from_slice(&[function | 0x80, exception as u8]);
+    }
+    let crc = crc16(&out);
+    let _ = out.extend_from_slice(&crc.to_le_bytes());
+    Some(out)
+}
+
+fn word(bytes: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read(pdu: &[u8], out: &mut Adu, register: impl Fn(u16) -> Option<u16>) -> Result<(), Exception> {
+    if pdu.len() != 4 {
+        return Err(Exception::IllegalValue);
+    }
+    let (start, count) = (word(pdu, 0), word(pdu, 2));
+    if !(1..=MAX_READ).contains(&count) {
+        return Err(Exception::IllegalValue);
+    }
+    let _ = out.push(count as u8 * 2);
+    for addr in (0..count).map(|i| start.checked_add(i)) {
+        let value = addr.and_then(&register).ok_or(Exception::IllegalAddress)?;
+        let _ = out.extend_from_slice(&value.to_be_bytes());
+    }
+    Ok(())
+}
+
+fn write_single(
+    pdu: &[u8],
+    out: &mut Adu,
+    registers: &mut impl Registers,
+) -> Result<(), Exception> {
+    if pdu.len() != 4 {
+        return Err(Exception::IllegalValue);
+    }
+    registers.write(word(pdu, 0), &[word(pdu, 2)])?;
+    let _ = out.extend_from_slice(pdu);
+    Ok(())
+}
+
+fn write_multiple(
+    pdu: &[u8],
+    out: &mut Adu,
+    registers: &mut impl Registers,
+) -> Result<(), Exception> {
+    if pdu.len() < 5 {
+        return Err(Exception::IllegalValue);
+    }
+    let (start, count, bytes) = (word(pdu, 0), word(pdu, 2), pdu[4] as usize);
+    if !(1..=MAX_WRITE).contains(&count) || bytes != count as usize * 2 || pdu.len() != 5 + bytes {
+        return Err(Exception::IllegalValue);
+    }
+    let values: Vec<u16, { MAX_WRITE as usize }> =
+        (0..count as usize).map(|i| word(pdu, 5 + i * 2)).collect();
+    if start.checked_add(count - 1).is_none() {
+        return Err(Exception::IllegalAddress);
+    }
+    registers.write(start, &values)?;
+    let _ = out.extend_from_slice(&pdu[..4]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ten input registers holding their address, holding registers 0..4 writable
+    struct Fake {
+        holding: [u16; 4],
+    }
+
+    impl Registers for Fake {
+        fn input(&self, addr: u16) -> Option<u16> {
+            (addr < 10).then_some(addr)
+        }
+
+        fn holding(&self, addr: u16) -> Option<u16> {
+            self.holding.get(addr as usize).copied()
+        }
+
+        fn write(&mut self, addr: u16, values: &[u16]) -> Result<(), Exception> {
+            let target = self
+                .holding
+                .get_mut(addr as usize..addr as usize + values.len())
+                .ok_or(Exception::IllegalAddress)?;
+            target.copy_from_slice(values);
+            Ok(())
+        }
+    }
+
+    fn frame(bytes: &[u8]) -> Adu {
+        let mut out = Adu::from_slice(bytes).unwrap();
+        out.extend_from_slice(&crc16(bytes).to_le_bytes()).unwrap();
+        out
+    }
+
+    #[test]
+    fn crc_matches_the_spec_example() {
+        assert_eq!(
+            crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0A]).to_le_bytes(),
+            [0xC5, 0xCD]
+        );
+    }
+
+    #[test]
+    fn reads_registers() {
+        let mut fake = Fake { holding: [7; 4] };
+        let response = handle(1, &frame(&[1, 0x04, 0, 2, 0, 3]), &mut fake).unwrap();
+        assert_eq!(response, frame(&[1, 0x04, 6, 0, 2, 0, 3, 0, 4]));
+        let response = handle(1, &frame(&[1, 0x03, 0, 0, 0, 1]), &mut fake).unwrap();
+        assert_eq!(response, frame(&[1, 0x03, 2, 0, 7]));
+    }
+
+    #[test]
+    fn writes_registers() {
+        let mut fake = Fake { holding: [0; 4] };
+        let request = frame(&[1, 0x06, 0, 1, 0x12, 0x34]);
+        assert_eq!(handle(1, &request, &mut fake).unwrap(), request);
+        let request = frame(&[1, 0x10, 0, 2, 0, 2, 4, 0, 5, 0, 6]);
+        let response = handle(1, &request, &mut fake).unwrap();
+        assert_eq!(response, frame(&[1, 0x10, 0, 2, 0, 2]));
+        assert_eq!(fake.holding, [0, 0x1234, 5, 6]);
+    }
+
+    #[test]
+    fn exceptions() {
+        let mut fake = Fake { holding: [0; 4] };
+        let response = handle(1, &frame(&[1, 0x05, 0, 0, 0xFF, 0]), &mut fake).unwrap();
+        assert_eq!(response, frame(&[1, 0x85, 0x01]));
+        let response = handle(1, &frame(&[1, 0x04, 0, 8, 0, 3]), &mut fake).unwrap();
+        assert_eq!(response, frame(&[1, 0x84, 0x02]));
+        let response = handle(1, &frame(&[1, 0x03, 0, 0, 0, 0]), &mut fake).unwrap();
+        assert_eq!(response, frame(&[1, 0x83, 0x03]));
+        let response = handle(1, &frame(&[1, 0x10, 0, 3, 0, 2, 4, 0, 5, 0, 6]), &mut fake).unwrap();
+        assert_eq!(response, frame(&[1, 0x90, 0x02]));
+        assert_eq!(fake.holding, [0; 4]);
+    }
+
+    #[test]
+    fn ignores_other_units_and_bad_frames() {
+        let mut fake = Fake { holding: [0; 4] };
+        assert!(handle(1, &frame(&[2, 0x04, 0, 0, 0, 1]), &mut fake).is_none());
+        let mut corrupt = frame(&[1, 0x04, 0, 0, 0, 1]);
+        corrupt[3] ^= 1;
+        assert!(handle(1, &corrupt, &mut fake).is_none());
+        assert!(handle(1, &[1, 0x04], &mut fake).is_none());
+
+        // broadcast writes apply silently, broadcast reads are ignored
+        assert!(handle(1, &frame(&[0, 0x06, 0, 0, 0, 9]), &mut fake).is_none());
+        assert_eq!(fake.holding[0], 9);
+        assert!(handle(1, &frame(&[0, 0x03, 0, 0, 0, 1]), &mut fake).is_none());
+    }
+}
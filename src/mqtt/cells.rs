@@ -0,0 +1,160 @@
+//! Per-cell telemetry split into indexed chunks.
+//!
+//! A full 96 cell snapshot does not fit the 512 byte UART buffer or the 2 KiB heap as
+//! one JSON document, so it goes out as several `cells` messages, one rendered at a time:
+//!
+//! ```text
+//! {"seq":7,"kind":"mv","first":0,"total":96,"values":[3901,3899,...]}    24 per chunk
+//! {"seq":7,"kind":"temp","first":0,"total":12,"values":[21.5,22.0,...]}
+//! {"seq":7,"kind":"bal","first":0,"total":96,"values":[0,1,0,...]}
+//! ```
+//!
+//! Chunks with the same `seq` belong to one snapshot, `values[i]` is element
+//! `first + i` of the `kind` array, and `total` is the array length.
+
+use crate::battery::CellData;
+use core::fmt::Write;
+use miniserde::__private::String;
+
+/// Cell voltages per chunk
+const MV_CHUNK: usize = 24;
+
+/// A `CellData` reading numbered for reassembly
+#[derive(Clone, Debug, Default)]
+pub struct CellSnapshot {
+    pub seq: u32,
+    pub data: CellData,
+}
+
+impl CellSnapshot {
+    /// Replace the data and bump `seq`
+    pub fn update(&mut self, data: CellData) {
+        self.seq = self.seq.wrapping_add(1);
+        self.data = data;
+    }
+
+    /// Render chunk `part`, `None` once all chunks have been rendered
+    pub fn chunk(&self, part: usize) -> Option<String> {
+        let data = &self.data;
+        let mv_parts = (data.millivolts.len() + MV_CHUNK - 1) / MV_CHUNK;
+        let mut out = String::with_capacity(256);
+        if part < mv_parts {
+            let first = part * MV_CHUNK;
+            let end = (first + MV_CHUNK).min(data.millivolts.len());
+            self.header(&mut out, "mv", first, data.millivolts.len());
+            values(&mut out, data.millivolts[first..end].iter().map(|mv| *mv as f32), 0);
+        } else if part == mv_parts && !data.temps.is_empty() {
+            self.header(&mut out, "temp", 0, data.temps.len());
+            values(&mut out, data.temps.iter().copied(), 1);
+        } else if part == mv_parts + !data.temps.is_empty() as usize
+            && !data.balancing.is_empty()
+        {
+            self.header(&mut out, "bal", 0, data.balancing.len());
+            values(&mut out, data.balancing.iter().map(|b| *b as u8 as f32), 0);
+        } else {
+            return None;
+        }
+        Some(out)
+    }
+
+    fn header(&self, out: &mut String, kind: &str, first: usize, total: usize) {
+        let _ = write!(
+            out,
+            "{{\"seq\":{},\"kind\":\"{}\",\"first\":{},\"total\":{},\"values\":",
+            self.seq, kind, first, total
+        );
+    }
+}
+
+fn values(out: &mut String, values: impl Iterator<Item = f32>, decimals: usize) {
+    out.push('[');
+    for (i, value) in values.enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let _ = write!(out, "{:.*}", decimals, value);
+    }
+    out.push_str("]}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battery::{MAX_CELLS, MAX_MODULES};
+    use crate::mqtt::Topic;
+    use miniserde::json::{self, Number, Value};
+
+    fn full_pack() -> CellSnapshot {
+        let mut snapshot = CellSnapshot::default();
+        snapshot.update(CellData {
+            millivolts: (0..MAX_CELLS).map(|i| 4100 + i as u16).collect(),
+            temps: (0..MAX_MODULES).map(|i| -10.5 + i as f32).collect(),
+            balancing: (0..MAX_CELLS).map(|i| i % 7 == 0).collect(),
+        });
+        snapshot
+    }
+
+    fn number(value: &Value) -> f64 {
+        match value {
+            Value::Number(Number::U64(n)) => *n as f64,
+            Value::Number(Number::I64(n)) => *n as f64,
+            Value::Number(Number::F64(n)) => *n,
+            _ => panic!("not a number"),
+        }
+    }
+
+    #[test]
+    fn chunks_fit_the_uart_buffer() {
+        let snapshot = full_pack();
+        let parts: Vec<_> = (0..).map_while(|part| snapshot.chunk(part)).collect();
+        assert_eq!(parts.len(), 4 + 1 + 1);
+        for part in &parts {
+            assert!(Topic::Cells.envelope(part).len() < 512, "{}", part);
+        }
+    }
+
+    #[test]
+    fn chunks_reassemble() {
+        let snapshot = full_pack();
+        let mut mv = [0_u16; MAX_CELLS];
+        let mut temps = [0_f32; MAX_MODULES];
+        let mut bal = [false; MAX_CELLS];
+        for part in (0..).map_while(|part| snapshot.chunk(part)) {
+            let Ok(Value::Object(chunk)) = json::from_str::<Value>(&part) else {
+                panic!("bad chunk {}", part);
+            };
+            assert_eq!(number(&chunk["seq"]), 1.0);
+            let first = number(&chunk["first"]) as usize;
+            let Value::Array(values) = &chunk["values"] else {
+                panic!("no values");
+            };
+            let Value::String(kind) = &chunk["kind"] else {
+                panic!("no kind");
+            };
+            for (i, value) in values.iter().enumerate() {
+                let value = number(value);
+                match kind.as_str() {
+                    "mv" => mv[first + i] = value as u16,
+                    "temp" => temps[first + i] = value as f32,
+                    "bal" => bal[first + i] = value != 0.0,
+                    other => panic!("unknown kind {}", other),
+                }
+            }
+        }
+        assert_eq!(&mv[..], snapshot.data.millivolts.as_slice());
+        assert_eq!(&temps[..], snapshot.data.temps.as_slice());
+        assert_eq!(&bal[..], snapshot.data.balancing.as_slice());
+    }
+
+    #[test]
+    fn skips_missing_arrays() {
+        let mut snapshot = CellSnapshot::default();
+        assert!(snapshot.chunk(0).is_none());
+        snapshot.update(CellData {
+            millivolts: (0..30).map(|_| 3700).collect(),
+            ..Default::default()
+        });
+        assert!(snapshot.chunk(1).unwrap().contains("\"first\":24"));
+        assert!(snapshot.chunk(2).is_none());
+    }
+}
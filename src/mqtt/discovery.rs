@@ -0,0 +1,243 @@
+//! Home Assistant MQTT discovery payloads for every telemetry field.
+//!
+//! Each `discovery` message names the retained config topic and its payload, the UART
+//! bridge publishes `payload` to `topic` as-is and the telemetry lines to
+//! `<base>/<topic>`, e.g. `gateway/<uid>/summary`. Payloads use Home Assistant's
+//! abbreviated keys so every line fits the 512 byte UART buffer.
+
+use super::Topic;
+use core::fmt::Write;
+use miniserde::__private::String;
+
+/// Unique device id, the STM32 96-bit UID in hex
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DeviceId([u8; 12]);
+
+impl DeviceId {
+    pub fn from_uid(uid: [u8; 12]) -> Self {
+        Self(uid)
+    }
+
+    /// Append the UID as 24 lowercase hex digits
+    pub fn write_hex(&self, out: &mut String) {
+        for byte in self.0 {
+            let _ = write!(out, "{:02x}", byte);
+        }
+    }
+}
+
+/// One Home Assistant sensor backed by a field of a telemetry message
+pub struct Sensor {
+    /// JSON key in the `topic` message, also the entity suffix
+    pub key: &'static str,
+    /// Dotted path to a nested field, in place of `key`
+    pub path: Option<&'static str>,
+    pub name: &'static str,
+    pub topic: Topic,
+    pub device_class: Option<&'static str>,
+    pub unit: Option<&'static str>,
+    pub state_class: Option<&'static str>,
+}
+
+/// Numeric reading
+const fn sensor(
+    topic: Topic,
+    key: &'static str,
+    name: &'static str,
+    device_class: Option<&'static str>,
+    unit: Option<&'static str>,
+) -> Sensor {
+    Sensor {
+        key,
+        path: None,
+        name,
+        topic,
+        device_class,
+        unit,
+        state_class: Some("measurement"),
+    }
+}
+
+/// One of a fixed set of states
+const fn status(topic: Topic, key: &'static str, name: &'static str) -> Sensor {
+    Sensor {
+        key,
+        path: None,
+        name,
+        topic,
+        device_class: Some("enum"),
+        unit: None,
+        state_class: None,
+    }
+}
+
+impl Sensor {
+    /// Read the value from the nested field at `path`, e.g. `bms_bus.state`
+    const fn at(self, path: &'static str) -> Sensor {
+        Sensor {
+            path: Some(path),
+            ..self
+        }
+    }
+}
+
+const S: Topic = Topic::Summary;
+const P: Topic = Topic::Stats;
+const VOLTAGE: Option<&str> = Some("voltage");
+const CURRENT: Option<&str> = Some("current");
+const TEMPERATURE: Option<&str> = Some("temperature");
+
+/// Summary fields, then the statistics period. Per-period energy totals reset every
+/// period, so they are plain measurements rather than `energy` totals.
+pub const SENSORS: &[Sensor] = &[
+    sensor(S, "soc", "SoC", Some("battery"), Some("%")),
+    sensor(S, "volts", "Pack voltage", VOLTAGE, Some("V")),
+    sensor(S, "cell_mv_high", "Cell voltage high", VOLTAGE, Some("mV")),
+    sensor(S, "cell_mv_low", "Cell voltage low", VOLTAGE, Some("mV")),
+    sensor(S, "cell_temp_high", "Cell temperature high", TEMPERATURE, Some("°C")),
+    sensor(S, "cell_temp_low", "Cell temperature low", TEMPERATURE, Some("°C")),
+    sensor(S, "amps", "Current", CURRENT, Some("A")),
+    sensor(S, "kwh", "Energy remaining", Some("energy_storage"), Some("kWh")),
+    sensor(S, "charge", "Charge limit", CURRENT, Some("A")),
+    sensor(S, "discharge", "Discharge limit", CURRENT, Some("A")),
+    sensor(S, "bal", "Balancing cells", None, None),
+    status(S, "valid", "BMS data valid"),
+    status(S, "alarm", "Alarm"),
+    status(S, "contactor", "Contactor"),
+    status(S, "bms_bus_state", "BMS bus state").at("bms_bus.state"),
+    sensor(S, "bms_bus_tec", "BMS bus TX errors", None, None).at("bms_bus.tec"),
+    sensor(S, "bms_bus_rec", "BMS bus RX errors", None, None).at("bms_bus.rec"),
+    sensor(S, "bms_bus_offs", "BMS bus-offs", None, None).at("bms_bus.bus_offs"),
+    status(S, "inverter_bus_state", "Inverter bus state").at("inverter_bus.state"),
+    sensor(S, "inverter_bus_tec", "Inverter bus TX errors", None, None).at("inverter_bus.tec"),
+    sensor(S, "inverter_bus_rec", "Inverter bus RX errors", None, None).at("inverter_bus.rec"),
+    sensor(S, "inverter_bus_offs", "Inverter bus-offs", None, None).at("inverter_bus.bus_offs"),
+    sensor(P, "soc_min", "SoC min", Some("battery"), Some("%")),
+    sensor(P, "soc_max", "SoC max", Some("battery"), Some("%")),
+    sensor(P, "volts_min", "Pack voltage min", VOLTAGE, Some("V")),
+    sensor(P, "volts_max", "Pack voltage max", VOLTAGE, Some("V")),
+    sensor(P, "cell_mv_min", "Cell voltage min", VOLTAGE, Some("mV")),
+    sensor(P, "cell_mv_max", "Cell voltage max", VOLTAGE, Some("mV")),
+    sensor(P, "cell_temp_min", "Cell temperature min", TEMPERATURE, Some("°C")),
+    sensor(P, "cell_temp_max", "Cell temperature max", TEMPERATURE, Some("°C")),
+    sensor(P, "amps_min", "Current min", CURRENT, Some("A")),
+    sensor(P, "amps_max", "Current max", CURRENT, Some("A")),
+    sensor(P, "ah_in", "Charge in", None, Some("Ah")),
+    sensor(P, "ah_out", "Charge out", None, Some("Ah")),
+    sensor(P, "kwh_in", "Energy in", None, Some("kWh")),
+    sensor(P, "kwh_out", "Energy out", None, Some("kWh")),
+];
+
+/// `gateway/<uid>`, telemetry goes to `<base>/<topic>`
+pub fn base_topic(id: &DeviceId) -> String {
+    let mut out = String::from("gateway/");
+    id.write_hex(&mut out);
+    out
+}
+
+/// Retained config topic for `SENSORS[part]`
+pub fn config_topic(id: &DeviceId, part: usize) -> Option<String> {
+    let sensor = SENSORS.get(part)?;
+    let mut out = String::from("homeassistant/sensor/gw_");
+    id.write_hex(&mut out);
+    let _ = write!(out, "/{}/config", sensor.key);
+    Some(out)
+}
+
+/// Discovery config payload for `SENSORS[part]`
+pub fn payload(id: &DeviceId, part: usize) -> Option<String> {
+    let sensor = SENSORS.get(part)?;
+    let mut out = String::with_capacity(320);
+    out.push_str("{\"~\":\"gateway/");
+    id.write_hex(&mut out);
+    let _ = write!(out, "\",\"name\":\"{}\",\"uniq_id\":\"gw_", sensor.name);
+    id.write_hex(&mut out);
+    let _ = write!(
+        out,
+        "_{}\",\"stat_t\":\"~/{}\",\"val_tpl\":\"{{{{value_json.{}}}}}\"",
+        sensor.key,
+        sensor.topic.name(),
+        sensor.path.unwrap_or(sensor.key)
+    );
+    if let Some(class) = sensor.device_class {
+        let _ = write!(out, ",\"dev_cla\":\"{}\"", class);
+    }
+    if let Some(unit) = sensor.unit {
+        let _ = write!(out, ",\"unit_of_meas\":\"{}\"", unit);
+    }
+    if let Some(class) = sensor.state_class {
+        let _ = write!(out, ",\"stat_cla\":\"{}\"", class);
+    }
+    out.push_str(",\"dev\":{\"ids\":[\"gw_");
+    id.write_hex(&mut out);
+    let _ = write!(
+        out,
+        "\"],\"name\":\"CAN gateway\",\"mdl\":\"STM32F105\",\"sw\":\"{}\"}}}}",
+        env!("CARGO_PKG_VERSION")
+    );
+    Some(out)
+}
+
+/// Discovery message `part` for the UART bridge, one per entry of `SENSORS`,
+/// `None` past the end
+pub fn render(id: &DeviceId, part: usize) -> Option<String> {
+    let topic = config_topic(id, part)?;
+    let payload = payload(id, part)?;
+    let mut out = String::with_capacity(topic.len() + payload.len() + 64);
+    let _ = write!(
+        out,
+        "{{\"topic\":\"{}\",\"base\":\"{}\",\"payload\":{}}}",
+        topic,
+        base_topic(id),
+        payload
+    );
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniserde::json::{self, Value};
+
+    const ID: DeviceId = DeviceId([
+        0x31, 0x00, 0x4a, 0x00, 0x12, 0x51, 0x33, 0x37, 0x35, 0x34, 0x39, 0x38,
+    ]);
+
+    #[test]
+    fn every_payload_is_json_and_fits_the_buffer() {
+        let parts: Vec<_> = (0..).map_while(|part| render(&ID, part)).collect();
+        assert_eq!(parts.len(), SENSORS.len());
+        for part in &parts {
+            assert!(Topic::Discovery.envelope(part).len() < 512, "{}", part);
+            assert!(json::from_str::<Value>(part).is_ok(), "{}", part);
+        }
+    }
+
+    #[test]
+    fn payload_fields() {
+        let soc = render(&ID, 0).unwrap();
+        assert!(soc.starts_with(
+            "{\"topic\":\"homeassistant/sensor/gw_31004a001251333735343938/soc/config\""
+        ));
+        assert!(soc.contains("\"uniq_id\":\"gw_31004a001251333735343938_soc\""));
+        assert!(soc.contains("\"stat_t\":\"~/summary\""));
+        assert!(soc.contains("\"val_tpl\":\"{{value_json.soc}}\""));
+        assert!(soc.contains("\"dev_cla\":\"battery\",\"unit_of_meas\":\"%\""));
+        assert!(soc.contains("\"stat_cla\":\"measurement\""));
+
+        let contactor = render(&ID, 13).unwrap();
+        assert!(contactor.contains("\"dev_cla\":\"enum\""));
+        assert!(!contactor.contains("stat_cla"));
+    }
+
+    #[test]
+    fn bus_health_reads_nested_fields() {
+        let part = |key| SENSORS.iter().position(|sensor| sensor.key == key).unwrap();
+        let state = render(&ID, part("inverter_bus_state")).unwrap();
+        assert!(state.contains("/inverter_bus_state/config\""));
+        assert!(state.contains("\"val_tpl\":\"{{value_json.inverter_bus.state}}\""));
+        assert!(state.contains("\"dev_cla\":\"enum\""));
+        let offs = render(&ID, part("bms_bus_offs")).unwrap();
+        assert!(offs.contains("\"val_tpl\":\"{{value_json.bms_bus.bus_offs}}\""));
+    }
+}
@@ -0,0 +1,161 @@
+use crate::battery::BatteryState;
+use crate::candump::Bus;
+use crate::contactor::ContactorState;
+use crate::health::BusHealth;
+use crate::protection::{Cause, Level, Status};
+use miniserde::__private::String;
+use miniserde::{json, Serialize};
+
+pub mod cells;
+pub mod discovery;
+pub mod schedule;
+pub mod stats;
+
+/// Telemetry message classes, each published at its own rate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Topic {
+    /// `MqttFormat`
+    Summary,
+    /// `cells::CellSnapshot` chunks
+    Cells,
+    /// `schedule::Alarm`, on change only
+    Alarm,
+    /// `stats::Period`
+    Stats,
+    /// `discovery::render` payloads, at boot and on request
+    Discovery,
+    /// `gateway::autobaud::AutobaudReport`, once per finished bitrate search
+    Autobaud,
+}
+
+impl Topic {
+    pub fn name(self) -> &'static str {
+        match self {
+            Topic::Summary => "summary",
+            Topic::Cells => "cells",
+            Topic::Alarm => "alarm",
+            Topic::Stats => "stats",
+            Topic::Discovery => "discovery",
+            Topic::Autobaud => "autobaud",
+        }
+    }
+
+    /// `{"topic":"<name>","data":<data>}`
+    pub fn envelope(self, data: &str) -> String {
+        let mut out = String::new();
+        out.push_str("{\"topic\":\"");
+        out.push_str(self.name());
+        out.push_str("\",\"data\":");
+        out.push_str(data);
+        out.push('}');
+        out
+    }
+}
+
+#[derive(Clone, Copy, Serialize)]
+pub struct MqttFormat {
+    soc: f32,
+    volts: f32,
+    cell_mv_high: f32,
+    cell_mv_low: f32,
+    cell_temp_high: f32,
+    cell_temp_low: f32,
+    amps: f32,
+    kwh: f32,
+    charge: f32,
+    discharge: f32,
+    bal: u8,
+    valid: bool,
+    alarm: Level,
+    trip: Option<Cause>,
+    contactor: ContactorState,
+    bms_bus: BusHealth,
+    inverter_bus: BusHealth,
+}
+
+impl MqttFormat {
+    pub fn default() -> Self {
+        Self {
+            soc: 0.0,
+            volts: 0.0,
+            cell_mv_high: 0.0,
+            cell_mv_low: 0.0,
+            cell_temp_high: 0.0,
+            cell_temp_low: 0.0,
+            amps: 0.0,
+            kwh: 0.0,
+            charge: 0.0,
+            discharge: 0.0,
+            bal: 0,
+            valid: false,
+            alarm: Level::Normal,
+            trip: None,
+            contactor: ContactorState::Open,
+            bms_bus: BusHealth::default(),
+            inverter_bus: BusHealth::default(),
+        }
+    }
+    pub fn update(&mut self, state: &BatteryState) {
+        self.soc = state.soc;
+        self.volts = state.pack_volts;
+        self.cell_mv_high = state.cell_mv_high as f32;
+        self.cell_mv_low = state.cell_mv_low as f32;
+        self.cell_temp_high = state.cell_temp_high;
+        self.cell_temp_low = state.cell_temp_low;
+        self.amps = state.current;
+        self.kwh = state.kwh_remaining;
+        self.charge = state.charge_max;
+        self.discharge = state.discharge_max;
+        self.bal = state.balancing_cells;
+        self.valid = state.valid;
+    }
+    pub fn update_protection(&mut self, status: &Status, trip: Option<Cause>) {
+        self.alarm = status.level;
+        self.trip = trip;
+    }
+    pub fn update_contactor(&mut self, state: ContactorState) {
+        self.contactor = state;
+    }
+    pub fn update_bus(&mut self, bus: Bus, health: BusHealth) {
+        match bus {
+            Bus::Bms => self.bms_bus = health,
+            Bus::Inverter => self.inverter_bus = health,
+        }
+    }
+    pub fn device_update_msg(&self) -> String {
+        json::to_string(&self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_from_battery_state() {
+        let state = BatteryState {
+            soc: 55.0,
+            pack_volts: 380.5,
+            cell_mv_high: 3950,
+            balancing_cells: 3,
+            valid: true,
+            ..Default::default()
+        };
+        let mut mqtt = MqttFormat::default();
+        mqtt.update(&state);
+        let json = mqtt.device_update_msg();
+        assert!(json.contains("\"soc\":55.0"));
+        assert!(json.contains("\"volts\":380.5"));
+        assert!(json.contains("\"cell_mv_high\":3950.0"));
+        assert!(json.contains("\"bal\":3"));
+        assert!(json.contains("\"valid\":true"));
+    }
+
+    #[test]
+    fn topic_envelope() {
+        assert_eq!(
+            Topic::Cells.envelope("{\"a\":1}"),
+            "{\"topic\":\"cells\",\"data\":{\"a\":1}}"
+        );
+    }
+}
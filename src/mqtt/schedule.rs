@@ -0,0 +1,144 @@
+//! Decides which telemetry classes are due: summary every `mqtt_rate_secs`, cells
+//! every `cells_rate_secs`, statistics every `stats_rate_secs`, and an alarm message
+//! plus an immediate summary whenever the alarm/contactor/gateway state changes.
+//! Home Assistant discovery goes out first after boot and again on request.
+
+use super::Topic;
+use crate::config::{Config, State};
+use crate::contactor::ContactorState;
+use crate::health::ErrorState;
+use crate::protection::{Cause, Level};
+use heapless::Vec;
+use miniserde::Serialize;
+
+/// Everything whose change is published immediately
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Alarm {
+    pub alarm: Level,
+    pub trip: Option<Cause>,
+    pub contactor: ContactorState,
+    pub state: State,
+    pub bms_bus: ErrorState,
+    pub inverter_bus: ErrorState,
+}
+
+pub struct Scheduler {
+    discovery: bool,
+    summary_ms: Option<u64>,
+    cells_ms: Option<u64>,
+    stats_ms: Option<u64>,
+    alarm: Option<Alarm>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            discovery: true,
+            summary_ms: None,
+            cells_ms: None,
+            stats_ms: None,
+            alarm: None,
+        }
+    }
+
+    /// Send the discovery payloads again on the next `due`
+    pub fn request_discovery(&mut self) {
+        self.discovery = true;
+    }
+
+    /// Topics to publish now, in order, leaving room for `Topic::Autobaud`
+    pub fn due(&mut self, config: &Config, alarm: Alarm, now_ms: u64) -> Vec<Topic, 6> {
+        let mut topics = Vec::new();
+        if core::mem::take(&mut self.discovery) {
+            let _ = topics.push(Topic::Discovery);
+        }
+        let elapsed = |last: Option<u64>, secs: u32| {
+            last.map_or(true, |last| now_ms.saturating_sub(last) >= secs as u64 * 1000)
+        };
+
+        let changed = self.alarm != Some(alarm);
+        if changed {
+            self.alarm = Some(alarm);
+            let _ = topics.push(Topic::Alarm);
+        }
+        if changed || elapsed(self.summary_ms, config.mqtt_rate_secs()) {
+            self.summary_ms = Some(now_ms);
+            let _ = topics.push(Topic::Summary);
+        }
+        if elapsed(self.cells_ms, config.cells_rate_secs()) {
+            self.cells_ms = Some(now_ms);
+            let _ = topics.push(Topic::Cells);
+        }
+        // the first statistics period starts now rather than being due at once
+        let stats_ms = *self.stats_ms.get_or_insert(now_ms);
+        if elapsed(Some(stats_ms), config.stats_rate_secs()) {
+            self.stats_ms = Some(now_ms);
+            let _ = topics.push(Topic::Stats);
+        }
+        topics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUIET: Alarm = Alarm {
+        alarm: Level::Normal,
+        trip: None,
+        contactor: ContactorState::Closed,
+        state: State::Online,
+        bms_bus: ErrorState::Active,
+        inverter_bus: ErrorState::Active,
+    };
+
+    #[test]
+    fn publishes_each_class_at_its_rate() {
+        let config = Config::default(); // 10 s, 60 s, 3600 s
+        let mut scheduler = Scheduler::new();
+        assert_eq!(
+            scheduler.due(&config, QUIET, 0).as_slice(),
+            [Topic::Discovery, Topic::Alarm, Topic::Summary, Topic::Cells]
+        );
+        assert!(scheduler.due(&config, QUIET, 9_999).is_empty());
+        assert_eq!(scheduler.due(&config, QUIET, 10_000).as_slice(), [Topic::Summary]);
+        assert_eq!(
+            scheduler.due(&config, QUIET, 60_000).as_slice(),
+            [Topic::Summary, Topic::Cells]
+        );
+        assert!(scheduler
+            .due(&config, QUIET, 3_600_000)
+            .contains(&Topic::Stats));
+        scheduler.request_discovery();
+        assert_eq!(
+            scheduler.due(&config, QUIET, 3_600_001).as_slice(),
+            [Topic::Discovery]
+        );
+    }
+
+    #[test]
+    fn state_change_publishes_immediately() {
+        let config = Config::default();
+        let mut scheduler = Scheduler::new();
+        scheduler.due(&config, QUIET, 0);
+        let tripped = Alarm {
+            alarm: Level::Alarm,
+            contactor: ContactorState::Opening,
+            ..QUIET
+        };
+        assert_eq!(
+            scheduler.due(&config, tripped, 1_000).as_slice(),
+            [Topic::Alarm, Topic::Summary]
+        );
+        assert!(scheduler.due(&config, tripped, 2_000).is_empty());
+
+        let bus_off = Alarm {
+            inverter_bus: ErrorState::BusOff,
+            ..tripped
+        };
+        assert_eq!(
+            scheduler.due(&config, bus_off, 2_500).as_slice(),
+            [Topic::Alarm, Topic::Summary]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+use crate::battery::BatteryState;
+use miniserde::Serialize;
+
+/// Readings further apart than this are treated as a gap, not integrated
+const MAX_STEP_MS: u64 = 60_000;
+
+/// Extremes and energy throughput over one statistics period
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Period {
+    period_secs: u32,
+    samples: u32,
+    soc_min: f32,
+    soc_max: f32,
+    volts_min: f32,
+    volts_max: f32,
+    cell_mv_min: u16,
+    cell_mv_max: u16,
+    cell_temp_min: f32,
+    cell_temp_max: f32,
+    amps_min: f32,
+    amps_max: f32,
+    ah_in: f32,
+    ah_out: f32,
+    kwh_in: f32,
+    kwh_out: f32,
+}
+
+/// Accumulates readings into the current `Period`
+#[derive(Default)]
+pub struct Stats {
+    period: Period,
+    started_ms: u64,
+    last_ms: Option<u64>,
+}
+
+impl Stats {
+    pub fn new(now_ms: u64) -> Self {
+        Self {
+            started_ms: now_ms,
+            ..Default::default()
+        }
+    }
+
+    /// Fold a valid reading into the period
+    pub fn record(&mut self, state: &BatteryState, now_ms: u64) {
+        let p = &mut self.period;
+        if p.samples == 0 {
+            p.soc_min = state.soc;
+            p.soc_max = state.soc;
+            p.volts_min = state.pack_volts;
+            p.volts_max = state.pack_volts;
+            p.cell_mv_min = state.cell_mv_low;
+            p.cell_mv_max = state.cell_mv_high;
+            p.cell_temp_min = state.cell_temp_low;
+            p.cell_temp_max = state.cell_temp_high;
+            p.amps_min = state.current;
+            p.amps_max = state.current;
+        }
+        p.samples += 1;
+        p.soc_min = p.soc_min.min(state.soc);
+        p.soc_max = p.soc_max.max(state.soc);
+        p.volts_min = p.volts_min.min(state.pack_volts);
+        p.volts_max = p.volts_max.max(state.pack_volts);
+        p.cell_mv_min = p.cell_mv_min.min(state.cell_mv_low);
+        p.cell_mv_max = p.cell_mv_max.max(state.cell_mv_high);
+        p.cell_temp_min = p.cell_temp_min.min(state.cell_temp_low);
+        p.cell_temp_max = p.cell_temp_max.max(state.cell_temp_high);
+        p.amps_min = p.amps_min.min(state.current);
+        p.amps_max = p.amps_max.max(state.current);
+
+        if let Some(last) = self.last_ms.filter(|last| now_ms.saturating_sub(*last) <= MAX_STEP_MS) {
+            let hours = now_ms.saturating_sub(last) as f32 / 3_600_000.0;
+            let ah = state.current * hours;
+            let kwh = ah * state.pack_volts / 1000.0;
+            // positive current is charging
+            match ah >= 0.0 {
+                true => {
+                    p.ah_in += ah;
+                    p.kwh_in += kwh;
+                }
+                false => {
+                    p.ah_out -= ah;
+                    p.kwh_out -= kwh;
+                }
+            }
+        }
+        self.last_ms = Some(now_ms);
+    }
+
+    /// Close the period, returning it and starting the next one
+    pub fn take(&mut self, now_ms: u64) -> Period {
+        let mut done = core::mem::take(&mut self.period);
+        done.period_secs = (now_ms.saturating_sub(self.started_ms) / 1000) as u32;
+        self.started_ms = now_ms;
+        done
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integrates_throughput_and_extremes() {
+        let mut stats = Stats::new(0);
+        let mut state = BatteryState {
+            soc: 50.0,
+            pack_volts: 400.0,
+            cell_mv_high: 3900,
+            cell_mv_low: 3850,
+            current: 10.0,
+            ..Default::default()
+        };
+        stats.record(&state, 0);
+        state.soc = 51.0;
+        stats.record(&state, 36_000); // 0.01 h at 10 A
+        state.current = -20.0;
+        stats.record(&state, 72_000); // 0.01 h at -20 A
+        let done = stats.take(3_600_000);
+        assert_eq!(done.samples, 3);
+        assert_eq!(done.period_secs, 3600);
+        assert_eq!((done.soc_min, done.soc_max), (50.0, 51.0));
+        assert_eq!((done.amps_min, done.amps_max), (-20.0, 10.0));
+        assert!((done.ah_in - 0.1).abs() < 1e-4);
+        assert!((done.ah_out - 0.2).abs() < 1e-4);
+        assert!((done.kwh_out - 0.08).abs() < 1e-4);
+        assert_eq!(stats.period.samples, 0);
+    }
+}
@@ -0,0 +1,325 @@
+//! Protection engine checking every live battery reading against the `Config` limits.
+//!
+//! `Config` limits are the alarm thresholds, a warning is raised a margin inside them.
+//! A level must persist for the quantity's delay before it is raised, and a reading
+//! must move back past the threshold by the hysteresis before it is lowered. The first
+//! alarm trips the engine, which stays latched until `reset()`.
+
+use crate::battery::BatteryState;
+use crate::config::{Config, MinMax};
+use miniserde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Quantity {
+    PackVolts,
+    CellMillivolts,
+    PackTemperature,
+    CellTemperature,
+    CurrentAmps,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum Side {
+    Low,
+    High,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, Serialize)]
+pub enum Level {
+    #[default]
+    Normal,
+    Warning,
+    Alarm,
+}
+
+/// Reading that raised a warning or tripped the protection
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Cause {
+    pub quantity: Quantity,
+    pub side: Side,
+    pub value: f32,
+    pub limit: f32,
+}
+
+/// Warning margin, hysteresis (both in the quantity's unit) and delay per quantity
+struct Tuning {
+    margin: f32,
+    hysteresis: f32,
+    delay_ms: u64,
+}
+
+impl Tuning {
+    const fn new(margin: f32, hysteresis: f32, delay_ms: u64) -> Self {
+        Self {
+            margin,
+            hysteresis,
+            delay_ms,
+        }
+    }
+}
+
+const fn tuning(quantity: Quantity) -> Tuning {
+    match quantity {
+        Quantity::PackVolts => Tuning::new(5.0, 2.0, 2000),
+        Quantity::CellMillivolts => Tuning::new(50.0, 20.0, 2000),
+        Quantity::PackTemperature => Tuning::new(5.0, 2.0, 5000),
+        Quantity::CellTemperature => Tuning::new(5.0, 2.0, 5000),
+        Quantity::CurrentAmps => Tuning::new(5.0, 2.0, 1000),
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Check {
+    level: Level,
+    /// Higher level waiting out its delay, and since when
+    pending: Option<(Level, u64)>,
+}
+
+impl Check {
+    /// `excess` is how far the reading is past the alarm limit (positive = outside)
+    fn update(&mut self, excess: f32, tuning: &Tuning, now_ms: u64) -> Level {
+        let raw = if excess >= 0.0 {
+            Level::Alarm
+        } else if excess >= -tuning.margin {
+            Level::Warning
+        } else {
+            Level::Normal
+        };
+        if raw > self.level {
+            match self.pending {
+                Some((level, since)) if level == raw => {
+                    if now_ms.saturating_sub(since) >= tuning.delay_ms {
+                        self.level = raw;
+                        self.pending = None;
+                    }
+                }
+                _ => self.pending = Some((raw, now_ms)),
+            }
+            return self.level;
+        }
+        self.pending = None;
+        // only step down once clear of the current level's threshold by the hysteresis
+        let threshold = match self.level {
+            Level::Alarm => 0.0,
+            Level::Warning => -tuning.margin,
+            Level::Normal => return self.level,
+        };
+        if excess < threshold - tuning.hysteresis {
+            self.level = raw;
+        }
+        self.level
+    }
+}
+
+const QUANTITIES: [Quantity; 5] = [
+    Quantity::PackVolts,
+    Quantity::CellMillivolts,
+    Quantity::PackTemperature,
+    Quantity::CellTemperature,
+    Quantity::CurrentAmps,
+];
+
+/// Result of one evaluation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Status {
+    /// Worst current level over all checks
+    pub level: Level,
+    /// Worst reading, `None` when everything is normal
+    pub cause: Option<Cause>,
+    /// Set only on the evaluation that tripped the protection
+    pub tripped_now: bool,
+}
+
+#[derive(Default)]
+pub struct Protection {
+    /// [quantity][side]
+    checks: [[Check; 2]; 5],
+    trip: Option<Cause>,
+    /// Level of the last evaluation
+    level: Level,
+}
+
+impl Protection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cause of the latched trip, if any
+    pub fn trip(&self) -> Option<Cause> {
+        self.trip
+    }
+
+    /// Worst level seen by the last evaluation
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    /// Clear the latched trip and all timers
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Check a reading against the config limits, `now_ms` is a monotonic timestamp
+    pub fn evaluate(&mut self, config: &Config, state: &BatteryState, now_ms: u64) -> Status {
+        let mut status = Status {
+            level: Level::Normal,
+            cause: None,
+            tripped_now: false,
+        };
+        for (q, quantity) in QUANTITIES.into_iter().enumerate() {
+            let tuning = tuning(quantity);
+            let (min, max) = limits(config, quantity);
+            for (s, side) in [Side::Low, Side::High].into_iter().enumerate() {
+                let value = reading(state, quantity, side);
+                let (excess, limit) = match side {
+                    Side::Low => (min - value, min),
+                    Side::High => (value - max, max),
+                };
+                let level = self.checks[q][s].update(excess, &tuning, now_ms);
+                if level > status.level {
+                    status.level = level;
+                    status.cause = Some(Cause {
+                        quantity,
+                        side,
+                        value,
+                        limit,
+                    });
+                }
+            }
+        }
+        if status.level == Level::Alarm && self.trip.is_none() {
+            self.trip = status.cause;
+            status.tripped_now = true;
+        }
+        self.level = status.level;
+        status
+    }
+}
+
+/// Alarm limits for a quantity, in the same unit as `reading`
+fn limits(config: &Config, quantity: Quantity) -> (f32, f32) {
+    fn f32s<T: Copy + Into<f32>>(minmax: &MinMax<T>) -> (f32, f32) {
+        (minmax.min().into(), minmax.max().into())
+    }
+    match quantity {
+        Quantity::PackVolts => f32s(config.pack_volts()),
+        Quantity::CellMillivolts => f32s(config.cell_millivolts()),
+        Quantity::PackTemperature => f32s(config.pack_temperature()),
+        Quantity::CellTemperature => f32s(config.cell_temperature()),
+        Quantity::CurrentAmps => f32s(config.current_amps()),
+    }
+}
+
+/// Reading checked against one side of a quantity, cells use their extreme on that side
+fn reading(state: &BatteryState, quantity: Quantity, side: Side) -> f32 {
+    match (quantity, side) {
+        (Quantity::PackVolts, _) => state.pack_volts,
+        (Quantity::CellMillivolts, Side::Low) => state.cell_mv_low as f32,
+        (Quantity::CellMillivolts, Side::High) => state.cell_mv_high as f32,
+        (Quantity::PackTemperature, _) => state.temp_avg,
+        (Quantity::CellTemperature, Side::Low) => state.cell_temp_low,
+        (Quantity::CellTemperature, Side::High) => state.cell_temp_high,
+        (Quantity::CurrentAmps, _) => state.current,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn healthy() -> BatteryState {
+        BatteryState {
+            pack_volts: 360.0,
+            cell_mv_high: 3800,
+            cell_mv_low: 3750,
+            cell_temp_high: 25.0,
+            cell_temp_low: 20.0,
+            temp_avg: 22.0,
+            current: 10.0,
+            valid: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn healthy_pack_is_normal() {
+        let mut protection = Protection::new();
+        let status = protection.evaluate(&Config::default(), &healthy(), 0);
+        assert_eq!(status.level, Level::Normal);
+        assert_eq!(status.cause, None);
+    }
+
+    #[test]
+    fn alarm_waits_for_delay_then_latches() {
+        let config = Config::default();
+        let mut protection = Protection::new();
+        let hot = BatteryState {
+            cell_mv_high: 4250,
+            ..healthy()
+        };
+        assert_eq!(protection.evaluate(&config, &hot, 0).level, Level::Normal);
+        assert_eq!(protection.evaluate(&config, &hot, 1999).level, Level::Normal);
+        let status = protection.evaluate(&config, &hot, 2000);
+        assert_eq!(status.level, Level::Alarm);
+        assert!(status.tripped_now);
+        let cause = protection.trip().unwrap();
+        assert_eq!(cause.quantity, Quantity::CellMillivolts);
+        assert_eq!(cause.side, Side::High);
+        assert_eq!(cause.limit, 4200.0);
+
+        // back to normal readings, trip stays latched until reset
+        for t in [3000, 10_000] {
+            assert!(!protection.evaluate(&config, &healthy(), t).tripped_now);
+        }
+        assert!(protection.trip().is_some());
+        protection.reset();
+        assert!(protection.trip().is_none());
+    }
+
+    #[test]
+    fn short_excursion_is_ignored() {
+        let config = Config::default();
+        let mut protection = Protection::new();
+        let surge = BatteryState {
+            current: -60.0,
+            ..healthy()
+        };
+        protection.evaluate(&config, &surge, 0);
+        protection.evaluate(&config, &healthy(), 500);
+        protection.evaluate(&config, &surge, 900);
+        assert_eq!(protection.evaluate(&config, &surge, 1800).level, Level::Normal);
+        assert!(protection.trip().is_none());
+    }
+
+    #[test]
+    fn warning_clears_with_hysteresis() {
+        let config = Config::default();
+        let mut protection = Protection::new();
+        let warm = |t: f32| BatteryState {
+            cell_temp_high: t,
+            ..healthy()
+        };
+        // limit 50, warning from 45
+        protection.evaluate(&config, &warm(46.0), 0);
+        assert_eq!(protection.evaluate(&config, &warm(46.0), 5000).level, Level::Warning);
+        // inside the warning band but within hysteresis
+        assert_eq!(protection.evaluate(&config, &warm(43.5), 6000).level, Level::Warning);
+        assert_eq!(protection.evaluate(&config, &warm(42.5), 7000).level, Level::Normal);
+    }
+
+    #[test]
+    fn low_side_uses_cell_minimum() {
+        let config = Config::default();
+        let mut protection = Protection::new();
+        let empty = BatteryState {
+            cell_mv_low: 2900,
+            ..healthy()
+        };
+        protection.evaluate(&config, &empty, 0);
+        let status = protection.evaluate(&config, &empty, 2000);
+        assert_eq!(status.level, Level::Alarm);
+        let cause = status.cause.unwrap();
+        assert_eq!(cause.side, Side::Low);
+        assert_eq!(cause.value, 2900.0);
+    }
+}
@@ -0,0 +1,154 @@
+//! Scripts for the simulator's battery and inverter stand-ins.
+//!
+//! ```text
+//! // comment
+//! @1500 18DAF1DB#037F2278   send at 1500 ms after start
+//! 18DADBF1#03229001 => 18DAF1DB#1008629001  reply when a frame starting with this arrives
+//! ```
+//! Reply rules match on ID and a data prefix, the first matching rule wins.
+//! A `candump -L` capture can be replayed instead with `Script::from_candump`.
+
+use crate::candump::{self, Bus, CandumpError};
+use crate::frame::{self, FrameTextError};
+use bxcan::Frame;
+use heapless::Vec;
+
+/// Timed frames and reply rules per script
+pub const MAX_ENTRIES: usize = 256;
+
+#[derive(Debug, PartialEq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub kind: ScriptErrorKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ScriptErrorKind {
+    Frame(FrameTextError),
+    Candump(CandumpError),
+    BadTime,
+    Syntax,
+    TooLong,
+}
+
+#[derive(Default)]
+pub struct Script {
+    /// (ms from start, frame), in file order
+    pub timed: Vec<(u64, Frame), MAX_ENTRIES>,
+    /// (request, reply)
+    pub replies: Vec<(Frame, Frame), MAX_ENTRIES>,
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Self, ScriptError> {
+        let mut script = Self::default();
+        for (line, entry) in text.lines().enumerate() {
+            let line = line + 1;
+            let err = |kind| ScriptError { line, kind };
+            let entry = entry.split("//").next().unwrap_or("").trim();
+            if entry.is_empty() {
+                continue;
+            }
+            if let Some(timed) = entry.strip_prefix('@') {
+                let (at, text) = timed
+                    .split_once(char::is_whitespace)
+                    .ok_or(err(ScriptErrorKind::BadTime))?;
+                let at = at.parse().map_err(|_| err(ScriptErrorKind::BadTime))?;
+                let frame = frame::parse(text).map_err(|e| err(ScriptErrorKind::Frame(e)))?;
+                script
+                    .timed
+                    .push((at, frame))
+                    .map_err(|_| err(ScriptErrorKind::TooLong))?;
+            } else if let Some((request, reply)) = entry.split_once("=>") {
+                let request = frame::parse(request).map_err(|e| err(ScriptErrorKind::Frame(e)))?;
+                let reply = frame::parse(reply).map_err(|e| err(ScriptErrorKind::Frame(e)))?;
+                script
+                    .replies
+                    .push((request, reply))
+                    .map_err(|_| err(ScriptErrorKind::TooLong))?;
+            } else {
+                return Err(err(ScriptErrorKind::Syntax));
+            }
+        }
+        Ok(script)
+    }
+
+    /// Replay the frames a capture saw on `bus`, timed relative to the first line
+    pub fn from_candump(text: &str, bus: Bus) -> Result<Self, ScriptError> {
+        let mut script = Self::default();
+        let mut start = None;
+        for (line, entry) in text.lines().enumerate() {
+            let err = |kind| ScriptError { line: line + 1, kind };
+            if entry.trim().is_empty() {
+                continue;
+            }
+            let record = candump::parse_line(entry).map_err(|e| err(ScriptErrorKind::Candump(e)))?;
+            let start = *start.get_or_insert(record.at_us);
+            if record.bus != bus {
+                continue;
+            }
+            let at = record.at_us.saturating_sub(start) / 1000;
+            script
+                .timed
+                .push((at, record.frame))
+                .map_err(|_| err(ScriptErrorKind::TooLong))?;
+        }
+        Ok(script)
+    }
+
+    /// Reply for a frame sent by the gateway, if a rule matches
+    pub fn reply_to(&self, frame: &Frame) -> Option<&Frame> {
+        self.replies
+            .iter()
+            .find(|(request, _)| {
+                request.id() == frame.id()
+                    && match (request.data(), frame.data()) {
+                        (Some(prefix), Some(data)) => data.starts_with(prefix),
+                        (None, None) => true,
+                        _ => false,
+                    }
+            })
+            .map(|(_, reply)| reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "
+        // ZE50 stand-in
+        @0 373#00
+        @1500 18DAF1DB#037F2278 // negative response
+        18DADBF1#032290 => 18DAF1DB#0462900102
+    ";
+
+    #[test]
+    fn parses_timed_and_replies() {
+        let script = Script::parse(SCRIPT).unwrap();
+        assert_eq!(script.timed.len(), 2);
+        assert_eq!(script.timed[1].0, 1500);
+        let request = frame::parse("18DADBF1#03229001FFFFFFFF").unwrap();
+        let reply = script.reply_to(&request).unwrap();
+        assert_eq!(reply.data().unwrap().as_ref(), &[0x04, 0x62, 0x90, 0x01, 0x02]);
+        assert!(script.reply_to(&frame::parse("18DADBF1#03229101").unwrap()).is_none());
+    }
+
+    #[test]
+    fn replays_one_bus_from_candump() {
+        let log = "(100.000000) can1 351#00\n(100.250000) can0 155#0102\n(101.000000) can0 155#0304\n";
+        let script = Script::from_candump(log, Bus::Bms).unwrap();
+        assert_eq!(script.timed.len(), 2);
+        assert_eq!(script.timed[0].0, 250);
+        assert_eq!(script.timed[1].0, 1000);
+        assert!(script.replies.is_empty());
+    }
+
+    #[test]
+    fn reports_line_numbers() {
+        let err = Script::parse("@0 373#00\n@x 373#00").err().unwrap();
+        assert_eq!(err, ScriptError { line: 2, kind: ScriptErrorKind::BadTime });
+        let err = Script::parse("373#00").err().unwrap();
+        assert_eq!(err.line, 1);
+    }
+}
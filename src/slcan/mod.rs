@@ -0,0 +1,268 @@
+//! Lawicel SLCAN, the ASCII protocol of USB-CAN adapters, so `slcand` and SavvyCAN
+//! can sniff and inject on one bus through the gateway (`Config::slcan_bus`).
+//!
+//! Commands end in `\r`, the reply is `\r` for OK or BEL for an error:
+//!
+//! * `O` open, `L` open listen-only, `C` close
+//! * `Sn` bitrate, 0-8 = 10k, 20k, 50k, 100k, 125k, 250k, 500k, 800k, 1M, only the
+//!   rate the bus already runs at is accepted
+//! * `tiiildd..` / `Tiiiiiiiildd..` transmit a standard/extended frame, `r`/`R` a remote
+//!   frame, answered with `z\r` / `Z\r`
+//! * `V` version, `N` serial number, `F` status flags, `Z0`/`Z1` timestamps off/on
+//!
+//! Received frames are sent in the same `t`/`T`/`r`/`R` form, followed by a 4 digit
+//! millisecond timestamp (0-59999) when enabled.
+
+use crate::frame;
+use crate::timing::{BITRATES, DEFAULT_BITRATE};
+use bxcan::{Frame, Id};
+use core::fmt::Write;
+
+/// Longest line: `T12345678` + dlc + 16 data digits + timestamp + `\r`
+pub type Line = heapless::String<32>;
+
+const OK: char = '\r';
+const BELL: char = '\x07';
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Normal,
+    /// Sniff only, transmit commands are refused
+    ListenOnly,
+}
+
+/// What the UART task has to do after a command
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    None,
+    Open(Mode),
+    Close,
+    /// Queue the frame on the SLCAN bus
+    Transmit(Frame),
+}
+
+/// Adapter state for one UART
+pub struct Session {
+    mode: Option<Mode>,
+    timestamps: bool,
+    /// Rate of the bridged bus, bit/s
+    bitrate: u32,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            mode: None,
+            timestamps: false,
+            bitrate: DEFAULT_BITRATE,
+        }
+    }
+
+    /// Rate the bridged bus runs at, the only one `Sn` accepts
+    pub fn set_bitrate(&mut self, bitrate: u32) {
+        self.bitrate = bitrate;
+    }
+
+    /// Channel opened with `O` or `L`, frames should be forwarded
+    pub fn is_open(&self) -> bool {
+        self.mode.is_some()
+    }
+
+    /// Handle one command without its `\r`, writing the reply into `reply`
+    pub fn handle(&mut self, command: &str, reply: &mut Line) -> Action {
+        reply.clear();
+        let (action, text) = match self.run(command) {
+            Ok(result) => result,
+            Err(()) => {
+                let _ = reply.push(BELL);
+                return Action::None;
+            }
+        };
+        let _ = reply.push_str(text);
+        let _ = reply.push(OK);
+        action
+    }
+
+    fn run(&mut self, command: &str) -> Result<(Action, &'static str), ()> {
+        let mut chars = command.chars();
+        let Some(code) = chars.next() else {
+            // a bare `\r`, sent by slcand to flush the adapter
+            return Ok((Action::None, ""));
+        };
+        let args = chars.as_str();
+        match (code, self.mode) {
+            ('O', None) => self.open(Mode::Normal),
+            ('L', None) => self.open(Mode::ListenOnly),
+            ('C', _) => {
+                let was_open = self.mode.take().is_some();
+                Ok((
+                    if was_open {
+                        Action::Close
+                    } else {
+                        Action::None
+                    },
+                    "",
+                ))
+            }
+            ('S', None) => {
+                let index: usize = args.parse().map_err(|_| ())?;
+                match BITRATES.get(index) {
+                    Some(&bitrate) if bitrate == self.bitrate => Ok((Action::None, "")),
+                    _ => Err(()),
+                }
+            }
+            ('t' | 'T' | 'r' | 'R', Some(Mode::Normal)) => {
+                let frame = parse_frame(code, args)?;
+                let ack = if matches!(code, 't' | 'r') { "z" } else { "Z" };
+                Ok((Action::Transmit(frame), ack))
+            }
+            ('V', _) => Ok((Action::None, "V1013")),
+            ('N', _) => Ok((Action::None, "NGW01")),
+            ('F', Some(_)) => Ok((Action::None, "F00")),
+            ('Z', None) => {
+                self.timestamps = match args {
+                    "0" => false,
+                    "1" => true,
+                    _ => return Err(()),
+                };
+                Ok((Action::None, ""))
+            }
+            _ => Err(()),
+        }
+    }
+
+    fn open(&mut self, mode: Mode) -> Result<(Action, &'static str), ()> {
+        self.mode = Some(mode);
+        Ok((Action::Open(mode), ""))
+    }
+
+    /// Line for a received frame, including the `\r`
+    pub fn write_frame(&self, out: &mut Line, frame: &Frame, at_us: u64) -> core::fmt::Result {
+        out.clear();
+        let remote = frame.data().is_none();
+        match (frame.id(), remote) {
+            (Id::Standard(id), false) => write!(out, "t{:03X}", id.as_raw())?,
+            (Id::Standard(id), true) => write!(out, "r{:03X}", id.as_raw())?,
+            (Id::Extended(id), false) => write!(out, "T{:08X}", id.as_raw())?,
+            (Id::Extended(id), true) => write!(out, "R{:08X}", id.as_raw())?,
+        }
+        write!(out, "{}", frame.dlc())?;
+        for byte in frame.data().map(|data| data.iter()).into_iter().flatten() {
+            write!(out, "{:02X}", byte)?;
+        }
+        if self.timestamps {
+            write!(out, "{:04X}", (at_us / 1000 % 60_000) as u16)?;
+        }
+        out.write_char(OK)
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `iii` or `iiiiiiii`, then the length digit and for data frames 2 hex digits per byte
+fn parse_frame(code: char, args: &str) -> Result<Frame, ()> {
+    let id_len = if code.is_ascii_uppercase() { 8 } else { 3 };
+    let id = args.get(..id_len).ok_or(())?;
+    let dlc = args.get(id_len..id_len + 1).ok_or(())?;
+    let data = &args[id_len + 1..];
+    let dlc: u8 = dlc.parse().map_err(|_| ())?;
+    // rebuild the can-utils text form and let `frame::parse` check it
+    let mut text: heapless::String<32> = heapless::String::new();
+    let _ = write!(text, "{}#", id);
+    if matches!(code, 'r' | 'R') {
+        if !data.is_empty() {
+            return Err(());
+        }
+        let _ = write!(text, "R{}", dlc);
+    } else {
+        if data.len() != dlc as usize * 2 {
+            return Err(());
+        }
+        text.push_str(data).map_err(|_| ())?;
+    }
+    frame::parse(&text).map_err(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bxcan::{ExtendedId, StandardId};
+
+    fn run(session: &mut Session, command: &str) -> (Action, Line) {
+        let mut reply = Line::new();
+        let action = session.handle(command, &mut reply);
+        (action, reply)
+    }
+
+    #[test]
+    fn slcand_startup_sequence() {
+        let mut session = Session::new();
+        assert_eq!(run(&mut session, "C"), (Action::None, "\r".into()));
+        assert_eq!(run(&mut session, "S6"), (Action::None, "\r".into()));
+        assert_eq!(run(&mut session, "S4").1, "\x07");
+        assert_eq!(
+            run(&mut session, "O"),
+            (Action::Open(Mode::Normal), "\r".into())
+        );
+        assert!(session.is_open());
+        // no bitrate change or second open while the channel is open
+        assert_eq!(run(&mut session, "S6").1, "\x07");
+        assert_eq!(run(&mut session, "O").1, "\x07");
+        assert_eq!(run(&mut session, "F").1, "F00\r");
+        assert_eq!(run(&mut session, "C"), (Action::Close, "\r".into()));
+        assert!(!session.is_open());
+    }
+
+    #[test]
+    fn accepts_only_the_bus_bitrate() {
+        let mut session = Session::new();
+        session.set_bitrate(250_000);
+        assert_eq!(run(&mut session, "S6").1, "\x07");
+        assert_eq!(run(&mut session, "S5").1, "\r");
+        assert_eq!(run(&mut session, "S9").1, "\x07");
+    }
+
+    #[test]
+    fn transmits_frames() {
+        let mut session = Session::new();
+        run(&mut session, "O");
+        let (action, reply) = run(&mut session, "t1232DEAD");
+        let expected = Frame::new_data(StandardId::new(0x123).unwrap(), [0xDE, 0xAD]);
+        assert_eq!(action, Action::Transmit(expected));
+        assert_eq!(reply, "z\r");
+
+        let (action, reply) = run(&mut session, "R18DAF1DB4");
+        let expected = Frame::new_remote(ExtendedId::new(0x18DAF1DB).unwrap(), 4);
+        assert_eq!(action, Action::Transmit(expected));
+        assert_eq!(reply, "Z\r");
+
+        // length digit and data disagree, id too long
+        assert_eq!(run(&mut session, "t1233DEAD").1, "\x07");
+        assert_eq!(run(&mut session, "t8001").1, "\x07");
+    }
+
+    #[test]
+    fn listen_only_refuses_transmit() {
+        let mut session = Session::new();
+        assert_eq!(run(&mut session, "L").0, Action::Open(Mode::ListenOnly));
+        assert_eq!(run(&mut session, "t1230").1, "\x07");
+    }
+
+    #[test]
+    fn formats_received_frames() {
+        let mut session = Session::new();
+        let mut line = Line::new();
+        let frame = Frame::new_data(ExtendedId::new(0x1871).unwrap(), [1, 2, 3, 4, 5, 6, 7, 8]);
+        session.write_frame(&mut line, &frame, 0).unwrap();
+        assert_eq!(line, "T0000187180102030405060708\r");
+
+        run(&mut session, "Z1");
+        let frame = Frame::new_remote(StandardId::new(0x7FF).unwrap(), 0);
+        session.write_frame(&mut line, &frame, 61_234_567).unwrap();
+        assert_eq!(line, "r7FF004D2\r");
+    }
+}
@@ -0,0 +1,284 @@
+//! Wear-levelled record store on internal flash.
+//!
+//! The region is split into fixed `SLOT_SIZE` slots written round-robin, each holding
+//! one record: magic, schema version, length, sequence number, payload and CRC-32.
+//! The valid record with the highest sequence number wins. A page is only erased when
+//! the writer moves into it, so the previous record survives a power cut mid-write.
+
+use embedded_storage::nor_flash::NorFlash;
+use heapless::Vec;
+
+pub const SLOT_SIZE: usize = 64;
+const HEADER_LEN: usize = 12;
+const CRC_LEN: usize = 4;
+/// Largest payload one slot can hold
+pub const MAX_PAYLOAD: usize = SLOT_SIZE - HEADER_LEN - CRC_LEN;
+const MAGIC: u32 = 0x4746_4353; // "SCFG"
+
+pub type Payload = Vec<u8, MAX_PAYLOAD>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// Schema version of `payload`, interpreted by the owner
+    pub version: u8,
+    pub payload: Payload,
+}
+
+#[derive(Debug)]
+pub enum StorageError<E> {
+    Flash(E),
+    /// Payload longer than `MAX_PAYLOAD`
+    TooLong,
+    /// Read-back after writing did not match
+    Verify,
+}
+
+pub struct SlotStore<F: NorFlash> {
+    flash: F,
+    /// Offset of the first page, as passed to `NorFlash`
+    base: u32,
+    pages: u32,
+    /// (slot, sequence) of the newest valid record
+    latest: Option<(u32, u32)>,
+}
+
+impl<F: NorFlash> SlotStore<F> {
+    /// `pages` erase pages starting at `base`, at least two so one always holds
+    /// the current record while the other is erased
+    pub fn new(flash: F, base: u32, pages: u32) -> Self {
+        assert!(pages >= 2 && F::ERASE_SIZE % SLOT_SIZE == 0);
+        Self {
+            flash,
+            base,
+            pages,
+            latest: None,
+        }
+    }
+
+    fn slots_per_page(&self) -> u32 {
+        (F::ERASE_SIZE / SLOT_SIZE) as u32
+    }
+
+    fn slots(&self) -> u32 {
+        self.slots_per_page() * self.pages
+    }
+
+    fn offset(&self, slot: u32) -> u32 {
+        self.base + slot * SLOT_SIZE as u32
+    }
+
+    fn read_slot(&mut self, slot: u32) -> Result<[u8; SLOT_SIZE], F::Error> {
+        let mut raw = [0xff; SLOT_SIZE];
+        self.flash.read(self.offset(slot), &mut raw)?;
+        Ok(raw)
+    }
+
+    /// Newest valid record, `None` if the region is blank or every slot is corrupt
+    pub fn load(&mut self) -> Result<Option<Record>, F::Error> {
+        let mut newest: Option<(u32, u32, Record)> = None;
+        for slot in 0..self.slots() {
+            let Some((seq, record)) = decode(&self.read_slot(slot)?) else {
+                continue;
+            };
+            if newest.as_ref().map_or(true, |(_, s, _)| seq > *s) {
+                newest = Some((slot, seq, record));
+            }
+        }
+        self.latest = newest.as_ref().map(|(slot, seq, _)| (*slot, *seq));
+        Ok(newest.map(|(_, _, record)| record))
+    }
+
+    /// Append a record after the current one, erasing the next page when crossing into it.
+    /// Call `load` first so the write position is known.
+    pub fn save(&mut self, version: u8, payload: &[u8]) -> Result<(), StorageError<F::Error>> {
+        if payload.len() > MAX_PAYLOAD {
+            return Err(StorageError::TooLong);
+        }
+        let (mut slot, seq) = match self.latest {
+            Some((slot, seq)) => ((slot + 1) % self.slots(), seq.wrapping_add(1)),
+            None => (0, 0),
+        };
+        loop {
+            if slot % self.slots_per_page() == 0 {
+                let from = self.offset(slot);
+                self.flash
+                    .erase(from, from + F::ERASE_SIZE as u32)
+                    .map_err(StorageError::Flash)?;
+                break;
+            }
+            if self.read_slot(slot).map_err(StorageError::Flash)? == [0xff; SLOT_SIZE] {
+                break;
+            }
+            // partially written slot, skip to the next page
+            slot = (slot / self.slots_per_page() + 1) * self.slots_per_page() % self.slots();
+        }
+
+        let raw = encode(seq, version, payload);
+        self.flash
+            .write(self.offset(slot), &raw)
+            .map_err(StorageError::Flash)?;
+        if self.read_slot(slot).map_err(StorageError::Flash)? != raw {
+            return Err(StorageError::Verify);
+        }
+        self.latest = Some((slot, seq));
+        Ok(())
+    }
+}
+
+fn encode(seq: u32, version: u8, payload: &[u8]) -> [u8; SLOT_SIZE] {
+    let mut raw = [0xff; SLOT_SIZE];
+    raw[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    raw[4] = version;
+    raw[5] = payload.len() as u8;
+    raw[6..8].copy_from_slice(&[0, 0]);
+    raw[8..12].copy_from_slice(&seq.to_le_bytes());
+    let end = HEADER_LEN + payload.len();
+    raw[HEADER_LEN..end].copy_from_slice(payload);
+    let crc = crc32(&raw[..end]);
+    raw[SLOT_SIZE - CRC_LEN..].copy_from_slice(&crc.to_le_bytes());
+    raw
+}
+
+fn decode(raw: &[u8; SLOT_SIZE]) -> Option<(u32, Record)> {
+    if raw[0..4] != MAGIC.to_le_bytes() {
+        return None;
+    }
+    let len = raw[5] as usize;
+    if len > MAX_PAYLOAD {
+        return None;
+    }
+    let end = HEADER_LEN + len;
+    let crc = u32::from_le_bytes(raw[SLOT_SIZE - CRC_LEN..].try_into().ok()?);
+    if crc != crc32(&raw[..end]) {
+        return None;
+    }
+    let seq = u32::from_le_bytes(raw[8..12].try_into().ok()?);
+    let record = Record {
+        version: raw[4],
+        payload: Vec::from_slice(&raw[HEADER_LEN..end]).ok()?,
+    };
+    Some((seq, record))
+}
+
+/// CRC-32 (IEEE 802.3, reflected, as used by zlib)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+    !crc
+}
+
+/// Erased-to-0xff RAM flash for host tests
+#[cfg(test)]
+pub(crate) mod ram {
+    use embedded_storage::nor_flash::{
+        ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash,
+    };
+
+    pub const PAGE: usize = 256;
+
+    pub struct RamFlash {
+        pub data: [u8; PAGE * 2],
+        pub erases: usize,
+    }
+
+    impl RamFlash {
+        pub fn new() -> Self {
+            Self {
+                data: [0xff; PAGE * 2],
+                erases: 0,
+            }
+        }
+    }
+
+    impl ErrorType for RamFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for RamFlash {
+        const READ_SIZE: usize = 1;
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+            Ok(())
+        }
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    impl NorFlash for RamFlash {
+        const WRITE_SIZE: usize = 2;
+        const ERASE_SIZE: usize = PAGE;
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            self.data[from as usize..to as usize].fill(0xff);
+            self.erases += 1;
+            Ok(())
+        }
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            for (cell, byte) in self.data[offset..].iter_mut().zip(bytes) {
+                // NOR flash can only clear bits
+                *cell &= *byte;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ram::{RamFlash, PAGE};
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn newest_record_wins_across_page_wrap() {
+        let mut store = SlotStore::new(RamFlash::new(), 0, 2);
+        assert_eq!(store.load().unwrap(), None);
+        let writes = 3 * PAGE / SLOT_SIZE;
+        for n in 0..writes {
+            store.save(1, &[n as u8]).unwrap();
+        }
+        // first write plus one erase per page crossing
+        assert_eq!(store.flash.erases, 3);
+
+        let mut reopened = SlotStore::new(store.flash, 0, 2);
+        let record = reopened.load().unwrap().unwrap();
+        assert_eq!(record.payload[0], (writes - 1) as u8);
+        reopened.save(1, &[0xaa]).unwrap();
+        assert_eq!(reopened.load().unwrap().unwrap().payload[0], 0xaa);
+    }
+
+    #[test]
+    fn corrupt_latest_falls_back_to_previous() {
+        let mut store = SlotStore::new(RamFlash::new(), 0, 2);
+        store.load().unwrap();
+        store.save(2, b"old").unwrap();
+        store.save(2, b"new").unwrap();
+        // torn write of the newest slot
+        store.flash.data[SLOT_SIZE + HEADER_LEN] = 0;
+        let record = store.load().unwrap().unwrap();
+        assert_eq!(record.payload.as_slice(), b"old");
+    }
+
+    #[test]
+    fn rejects_oversized_payload() {
+        let mut store = SlotStore::new(RamFlash::new(), 0, 2);
+        assert!(matches!(
+            store.save(1, &[0; MAX_PAYLOAD + 1]),
+            Err(StorageError::TooLong)
+        ));
+    }
+}
@@ -0,0 +1,146 @@
+//! bxCAN bit timing (BTR) from the APB1 clock, a bitrate and a sample point.
+//!
+//! One bit is `1 + seg1 + seg2` time quanta of `prescaler / pclk1`, sampled after
+//! `1 + seg1`. The register allows 1..=1024 for the prescaler, 1..=16 for seg1 and
+//! 1..=8 for seg2. `solve` only accepts exact bitrates and picks the quanta count
+//! whose sample point lands nearest the request, the most quanta on a tie.
+
+/// Rates `Config` accepts, also the SLCAN `S0`-`S8` table
+pub const BITRATES: [u32; 9] = [
+    10_000, 20_000, 50_000, 100_000, 125_000, 250_000, 500_000, 800_000, 1_000_000,
+];
+/// Rate both buses ran at before it was configurable
+pub const DEFAULT_BITRATE: u32 = 500_000;
+/// CiA 301 recommendation, 87.5%
+pub const SAMPLE_POINT_PERMILLE: u16 = 875;
+
+/// Fewer quanta leave too little room to place the sample point
+const MIN_QUANTA: u32 = 8;
+const MAX_QUANTA: u32 = 25;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BitTiming {
+    pub prescaler: u16,
+    pub seg1: u8,
+    pub seg2: u8,
+    /// Resynchronisation jump width, quanta
+    pub sjw: u8,
+}
+
+impl BitTiming {
+    /// BTR value for `set_bit_timing`, normal mode
+    pub fn btr(&self) -> u32 {
+        (self.sjw as u32 - 1) << 24
+            | (self.seg2 as u32 - 1) << 20
+            | (self.seg1 as u32 - 1) << 16
+            | (self.prescaler as u32 - 1)
+    }
+
+    pub fn quanta(&self) -> u32 {
+        1 + self.seg1 as u32 + self.seg2 as u32
+    }
+
+    pub fn bitrate(&self, pclk_hz: u32) -> u32 {
+        pclk_hz / (self.prescaler as u32 * self.quanta())
+    }
+
+    pub fn sample_point_permille(&self) -> u16 {
+        ((1 + self.seg1 as u32) * 1000 / self.quanta()) as u16
+    }
+}
+
+/// `bitrate` is not an exact division of the clock
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoTiming;
+
+/// Timing for `bitrate` from a `pclk_hz` APB1 clock
+pub fn solve(
+    pclk_hz: u32,
+    bitrate: u32,
+    sample_point_permille: u16,
+) -> Result<BitTiming, NoTiming> {
+    let mut best: Option<(u32, BitTiming)> = None;
+    for quanta in (MIN_QUANTA..=MAX_QUANTA).rev() {
+        let Some(per_bit) = bitrate.checked_mul(quanta) else {
+            continue;
+        };
+        if per_bit == 0 || pclk_hz % per_bit != 0 {
+            continue;
+        }
+        let prescaler = pclk_hz / per_bit;
+        if !(1..=1024).contains(&prescaler) {
+            continue;
+        }
+        let sample = (quanta * sample_point_permille as u32 + 500) / 1000;
+        let seg1 = sample.saturating_sub(1).clamp(1, 16);
+        let seg2 = quanta - 1 - seg1;
+        if !(1..=8).contains(&seg2) {
+            continue;
+        }
+        let timing = BitTiming {
+            prescaler: prescaler as u16,
+            seg1: seg1 as u8,
+            seg2: seg2 as u8,
+            sjw: seg2.min(4) as u8,
+        };
+        let error = timing
+            .sample_point_permille()
+            .abs_diff(sample_point_permille) as u32;
+        // largest quanta first, only a strictly better sample point replaces it
+        if best.map_or(true, |(best_error, _)| error < best_error) {
+            best = Some((error, timing));
+        }
+    }
+    best.map(|(_, timing)| timing).ok_or(NoTiming)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solves_the_standard_rates() {
+        for pclk in [16_000_000, 24_000_000, 32_000_000, 36_000_000] {
+            for bitrate in BITRATES {
+                let timing = solve(pclk, bitrate, SAMPLE_POINT_PERMILLE).unwrap();
+                assert_eq!(timing.bitrate(pclk), bitrate);
+                let sample = timing.sample_point_permille();
+                assert!((800..=900).contains(&sample), "{pclk} {bitrate} {sample}");
+                assert!(timing.sjw <= timing.seg2);
+            }
+        }
+    }
+
+    #[test]
+    fn encodes_btr() {
+        // 24 MHz / 3 / 16 quanta, sampled at 14/16
+        let timing = solve(24_000_000, 500_000, 875).unwrap();
+        assert_eq!(
+            timing,
+            BitTiming {
+                prescaler: 3,
+                seg1: 13,
+                seg2: 2,
+                sjw: 2
+            }
+        );
+        assert_eq!(timing.btr(), 0x011C_0002);
+        // the old fixed value, 32 MHz / 8 / 8 quanta
+        let old = BitTiming {
+            prescaler: 8,
+            seg1: 6,
+            seg2: 1,
+            sjw: 1,
+        };
+        assert_eq!(old.btr(), 0x0005_0007);
+        assert_eq!(old.bitrate(32_000_000), 500_000);
+    }
+
+    #[test]
+    fn rejects_inexact_rates() {
+        assert_eq!(solve(24_000_000, 33_333, 875), Err(NoTiming));
+        assert_eq!(solve(24_000_000, 0, 875), Err(NoTiming));
+        // 1 Mbit/s needs at least 8 quanta per bit
+        assert_eq!(solve(4_000_000, 1_000_000, 875), Err(NoTiming));
+    }
+}
@@ -0,0 +1,46 @@
+//! Print the gateway's binary frames read from stdin, one message per line.
+//!
+//! `wire-decode --start > /dev/ttyUSB0` writes the Start and GetConfig frames instead,
+//! switching the gateway's UART to binary telemetry.
+
+use gateway_wire::message::{GET_CONFIG, START};
+use gateway_wire::{encode, Decoder, Message, MAX_FRAME};
+use std::io::{self, Read, Write};
+
+fn main() -> io::Result<()> {
+    if std::env::args().any(|arg| arg == "--start") {
+        let mut stdout = io::stdout().lock();
+        let mut out = [0; MAX_FRAME];
+        for (seq, kind) in [START, GET_CONFIG].into_iter().enumerate() {
+            // empty payloads always fit
+            let len = encode(kind, seq as u8, &[], &mut out).unwrap();
+            stdout.write_all(&out[..len])?;
+        }
+        return stdout.flush();
+    }
+
+    let mut decoder = Decoder::new();
+    let mut last_seq: Option<u8> = None;
+    for byte in io::stdin().lock().bytes() {
+        let packet = match decoder.push(byte?) {
+            None => continue,
+            Some(Ok(packet)) => packet,
+            Some(Err(e)) => {
+                eprintln!("bad frame: {:?}", e);
+                continue;
+            }
+        };
+        if let Some(last) = last_seq {
+            let lost = packet.seq.wrapping_sub(last).wrapping_sub(1);
+            if lost > 0 {
+                eprintln!("{} frame(s) lost", lost);
+            }
+        }
+        last_seq = Some(packet.seq);
+        match Message::parse(&packet) {
+            Ok(message) => println!("{:3} {:?}", packet.seq, message),
+            Err(e) => eprintln!("{:3} kind {:#04x}: {:?}", packet.seq, packet.kind, e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! Consistent Overhead Byte Stuffing: removes every zero byte so `0x00` can delimit
+//! frames. Each block starts with the distance to the next zero, 0xFF for a full
+//! block of 254 bytes without one.
+
+/// Worst case encoded length of `len` bytes, without the delimiter
+pub const fn max_encoded(len: usize) -> usize {
+    len + len / 254 + 1
+}
+
+/// Encode `input` into `out`, which must hold `max_encoded(input.len())` bytes.
+/// Returns the encoded length.
+pub fn encode(input: &[u8], out: &mut [u8]) -> usize {
+    let mut code_at = 0;
+    let mut at = 1;
+    let mut code = 1_u8;
+    for (i, &byte) in input.iter().enumerate() {
+        if byte != 0 {
+            out[at] = byte;
+            at += 1;
+            code += 1;
+            if code < 0xFF {
+                continue;
+            }
+            // a full block ends without a zero, only open another if data follows
+            if i + 1 == input.len() {
+                out[code_at] = code;
+                return at;
+            }
+        }
+        out[code_at] = code;
+        code_at = at;
+        at += 1;
+        code = 1;
+    }
+    out[code_at] = code;
+    at
+}
+
+/// Decode `input` without its delimiter into `out`, `None` if it is not valid COBS
+/// or does not fit
+pub fn decode(input: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut i = 0;
+    let mut at = 0;
+    while i < input.len() {
+        let code = input[i] as usize;
+        let end = i + code;
+        if code == 0 || end > input.len() {
+            return None;
+        }
+        let block = &input[i + 1..end];
+        if block.contains(&0) {
+            return None;
+        }
+        out.get_mut(at..at + block.len())?.copy_from_slice(block);
+        at += block.len();
+        i = end;
+        if code < 0xFF && i < input.len() {
+            *out.get_mut(at)? = 0;
+            at += 1;
+        }
+    }
+    Some(at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(input: &[u8], expected: &[u8]) {
+        let mut out = [0; 300];
+        let len = encode(input, &mut out);
+        assert_eq!(&out[..len], expected);
+        let mut back = [0; 300];
+        let len = decode(expected, &mut back).unwrap();
+        assert_eq!(&back[..len], input);
+    }
+
+    #[test]
+    fn reference_vectors() {
+        round_trip(&[], &[0x01]);
+        round_trip(&[0x00], &[0x01, 0x01]);
+        round_trip(&[0x00, 0x00], &[0x01, 0x01, 0x01]);
+        round_trip(&[0x11, 0x22, 0x00, 0x33], &[0x03, 0x11, 0x22, 0x02, 0x33]);
+        round_trip(&[0x11, 0x00, 0x00, 0x00], &[0x02, 0x11, 0x01, 0x01, 0x01]);
+    }
+
+    #[test]
+    fn full_blocks() {
+        let input: std::vec::Vec<u8> = (1..=254).collect();
+        let mut expected = std::vec![0xFF];
+        expected.extend_from_slice(&input);
+        round_trip(&input, &expected);
+
+        let input: std::vec::Vec<u8> = (1..=255).collect();
+        let mut expected = std::vec![0xFF];
+        expected.extend(1..=254);
+        expected.extend_from_slice(&[0x02, 0xFF]);
+        round_trip(&input, &expected);
+        assert!(expected.len() <= max_encoded(input.len()));
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let mut out = [0; 8];
+        assert_eq!(decode(&[0x03, 0x11], &mut out), None);
+        assert_eq!(decode(&[0x02, 0x00], &mut out), None);
+        assert_eq!(decode(&[0x00], &mut out), None);
+        assert_eq!(decode(&[0x0A, 1, 2, 3, 4, 5, 6, 7, 8, 9], &mut out), None);
+    }
+}
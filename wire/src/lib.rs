@@ -0,0 +1,210 @@
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+
+//! Binary UART transport shared by the gateway firmware and host tools.
+//!
+//! Each frame is `kind, seq, payload.., crc` COBS-encoded and terminated by a zero byte,
+//! so a reader can resync on the next `0x00` after a corrupted or truncated frame.
+//! `seq` counts frames per direction and wraps, the CRC is CRC-16/CCITT-FALSE over
+//! `kind, seq, payload`, sent low byte first. See `message` for the payloads.
+
+pub mod cobs;
+pub mod message;
+
+pub use message::{Ack, Config, Message, Summary};
+
+/// Longest payload of one frame
+pub const MAX_PAYLOAD: usize = 64;
+/// Longest encoded frame, including the zero delimiter
+pub const MAX_FRAME: usize = cobs::max_encoded(MAX_PAYLOAD + 4) + 1;
+
+/// Buffer for one encoded frame
+pub type Frame = [u8; MAX_FRAME];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// Not valid COBS, e.g. the frame was cut short
+    Cobs,
+    Crc,
+    /// Frame or payload too short or too long
+    Length,
+    /// Unknown message kind
+    Kind,
+    /// Config record of a schema this build does not know
+    Version,
+}
+
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, initial 0xFFFF, no reflection
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0xFFFF_u16;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+        }
+    }
+    crc
+}
+
+/// One decoded frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct Packet {
+    pub kind: u8,
+    pub seq: u8,
+    len: usize,
+    payload: [u8; MAX_PAYLOAD],
+}
+
+impl Packet {
+    pub fn payload(&self) -> &[u8] {
+        &self.payload[..self.len]
+    }
+}
+
+/// Encode a frame into `out`, returning its length including the delimiter
+pub fn encode(kind: u8, seq: u8, payload: &[u8], out: &mut Frame) -> Result<usize, Error> {
+    if payload.len() > MAX_PAYLOAD {
+        return Err(Error::Length);
+    }
+    let mut raw = [0; MAX_PAYLOAD + 4];
+    let len = payload.len() + 2;
+    raw[..2].copy_from_slice(&[kind, seq]);
+    raw[2..len].copy_from_slice(payload);
+    let crc = crc16(&raw[..len]);
+    raw[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+    let encoded = cobs::encode(&raw[..len + 2], out);
+    out[encoded] = 0;
+    Ok(encoded + 1)
+}
+
+/// Decode one frame without its delimiter
+pub fn decode(encoded: &[u8]) -> Result<Packet, Error> {
+    let mut raw = [0; MAX_PAYLOAD + 4];
+    let len = cobs::decode(encoded, &mut raw).ok_or(Error::Cobs)?;
+    if len < 4 {
+        return Err(Error::Length);
+    }
+    let (body, crc) = raw[..len].split_at(len - 2);
+    if crc16(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+        return Err(Error::Crc);
+    }
+    let mut payload = [0; MAX_PAYLOAD];
+    payload[..len - 4].copy_from_slice(&body[2..]);
+    Ok(Packet {
+        kind: body[0],
+        seq: body[1],
+        len: len - 4,
+        payload,
+    })
+}
+
+/// Splits a byte stream on the zero delimiters
+pub struct Decoder {
+    buf: Frame,
+    len: usize,
+    overflow: bool,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    /// Feed one byte, a result comes back at each delimiter. Empty frames are skipped.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Packet, Error>> {
+        if byte != 0 {
+            match self.buf.get_mut(self.len) {
+                Some(slot) => {
+                    *slot = byte;
+                    self.len += 1;
+                }
+                None => self.overflow = true,
+            }
+            return None;
+        }
+        let result = match (self.len, self.overflow) {
+            (0, false) => None,
+            (_, true) => Some(Err(Error::Length)),
+            (len, false) => Some(decode(&self.buf[..len])),
+        };
+        self.len = 0;
+        self.overflow = false;
+        result
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        let mut out = [0; MAX_FRAME];
+        let len = encode(0x01, 7, &[0, 1, 0, 2], &mut out).unwrap();
+        assert_eq!(out[len - 1], 0);
+        assert!(!out[..len - 1].contains(&0));
+
+        let packet = decode(&out[..len - 1]).unwrap();
+        assert_eq!((packet.kind, packet.seq), (0x01, 7));
+        assert_eq!(packet.payload(), [0, 1, 0, 2]);
+
+        let len = encode(0x02, 0, &[0xAA; MAX_PAYLOAD], &mut out).unwrap();
+        assert_eq!(len, MAX_FRAME);
+        assert_eq!(
+            decode(&out[..len - 1]).unwrap().payload(),
+            [0xAA; MAX_PAYLOAD]
+        );
+        assert_eq!(
+            encode(0x02, 0, &[0; MAX_PAYLOAD + 1], &mut out),
+            Err(Error::Length)
+        );
+    }
+
+    #[test]
+    fn rejects_corrupt_frames() {
+        let mut out = [0; MAX_FRAME];
+        let len = encode(0x01, 1, &[1, 2, 3], &mut out).unwrap();
+        out[3] ^= 0x40;
+        assert_eq!(decode(&out[..len - 1]), Err(Error::Crc));
+        assert_eq!(decode(&[0x02, 0x01]), Err(Error::Length));
+        assert_eq!(decode(&[0x05, 0x01]), Err(Error::Cobs));
+    }
+
+    #[test]
+    fn stream_resyncs_after_garbage() {
+        let mut out = [0; MAX_FRAME];
+        let len = encode(0x11, 3, &[], &mut out).unwrap();
+        let mut stream = std::vec![0x42, 0x13, 0];
+        stream.extend_from_slice(&[0xFF; MAX_FRAME + 4]);
+        stream.push(0);
+        stream.extend_from_slice(&out[..len]);
+
+        let mut decoder = Decoder::new();
+        let results: std::vec::Vec<_> = stream.iter().filter_map(|b| decoder.push(*b)).collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_err());
+        assert_eq!(results[1], Err(Error::Length));
+        let packet = results[2].as_ref().unwrap();
+        assert_eq!(
+            (packet.kind, packet.seq, packet.payload()),
+            (0x11, 3, &[][..])
+        );
+    }
+}
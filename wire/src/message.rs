@@ -0,0 +1,377 @@
+//! Message kinds and their payloads, little-endian.
+//!
+//! ```text
+//! gw   -> 0x01 Summary     live readings, scaled integers
+//! gw   -> 0x02 Config      <version> <config record>
+//! gw   -> 0x03 Ack         <request seq> <status>
+//! host -> 0x10 Start       switch the UART to binary telemetry until reset, answered
+//!                          with Ack. Any other frame switches it as well.
+//! host -> 0x11 GetConfig   answered with Config
+//! host -> 0x12 SetConfig   <version> <config record>, answered with Ack
+//! ```
+//!
+//! The config record is the gateway's flash layout, `Config` decodes `CONFIG_VERSION`.
+
+use crate::{encode, Error, Frame, Packet, MAX_PAYLOAD};
+
+pub const SUMMARY: u8 = 0x01;
+pub const CONFIG: u8 = 0x02;
+pub const ACK: u8 = 0x03;
+pub const START: u8 = 0x10;
+pub const GET_CONFIG: u8 = 0x11;
+pub const SET_CONFIG: u8 = 0x12;
+
+/// Config record schema understood by `Config`
+pub const CONFIG_VERSION: u8 = 6;
+
+/// Live readings, as the Modbus input registers
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Summary {
+    /// 0.1 %
+    pub soc: u16,
+    /// 0.1 V
+    pub pack_volts: u16,
+    /// 0.1 A, negative while discharging
+    pub current: i16,
+    pub cell_mv_high: u16,
+    pub cell_mv_low: u16,
+    /// 0.1 °C
+    pub cell_temp_high: i16,
+    pub cell_temp_low: i16,
+    pub temp_avg: i16,
+    /// 0.01 kWh
+    pub kwh_remaining: u16,
+    /// 0.1 A
+    pub charge_max: u16,
+    pub discharge_max: u16,
+    pub balancing_cells: u8,
+    pub valid: bool,
+    /// Declaration index of the gateway's `State`, `ContactorState` and alarm `Level`
+    pub state: u8,
+    pub contactor: u8,
+    pub alarm: u8,
+    pub tripped: bool,
+    pub forced_open: bool,
+}
+
+impl Summary {
+    pub const LEN: usize = 29;
+
+    pub fn encode(&self, out: &mut [u8; Self::LEN]) {
+        let mut writer = Writer(&mut out[..]);
+        for word in [self.soc, self.pack_volts] {
+            writer.put(&word.to_le_bytes());
+        }
+        writer.put(&self.current.to_le_bytes());
+        for word in [self.cell_mv_high, self.cell_mv_low] {
+            writer.put(&word.to_le_bytes());
+        }
+        for temp in [self.cell_temp_high, self.cell_temp_low, self.temp_avg] {
+            writer.put(&temp.to_le_bytes());
+        }
+        for word in [self.kwh_remaining, self.charge_max, self.discharge_max] {
+            writer.put(&word.to_le_bytes());
+        }
+        writer.put(&[
+            self.balancing_cells,
+            self.valid as u8,
+            self.state,
+            self.contactor,
+            self.alarm,
+            self.tripped as u8,
+            self.forced_open as u8,
+        ]);
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != Self::LEN {
+            return Err(Error::Length);
+        }
+        let mut reader = Reader(bytes);
+        Ok(Self {
+            soc: reader.u16(),
+            pack_volts: reader.u16(),
+            current: reader.i16(),
+            cell_mv_high: reader.u16(),
+            cell_mv_low: reader.u16(),
+            cell_temp_high: reader.i16(),
+            cell_temp_low: reader.i16(),
+            temp_avg: reader.i16(),
+            kwh_remaining: reader.u16(),
+            charge_max: reader.u16(),
+            discharge_max: reader.u16(),
+            balancing_cells: reader.u8(),
+            valid: reader.u8() != 0,
+            state: reader.u8(),
+            contactor: reader.u8(),
+            alarm: reader.u8(),
+            tripped: reader.u8() != 0,
+            forced_open: reader.u8() != 0,
+        })
+    }
+}
+
+/// Gateway config, fields in record order. Enums are their declaration index.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Config {
+    pub pack_volts: [u16; 2],
+    pub cell_millivolts: [u16; 2],
+    pub pack_temperature: [i16; 2],
+    pub cell_temperature: [i16; 2],
+    pub current_amps: [i16; 2],
+    pub dod: [u8; 2],
+    pub timeout_secs: u8,
+    pub mqtt_rate_secs: u32,
+    pub battery_type: u8,
+    pub inverter_type: u8,
+    pub candump: bool,
+    pub cells_rate_secs: u32,
+    pub stats_rate_secs: u32,
+    pub slcan_bus: u8,
+    pub bus_off_backoff_ms: [u16; 2],
+    pub bms_bitrate_kbps: u16,
+    pub inverter_bitrate_kbps: u16,
+}
+
+impl Config {
+    /// Payload length including the version byte
+    pub const LEN: usize = 48;
+
+    pub fn encode(&self, out: &mut [u8; Self::LEN]) {
+        let mut writer = Writer(&mut out[..]);
+        writer.put(&[CONFIG_VERSION]);
+        for limit in [self.pack_volts, self.cell_millivolts] {
+            writer.put(&limit[0].to_le_bytes());
+            writer.put(&limit[1].to_le_bytes());
+        }
+        for limit in [
+            self.pack_temperature,
+            self.cell_temperature,
+            self.current_amps,
+        ] {
+            writer.put(&limit[0].to_le_bytes());
+            writer.put(&limit[1].to_le_bytes());
+        }
+        writer.put(&[self.dod[0], self.dod[1], self.timeout_secs]);
+        writer.put(&self.mqtt_rate_secs.to_le_bytes());
+        writer.put(&[self.battery_type, self.inverter_type, self.candump as u8]);
+        writer.put(&self.cells_rate_secs.to_le_bytes());
+        writer.put(&self.stats_rate_secs.to_le_bytes());
+        writer.put(&[self.slcan_bus]);
+        writer.put(&self.bus_off_backoff_ms[0].to_le_bytes());
+        writer.put(&self.bus_off_backoff_ms[1].to_le_bytes());
+        writer.put(&self.bms_bitrate_kbps.to_le_bytes());
+        writer.put(&self.inverter_bitrate_kbps.to_le_bytes());
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        match bytes.first() {
+            Some(&CONFIG_VERSION) => (),
+            Some(_) => return Err(Error::Version),
+            None => return Err(Error::Length),
+        }
+        if bytes.len() != Self::LEN {
+            return Err(Error::Length);
+        }
+        let mut reader = Reader(&bytes[1..]);
+        Ok(Self {
+            pack_volts: [reader.u16(), reader.u16()],
+            cell_millivolts: [reader.u16(), reader.u16()],
+            pack_temperature: [reader.i16(), reader.i16()],
+            cell_temperature: [reader.i16(), reader.i16()],
+            current_amps: [reader.i16(), reader.i16()],
+            dod: [reader.u8(), reader.u8()],
+            timeout_secs: reader.u8(),
+            mqtt_rate_secs: reader.u32(),
+            battery_type: reader.u8(),
+            inverter_type: reader.u8(),
+            candump: reader.u8() != 0,
+            cells_rate_secs: reader.u32(),
+            stats_rate_secs: reader.u32(),
+            slcan_bus: reader.u8(),
+            bus_off_backoff_ms: [reader.u16(), reader.u16()],
+            bms_bitrate_kbps: reader.u16(),
+            inverter_bitrate_kbps: reader.u16(),
+        })
+    }
+}
+
+/// Result of a host request
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ack {
+    Ok = 0,
+    /// Config failed validation or has an unknown version, nothing changed
+    Rejected = 1,
+    /// Unknown message kind
+    Unsupported = 2,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Summary(Summary),
+    Config(Config),
+    Ack { request: u8, status: Ack },
+    Start,
+    GetConfig,
+    SetConfig(Config),
+}
+
+impl Message {
+    pub fn kind(&self) -> u8 {
+        match self {
+            Message::Summary(_) => SUMMARY,
+            Message::Config(_) => CONFIG,
+            Message::Ack { .. } => ACK,
+            Message::Start => START,
+            Message::GetConfig => GET_CONFIG,
+            Message::SetConfig(_) => SET_CONFIG,
+        }
+    }
+
+    pub fn parse(packet: &Packet) -> Result<Self, Error> {
+        let payload = packet.payload();
+        Ok(match packet.kind {
+            SUMMARY => Message::Summary(Summary::decode(payload)?),
+            CONFIG => Message::Config(Config::decode(payload)?),
+            SET_CONFIG => Message::SetConfig(Config::decode(payload)?),
+            ACK => match payload {
+                [request, status] => Message::Ack {
+                    request: *request,
+                    status: match status {
+                        0 => Ack::Ok,
+                        1 => Ack::Rejected,
+                        2 => Ack::Unsupported,
+                        _ => return Err(Error::Length),
+                    },
+                },
+                _ => return Err(Error::Length),
+            },
+            START | GET_CONFIG if !payload.is_empty() => return Err(Error::Length),
+            START => Message::Start,
+            GET_CONFIG => Message::GetConfig,
+            _ => return Err(Error::Kind),
+        })
+    }
+
+    /// Encode as frame number `seq`, returning the frame length
+    pub fn encode(&self, seq: u8, out: &mut Frame) -> usize {
+        let mut payload = [0; MAX_PAYLOAD];
+        let len = match self {
+            Message::Summary(summary) => {
+                let mut bytes = [0; Summary::LEN];
+                summary.encode(&mut bytes);
+                payload[..Summary::LEN].copy_from_slice(&bytes);
+                Summary::LEN
+            }
+            Message::Config(config) | Message::SetConfig(config) => {
+                let mut bytes = [0; Config::LEN];
+                config.encode(&mut bytes);
+                payload[..Config::LEN].copy_from_slice(&bytes);
+                Config::LEN
+            }
+            Message::Ack { request, status } => {
+                payload[..2].copy_from_slice(&[*request, *status as u8]);
+                2
+            }
+            Message::Start | Message::GetConfig => 0,
+        };
+        // every payload above fits MAX_PAYLOAD
+        encode(self.kind(), seq, &payload[..len], out).unwrap_or(0)
+    }
+}
+
+struct Writer<'a>(&'a mut [u8]);
+
+impl Writer<'_> {
+    fn put(&mut self, bytes: &[u8]) {
+        let (head, rest) = core::mem::take(&mut self.0).split_at_mut(bytes.len());
+        head.copy_from_slice(bytes);
+        self.0 = rest;
+    }
+}
+
+/// Reads from a slice whose length was checked up front
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> [u8; N] {
+        let (head, rest) = self.0.split_at(N);
+        self.0 = rest;
+        head.try_into().unwrap()
+    }
+    fn u8(&mut self) -> u8 {
+        self.take::<1>()[0]
+    }
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.take())
+    }
+    fn i16(&mut self) -> i16 {
+        i16::from_le_bytes(self.take())
+    }
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode, MAX_FRAME};
+
+    fn round_trip(message: Message) {
+        let mut out = [0; MAX_FRAME];
+        let len = message.encode(9, &mut out);
+        let packet = decode(&out[..len - 1]).unwrap();
+        assert_eq!(packet.seq, 9);
+        assert_eq!(Message::parse(&packet).unwrap(), message);
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        round_trip(Message::Summary(Summary {
+            soc: 554,
+            current: -123,
+            cell_temp_low: -40,
+            valid: true,
+            forced_open: true,
+            ..Default::default()
+        }));
+        round_trip(Message::SetConfig(Config {
+            pack_volts: [300, 400],
+            pack_temperature: [-20, 50],
+            stats_rate_secs: 3600,
+            slcan_bus: 1,
+            inverter_bitrate_kbps: 250,
+            ..Default::default()
+        }));
+        round_trip(Message::Ack {
+            request: 3,
+            status: Ack::Rejected,
+        });
+        round_trip(Message::GetConfig);
+    }
+
+    #[test]
+    fn summary_layout() {
+        let summary = Summary {
+            soc: 0x0102,
+            current: -2,
+            forced_open: true,
+            ..Default::default()
+        };
+        let mut bytes = [0; Summary::LEN];
+        summary.encode(&mut bytes);
+        assert_eq!(bytes[..6], [0x02, 0x01, 0, 0, 0xFE, 0xFF]);
+        assert_eq!(bytes[Summary::LEN - 1], 1);
+    }
+
+    #[test]
+    fn rejects_unknown_config_versions() {
+        let mut bytes = [0; Config::LEN];
+        Config::default().encode(&mut bytes);
+        assert_eq!(bytes[0], CONFIG_VERSION);
+        bytes[0] = 4;
+        assert_eq!(Config::decode(&bytes), Err(Error::Version));
+        assert_eq!(Config::decode(&bytes[..1]), Err(Error::Version));
+    }
+}